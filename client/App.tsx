@@ -1,9 +1,10 @@
 import { useMemo, useState } from "react";
-import { State } from "./api";
+import { emptyTrash, State } from "./api";
 import { GridView } from "./components/GridView";
 import { ListView } from "./components/ListView";
 import { FeedView } from "./components/FeedView";
 import { Navbar } from "./components/Navbar";
+import { useSetState } from "./contexts/state";
 import { Filter, Sort, ViewMode } from "./types";
 
 export type AppProps = {
@@ -15,13 +16,19 @@ export function App({ state }: AppProps) {
   const [filter, setFilter] = useState<Filter>({ mode: "none" });
   const [sort, setSort] = useState<Sort>({ by: "mtime", desc: true });
 
+  const setState = useSetState();
+
   const videos = useMemo(() => {
+    const inTrash = state.videos.filter((video) => video.trashed);
+    const notInTrash = state.videos.filter((video) => !video.trashed);
     const videos =
-      filter.mode === "with-tag"
-        ? state.videos.filter((video) => video.tags.includes(filter.tag))
+      filter.mode === "trash"
+        ? inTrash
+        : filter.mode === "with-tag"
+        ? notInTrash.filter((video) => video.tags.includes(filter.tag))
         : filter.mode === "tagless"
-        ? state.videos.filter((video) => video.tags.length === 0)
-        : state.videos;
+        ? notInTrash.filter((video) => video.tags.length === 0)
+        : notInTrash;
     return videos.sort(
       (a, b) =>
         (sort.by === "mtime"
@@ -33,7 +40,13 @@ export function App({ state }: AppProps) {
 
   const tags = useMemo(
     () =>
-      Array.from(new Set(state.videos.flatMap((video) => video.tags))).sort(),
+      Array.from(
+        new Set(
+          state.videos
+            .filter((video) => !video.trashed)
+            .flatMap((video) => video.tags)
+        )
+      ).sort(),
     [state]
   );
 
@@ -48,10 +61,28 @@ export function App({ state }: AppProps) {
         onSort={setSort}
         tags={tags}
       />
+      {filter.mode === "trash" ? (
+        <div className="navbar">
+          <span>
+            {videos.length} video{videos.length === 1 ? "" : "s"} in the trash
+          </span>
+          <button
+            onClick={() => {
+              if (confirm("delete everything in the trash for good?")) {
+                emptyTrash().then(setState);
+              }
+            }}
+            className="deletebtn"
+            disabled={videos.length === 0}
+          >
+            Empty trash
+          </button>
+        </div>
+      ) : null}
       {viewMode.mode === "grid" ? (
         <GridView videos={videos} />
       ) : viewMode.mode === "list" ? (
-        <ListView videos={videos} />
+        <ListView videos={videos} allVideos={state.videos} />
       ) : (
         <FeedView videos={videos} />
       )}
@@ -43,7 +43,7 @@ export function Wrapper() {
   const [videoThumb, setVideoThumb] = useState("");
   const showVideo = useCallback((video: Video) => {
     setVideoOpen(true);
-    setVideoThumb(video.thumbnail_name);
+    setVideoThumb(video.id);
   }, []);
 
   if (!state) {
@@ -64,7 +64,7 @@ export function Wrapper() {
           open={videoOpen}
           onClose={() => setVideoOpen(false)}
           video={
-            state.videos.find((video) => video.thumbnail_name === videoThumb) ??
+            state.videos.find((video) => video.id === videoThumb) ??
             null
           }
         />
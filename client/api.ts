@@ -2,15 +2,30 @@ declare const ROOT: string;
 
 export type Video = {
   /** unique identifier for video */
+  id: string;
+  /** file name of the thumbnail; used to be the ID */
   thumbnail_name: string;
   /** only may be used for displaying file name */
   path: string;
   tags: string[];
   note: string;
   mtime: { secs_since_epoch: number; nanos_since_epoch: number };
-  stow_state: "Original" | { Elsewhere: string };
+  stow_state: "Original" | { Stowed: { target: string; path: string } };
+  /** set while the video is in the trash */
+  trashed: {
+    path: string;
+    at: { secs_since_epoch: number; nanos_since_epoch: number };
+  } | null;
   size: number;
   probe: Probe | null;
+  /** set on videos the app cooked */
+  cooked_from?: Provenance | null;
+};
+/** What went into a cooked video. */
+export type Provenance = {
+  job: string;
+  project: string | null;
+  sources: { id: string; thumbnail_name: string; start: number; end: number }[];
 };
 export type Probe = {
   width: number;
@@ -22,6 +37,8 @@ export type Probe = {
   color_transfer: string | null;
   color_primaries: string | null;
   bit_rate: number;
+  codec?: string | null;
+  profile?: string | null;
   audio: {
     sample_rate: number;
     bit_rate: number;
@@ -33,8 +50,11 @@ export type Rotation = "Unrotated" | "Neg90" | "Pos90" | "Neg180";
 export const isTransposed = (rot: Rotation) =>
   rot === "Neg90" || rot === "Pos90";
 export type State = {
+  schema: number;
   videos: Video[];
   version: string | null;
+  roots: string[];
+  projects: Project[];
 };
 export type VideoMetadataEditReq = {
   target: VideoSelectRequest;
@@ -44,14 +64,22 @@ export type JsonError = {
   error: string;
 };
 export type VideoSelectRequest =
-  | { Thumbnail: string }
-  | { Thumbnails: string[] }
+  | { Id: string }
+  | { Ids: string[] }
   | { Tag: string };
 
-const toJson = async <T = State>(r: Response): Promise<T> =>
-  r.ok
-    ? r.json()
-    : Promise.reject(new Error(`HTTP ${r.status} error: ${await r.text()}`));
+const toJson = async <T = State>(r: Response): Promise<T> => {
+  if (r.ok) {
+    return r.json();
+  }
+  const text = await r.text();
+  let message = `HTTP ${r.status} error: ${text}`;
+  try {
+    // error responses are usually a JsonError
+    message = JSON.parse(text).error ?? message;
+  } catch {}
+  throw new Error(message);
+};
 
 export const getList = () => fetch(new URL("/list", ROOT)).then(toJson);
 
@@ -67,42 +95,42 @@ const editMetadata = (path: string, req: VideoMetadataEditReq) =>
     );
 
 export const addTag = (
-  video: Video | { thumbnail_name: string }[],
+  video: Video | { id: string }[],
   tag: string
 ) =>
   editMetadata("/tag/add", {
     target: Array.isArray(video)
-      ? { Thumbnails: video.map((video) => video.thumbnail_name) }
-      : { Thumbnail: video.thumbnail_name },
+      ? { Ids: video.map((video) => video.id) }
+      : { Id: video.id },
     tag_or_note: tag,
   });
 
 export const removeTag = (
-  video: Video | { thumbnail_name: string }[],
+  video: Video | { id: string }[],
   tag: string
 ) =>
   editMetadata("/tag/remove", {
     target: Array.isArray(video)
-      ? { Thumbnails: video.map((video) => video.thumbnail_name) }
-      : { Thumbnail: video.thumbnail_name },
+      ? { Ids: video.map((video) => video.id) }
+      : { Id: video.id },
     tag_or_note: tag,
   });
 
 export const setNote = (video: Video, note: string) =>
   editMetadata("/editnote", {
-    target: { Thumbnail: video.thumbnail_name },
+    target: { Id: video.id },
     tag_or_note: note,
   });
 
 export const getVideoUrl = (video: Video) =>
-  new URL(`/v/${encodeURIComponent(video.thumbnail_name)}`, ROOT);
+  new URL(`/v/${encodeURIComponent(video.id)}`, ROOT);
 
 export const getThumbnailUrl = (video: Video) =>
-  new URL(`/t/${encodeURIComponent(video.thumbnail_name)}`, ROOT);
+  new URL(`/t/${encodeURIComponent(video.id)}`, ROOT);
 
 // using real video for preview now
 export const getPreviewUrl = (video: Video) =>
-  new URL(`/v/${encodeURIComponent(video.thumbnail_name)}`, ROOT);
+  new URL(`/v/${encodeURIComponent(video.id)}`, ROOT);
 
 const deleteVideos = (request: VideoSelectRequest) =>
   fetch(new URL("/videos", ROOT), {
@@ -111,10 +139,20 @@ const deleteVideos = (request: VideoSelectRequest) =>
   }).then(toJson);
 
 export const deleteVideo = (video: Video) =>
-  deleteVideos({ Thumbnail: video.thumbnail_name });
+  deleteVideos({ Id: video.id });
 
 export const deleteVideosByTag = (tag: string) => deleteVideos({ Tag: tag });
 
+export const restoreFromTrash = (video: Video) =>
+  fetch(new URL("/trash/restore", ROOT), {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify({ Id: video.id }),
+  }).then(toJson);
+
+export const emptyTrash = () =>
+  fetch(new URL("/trash", ROOT), { method: "DELETE" }).then(toJson);
+
 export const createPreviewList = (tag: string) =>
   fetch(new URL("/preview", ROOT), {
     method: "POST",
@@ -122,40 +160,256 @@ export const createPreviewList = (tag: string) =>
     body: JSON.stringify({ tag }),
   }).then(toJson);
 
+export type Transition = {
+  kind: "Crossfade" | "FadeBlack" | "Wipe";
+  /** in seconds */
+  duration: number;
+};
+
+/** What to do with clips whose aspect ratio doesn't match the video's. */
+export type Fit =
+  | { Blur: { downscale?: number; blur?: number } }
+  | { Letterbox: { color?: string } }
+  | { Crop: { x?: number; y?: number } };
+
+/** Text drawn over a clip or title card. Times are within the clip. */
+export type TextOverlay = {
+  text: string;
+  start?: number;
+  /** until the end of the clip if missing */
+  end?: number | null;
+  position?: "Top" | "Center" | "Bottom";
+  /** fraction of the video's height */
+  size?: number;
+  color?: string;
+};
+
+export type TitleCard = {
+  /** in seconds */
+  duration: number;
+  /** a colour, or a blurred frame from a video */
+  background?: { Color: string } | { Frame: { id: string; time: number } };
+  texts: TextOverlay[];
+  transition?: Transition | null;
+};
+
 export type CookClip = {
   start: number;
   end: number;
-  thumbnail_name: string;
+  id: string;
   override_rotation: Rotation | null;
+  transition: Transition | null;
+  fit: Fit | null;
+  texts: TextOverlay[];
+  /** 1 is as is */
+  volume?: number;
+  mute?: boolean;
+  /** in seconds */
+  fade_in?: number;
+  fade_out?: number;
+  /** 0.25 to 4 */
+  speed?: number;
+  reverse?: boolean;
 };
 
-export type Size = { width: number; height: number };
+/** A background track for the whole video. */
+export type Music = {
+  /** a `File` path is relative to the server's `music_dir` */
+  source: { Video: { id: string } } | { File: { path: string } };
+  /** seconds into the track */
+  start?: number;
+  /** 0.3 if missing */
+  volume?: number;
+  loop?: boolean;
+  /** seconds at the end of the video */
+  fade_out?: number;
+};
 
-const decoder = new TextDecoder();
-export const cook = async function* (
-  clips: CookClip[],
-  encoding: Probe,
-  name: string
-): AsyncGenerator<string> {
-  const response = await fetch(new URL("/cook", ROOT), {
+export type CookItem = CookClip | { card: TitleCard };
+
+/** EBU R128 targets for evening out how loud the clips are. */
+export type Loudness = {
+  /** LUFS, -16 if missing */
+  target?: number;
+  /** dBTP */
+  true_peak?: number;
+  /** LU */
+  range?: number;
+};
+
+/** How cooked videos get encoded. Missing fields keep the defaults. */
+export type EncodingPreset = {
+  width?: number | null;
+  height?: number | null;
+  fps?: number | null;
+  video_codec?: string;
+  crf?: number | null;
+  preset?: string | null;
+  audio_codec?: string;
+  audio_bitrate?: string | null;
+};
+
+export const getPresets = () =>
+  fetch(new URL("/presets", ROOT)).then((r) =>
+    toJson<Record<string, EncodingPreset>>(r)
+  );
+
+/** Cook settings that don't come from the clips. */
+export type CookOptions = {
+  fit: Fit;
+  music: Music | null;
+  loudness: Loudness | null;
+  /** a preset's name, or its settings */
+  preset: string | EncodingPreset | null;
+};
+
+/** The parts of a saved project that can be edited. */
+export type ProjectData = {
+  name: string;
+  clips: CookItem[];
+  /** picked when cooking */
+  encoding: Probe | null;
+  /** file name in Downloads, without the .mp4 */
+  output_name: string;
+} & Partial<CookOptions>;
+
+/** An edit saved on the server. */
+export type Project = ProjectData & {
+  id: string;
+  created: { secs_since_epoch: number; nanos_since_epoch: number };
+  updated: { secs_since_epoch: number; nanos_since_epoch: number };
+};
+
+export const getProjects = () =>
+  fetch(new URL("/projects", ROOT)).then((r) => toJson<Project[]>(r));
+
+export const createProject = (data: ProjectData) =>
+  fetch(new URL("/projects", ROOT), {
     method: "POST",
     headers: { "content-type": "application/json" },
-    body: JSON.stringify({ clips, encoding, name }),
-  });
+    body: JSON.stringify(data),
+  }).then((r) => toJson<Project>(r));
+
+export const updateProject = (id: string, data: ProjectData) =>
+  fetch(new URL(`/projects/${encodeURIComponent(id)}`, ROOT), {
+    method: "PUT",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify(data),
+  }).then((r) => toJson<Project>(r));
+
+export const deleteProject = (id: string) =>
+  fetch(new URL(`/projects/${encodeURIComponent(id)}`, ROOT), {
+    method: "DELETE",
+  }).then((r) => toJson<Project[]>(r));
+
+export type Size = { width: number; height: number };
+
+export type Job = {
+  id: string;
+  name: string;
+  status: "Running" | "Succeeded" | "Failed" | "Cancelled";
+  started: { secs_since_epoch: number; nanos_since_epoch: number };
+  finished: { secs_since_epoch: number; nanos_since_epoch: number } | null;
+  output: string | null;
+  /** the saved project that was cooked */
+  project: string | null;
+  error: string | null;
+};
+
+const readText = async function* (response: Response): AsyncGenerator<string> {
   if (!response.ok) {
     throw new Error(`HTTP ${response.status} error: ${await response.text()}`);
   }
-
+  const decoder = new TextDecoder();
   const reader = response.body?.getReader();
   while (reader) {
     const { done, value } = await reader.read();
     if (done) {
       break;
     }
-    yield decoder.decode(value);
+    yield decoder.decode(value, { stream: true });
+  }
+};
+
+export type CookProgress = {
+  /** 0 to 100 */
+  percent: number;
+  stage: "clips" | "transitions" | "concat" | "music";
+  clip: number | null;
+  clips: number;
+  speed: number | null;
+  /** seconds left */
+  eta: number | null;
+};
+
+export type JobEvent =
+  | { type: "log"; line: string }
+  | ({ type: "progress" } & CookProgress)
+  | ({ type: "done" } & Job);
+
+/** Parses a job log, which has a JSON event per line. */
+const readEvents = async function* (
+  response: Response
+): AsyncGenerator<JobEvent> {
+  let data = "";
+  for await (const text of readText(response)) {
+    data += text;
+    const lines = data.split("\n");
+    data = lines.pop() ?? "";
+    for (const line of lines) {
+      if (line.trim()) yield JSON.parse(line);
+    }
+  }
+};
+
+const startCook = async (
+  body: unknown
+): Promise<{ jobId: string; log: AsyncGenerator<JobEvent> }> => {
+  const response = await fetch(new URL("/cook", ROOT), {
+    method: "POST",
+    headers: { "content-type": "application/json" },
+    body: JSON.stringify(body),
+  });
+  if (!response.ok) {
+    // a 4xx lists everything wrong with the request
+    const { error }: JsonError = await response
+      .json()
+      .catch(() => ({ error: `HTTP ${response.status} error` }));
+    throw new Error(error);
   }
+  return {
+    jobId: response.headers.get("X-Job-Id") ?? "",
+    log: readEvents(response),
+  };
 };
 
+/** Starts a cook job. `log` follows its events until it's done. */
+export const cook = (
+  clips: CookItem[],
+  encoding: Probe,
+  name: string,
+  options: CookOptions
+) => startCook({ clips, encoding, name, ...options });
+
+/** Cooks a saved project as it is on the server. */
+export const cookProject = (id: string) => startCook({ project: id });
+
+/** Follows a job's log from the start. */
+export const attachJob = async (id: string) =>
+  readEvents(
+    await fetch(new URL(`/jobs/${encodeURIComponent(id)}/log`, ROOT))
+  );
+
+export const getJob = (id: string) =>
+  fetch(new URL(`/jobs/${encodeURIComponent(id)}`, ROOT)).then((r) =>
+    toJson<Job>(r)
+  );
+
+export const cancelJob = (id: string) =>
+  fetch(new URL(`/jobs/${encodeURIComponent(id)}`, ROOT), {
+    method: "DELETE",
+  }).then((r) => toJson<Job>(r));
+
 export const renameTag = (oldName: string, newName: string) =>
   fetch(new URL("/tag/rename", ROOT), {
     method: "POST",
@@ -163,12 +417,36 @@ export const renameTag = (oldName: string, newName: string) =>
     body: JSON.stringify({ old: oldName, new: newName }),
   }).then(toJson);
 
-export const moveForYouTube = (tag: string) =>
-  fetch(new URL("/for-youtube", ROOT), {
+const step = (path: string) =>
+  fetch(new URL(path, ROOT), { method: "POST" }).then(toJson);
+
+export const undo = () => step("/undo");
+
+export const redo = () => step("/redo");
+
+export type StowTargets = Record<
+  string,
+  {
+    /** null if the target was removed from the config */
+    dir: string | null;
+    template: string | null;
+    videos: { id: string; path: string }[];
+  }
+>;
+
+export const getStowTargets = () =>
+  fetch(new URL("/stow", ROOT)).then((r) => toJson<StowTargets>(r));
+
+export const stowVideos = (tag: string, target: string) =>
+  fetch(new URL("/stow", ROOT), {
     method: "POST",
     headers: { "content-type": "application/json" },
-    body: JSON.stringify({ Tag: tag }),
-  }).then(toJson);
+    body: JSON.stringify({ target, videos: { Tag: tag } }),
+  })
+    .then((r) => toJson<State | JsonError>(r))
+    .then((resp) =>
+      "error" in resp ? Promise.reject(new Error(resp.error)) : resp
+    );
 
 export const restoreFiles = (tag: string) =>
   fetch(new URL("/restore", ROOT), {
@@ -1,6 +1,6 @@
 import { getThumbnailUrl, Video } from "../api";
 import { Clip } from "../types";
-import { formatSeconds } from "../util";
+import { clipDuration, formatSeconds } from "../util";
 
 type ClipProps = {
   ends: [canLeft: boolean, canRight: boolean];
@@ -27,9 +27,11 @@ export function Clip({ ends, clip, video, onClick, onMove }: ClipProps) {
             : ""
         }
         data-dur={
-          clip.start === 0 && clip.end === video.probe?.duration
+          clip.start === 0 &&
+          clip.end === video.probe?.duration &&
+          clip.speed === undefined
             ? "Full"
-            : formatSeconds(clip.end - clip.start)
+            : formatSeconds(clipDuration(clip))
         }
       >
         <img
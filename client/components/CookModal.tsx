@@ -1,6 +1,16 @@
 import { ChangeEvent, useEffect, useMemo, useRef, useState } from "react";
-import { getThumbnailUrl, Probe, Video } from "../api";
+import {
+  CookOptions,
+  EncodingPreset,
+  Fit,
+  getPresets,
+  getThumbnailUrl,
+  Music,
+  Probe,
+  Video,
+} from "../api";
 import { extractFilename, map } from "../util";
+import { FitSelect } from "./FitSelect";
 
 const keysVideo = [
   "pix_fmt",
@@ -19,7 +29,7 @@ export type CookModalProps = {
   sizes: [string, Set<Video>][];
   open: boolean;
   onClose: () => void;
-  onCook: (encoding: Probe) => void;
+  onCook: (encoding: Probe, options: CookOptions) => void;
 };
 export function CookModal({
   videos,
@@ -30,11 +40,16 @@ export function CookModal({
 }: CookModalProps) {
   const dialogRef = useRef<HTMLDialogElement>(null);
   const [hasBeenOpen, setHasBeenOpen] = useState(false);
+  const [fit, setFit] = useState<Fit>({ Blur: {} });
+  const [presets, setPresets] = useState<Record<string, EncodingPreset>>({});
 
   useEffect(() => {
     if (open) {
       dialogRef.current?.showModal();
       setHasBeenOpen(true);
+      getPresets().then(setPresets, (err) =>
+        console.error("Couldn't get presets", err)
+      );
     } else {
       dialogRef.current?.close();
     }
@@ -91,7 +106,7 @@ export function CookModal({
             </label>
             {videos.map((video) => (
               <img
-                key={video.thumbnail_name}
+                key={video.id}
                 alt={extractFilename(video)}
                 src={getThumbnailUrl(video).toString()}
               />
@@ -153,25 +168,60 @@ export function CookModal({
               bit_rate: 0,
             };
           }
+          const musicId = String(data.get("music") ?? "");
+          const music: Music | null = musicId
+            ? {
+                source: { Video: { id: musicId } },
+                volume: Number(data.get("music_volume")),
+                loop: data.has("music_loop"),
+                fade_out: Number(data.get("music_fade_out")),
+              }
+            : null;
+          const loudness = data.has("normalize")
+            ? { target: Number(data.get("loudness_target")) }
+            : null;
           const [width, height] =
             data.get("size") === "other"
               ? [Number(data.get("width")), Number(data.get("height"))]
               : String(data.get("size")).split("x");
-          onCook({
-            width: +width,
-            height: +height,
-            pix_fmt: String(data.get("pix_fmt")),
-            color_primaries: map(data.get("color_primaries"), String),
-            color_space: map(data.get("color_space"), String),
-            color_transfer: map(data.get("color_transfer"), String),
-            audio,
-            // unused
-            bit_rate: 0,
-            duration: 0,
-            rotation: "Unrotated",
-          });
+          onCook(
+            {
+              width: +width,
+              height: +height,
+              pix_fmt: String(data.get("pix_fmt")),
+              color_primaries: map(data.get("color_primaries"), String),
+              color_space: map(data.get("color_space"), String),
+              color_transfer: map(data.get("color_transfer"), String),
+              audio,
+              // unused
+              bit_rate: 0,
+              duration: 0,
+              rotation: "Unrotated",
+            },
+            {
+              fit,
+              music,
+              loudness,
+              preset: map(data.get("preset"), String) || null,
+            }
+          );
         }}
       >
+        <fieldset className="choices">
+          <legend>Encoding</legend>
+          <select name="preset" defaultValue="">
+            <option value="">h264, same size and frame rate as below</option>
+            {Object.entries(presets).map(([name, preset]) => (
+              <option key={name} value={name}>
+                {name} ({preset.video_codec ?? "libx264"}
+                {preset.width && preset.height
+                  ? `, ${preset.width}×${preset.height}`
+                  : ""}
+                {preset.fps ? `, ${preset.fps} fps` : ""})
+              </option>
+            ))}
+          </select>
+        </fieldset>
         <fieldset className="choices">
           <legend>Video Resolution</legend>
           {sizes.map(([size, videos], i) => (
@@ -187,7 +237,7 @@ export function CookModal({
               </label>
               {Array.from(videos, (video) => (
                 <img
-                  key={video.thumbnail_name}
+                  key={video.id}
                   alt={extractFilename(video)}
                   src={getThumbnailUrl(video).toString()}
                 />
@@ -222,6 +272,10 @@ export function CookModal({
             </label>
           </p>
         </fieldset>
+        <fieldset className="choices">
+          <legend>Clips with a different aspect ratio</legend>
+          <FitSelect value={fit} onChange={(fit) => fit && setFit(fit)} />
+        </fieldset>
         {renderField("pix_fmt", "Pixel Format")}
         {renderField("color_primaries", "Color Primaries")}
         {renderField("color_space", "Color Space")}
@@ -236,6 +290,63 @@ export function CookModal({
         ) : (
           <p>Your clips have no audio.</p>
         )}
+        <fieldset className="choices">
+          <legend>Loudness</legend>
+          <label>
+            <input type="checkbox" name="normalize" /> Even out how loud the
+            clips are
+          </label>{" "}
+          <label className="num">
+            Target:{" "}
+            <input
+              type="number"
+              name="loudness_target"
+              min={-70}
+              max={-5}
+              step={1}
+              defaultValue={-16}
+            />{" "}
+            LUFS
+          </label>
+        </fieldset>
+        <fieldset className="choices">
+          <legend>Background music</legend>
+          <select name="music" defaultValue="">
+            <option value="">None</option>
+            {videos
+              .filter((video) => video.probe?.audio)
+              .map((video) => (
+                <option key={video.id} value={video.id}>
+                  {extractFilename(video)}
+                </option>
+              ))}
+          </select>{" "}
+          <label>
+            Volume:{" "}
+            <input
+              type="range"
+              name="music_volume"
+              min={0}
+              max={1}
+              step={0.05}
+              defaultValue={0.3}
+            />
+          </label>{" "}
+          <label>
+            <input type="checkbox" name="music_loop" defaultChecked /> Loop
+          </label>{" "}
+          <label className="num">
+            Fade out:{" "}
+            <input
+              type="number"
+              name="music_fade_out"
+              min={0}
+              step={0.5}
+              defaultValue={2}
+            />
+            s
+          </label>
+        </fieldset>
         <button type="submit">cook! 🧑‍🍳</button>
       </form>
     </dialog>
@@ -7,12 +7,20 @@ import {
   useState,
 } from "react";
 import {
-  cook,
+  attachJob,
+  cancelJob,
+  cookProject,
   createPreviewList,
+  createProject,
+  getJob,
   getPreviewUrl,
+  getProjects,
   isTransposed,
+  JobEvent,
+  ProjectData,
   Size,
   State,
+  updateProject,
   Video,
 } from "../api";
 import { getThumbnailUrl } from "../api";
@@ -20,7 +28,13 @@ import { Clip as ClipComponent } from "./Clip";
 import { Trimmer } from "./Trimmer";
 import { ProjectState, Clip } from "../types";
 import { useSetState } from "../contexts/state";
-import { formatHms, formatMmSs, rotToAngle } from "../util";
+import {
+  formatHms,
+  formatMmSs,
+  fromCookItems,
+  rotToAngle,
+  toCookItems,
+} from "../util";
 import { CookModal } from "./CookModal";
 
 type SizeStr = `${number}x${number}`;
@@ -50,6 +64,12 @@ export function Editor({ state, tag }: EditorProps) {
   const [speedUp, setSpeedUp] = useState(false);
   const pointerId = useRef<number | null>(null);
   const [showCook, setShowCook] = useState(false);
+  const [cookJob, setCookJob] = useState<string | null>(null);
+  /** the project on the server. `id` is null until it's first saved */
+  const projectRef = useRef<{ id: string | null; data: ProjectData } | null>(
+    null
+  );
+  const savingRef = useRef(Promise.resolve());
 
   useEffect(() => {
     if (loading) {
@@ -65,17 +85,67 @@ export function Editor({ state, tag }: EditorProps) {
   }, [loading]);
 
   useEffect(() => {
-    const project = localStorage.getItem(`video-sort/project/${tag}`);
-    setProjectState(project ? JSON.parse(project) : { clips: [] });
+    projectRef.current = null;
+    setProjectState({ clips: [], uninitialized: true });
+    getProjects().then((projects) => {
+      const saved = projects.find((project) => project.name === tag);
+      if (saved) {
+        projectRef.current = { id: saved.id, data: saved };
+        setProjectState({ clips: fromCookItems(saved.clips) });
+        return;
+      }
+      projectRef.current = {
+        id: null,
+        data: {
+          name: tag,
+          clips: [],
+          encoding: null,
+          output_name: `video-sort-${tag}`,
+        },
+      };
+      // edits from before projects were saved on the server
+      const project = localStorage.getItem(`video-sort/project/${tag}`);
+      const projectState: ProjectState = project
+        ? JSON.parse(project)
+        : { clips: [] };
+      // projects saved before videos had IDs refer to thumbnail names
+      for (const clip of projectState.clips) {
+        const video = state.videos.find(
+          (video) => video.thumbnail_name === clip.thumb
+        );
+        if (video) clip.thumb = video.id;
+      }
+      setProjectState(projectState);
+    }, console.error);
   }, [tag]);
 
+  /** Saves changes to the project, one save at a time. */
+  const saveProject = (changes: Partial<ProjectData>) => {
+    const project = projectRef.current;
+    if (!project) return savingRef.current;
+    project.data = { ...project.data, ...changes };
+    savingRef.current = savingRef.current
+      .then(async () => {
+        // don't make a project for every tag that gets opened
+        if (!project.id && project.data.clips.length === 0) return;
+        if (project.id) {
+          await updateProject(project.id, project.data);
+        } else {
+          project.id = (await createProject(project.data)).id;
+          localStorage.removeItem(`video-sort/project/${tag}`);
+        }
+      })
+      .catch(console.error);
+    return savingRef.current;
+  };
+
   useEffect(() => {
-    if (!projectState.uninitialized) {
-      localStorage.setItem(
-        `video-sort/project/${tag}`,
-        JSON.stringify(projectState)
-      );
-    }
+    if (projectState.uninitialized) return;
+    const timeout = setTimeout(
+      () => saveProject({ clips: toCookItems(projectState.clips) }),
+      500
+    );
+    return () => clearTimeout(timeout);
   }, [projectState]);
 
   const videos = useMemo(
@@ -102,10 +172,69 @@ export function Editor({ state, tag }: EditorProps) {
     }
   }, [needProbe, loading]);
 
+  const followCook = async (jobId: string, log: AsyncGenerator<JobEvent>) => {
+    setLoading(true);
+    setCookJob(jobId);
+    localStorage.setItem(`video-sort/cook-job/${tag}`, jobId);
+    try {
+      const errors: string[] = [];
+      for await (const event of log) {
+        if (event.type === "log") {
+          errors.push(event.line);
+        } else if (event.type === "progress") {
+          const { percent, stage, clip, clips, speed, eta } = event;
+          setCookStatus(
+            [
+              `${percent.toFixed(1)}%`,
+              stage === "concat"
+                ? "joining clips"
+                : stage === "music"
+                ? "mixing in music"
+                : `clip ${(clip ?? 0) + 1} of ${clips}`,
+              speed !== null ? `${speed.toFixed(2)}x` : null,
+              eta !== null ? `about ${Math.ceil(eta)}s left` : null,
+              ...errors,
+            ]
+              .filter((part) => part !== null)
+              .join("\n")
+          );
+        }
+      }
+      const job = await getJob(jobId);
+      if (job.status === "Succeeded") {
+        alert(
+          `Successfully saved to ${job.name}.mp4 in your Downloads folder.`
+        );
+      } else if (job.status === "Failed") {
+        alert(`Cooking failed: ${job.error}`);
+      }
+      localStorage.removeItem(`video-sort/cook-job/${tag}`);
+    } finally {
+      setLoading(false);
+      setCookJob(null);
+    }
+  };
+
+  // pick up where we left off if the page reloaded mid-cook
+  useEffect(() => {
+    const jobId = localStorage.getItem(`video-sort/cook-job/${tag}`);
+    if (!jobId) return;
+    getJob(jobId)
+      .then(async (job) => {
+        if (job.status === "Running") {
+          setCookStatus("Reconnecting...");
+          await followCook(jobId, await attachJob(jobId));
+        } else {
+          localStorage.removeItem(`video-sort/cook-job/${tag}`);
+        }
+      })
+      .catch(() => localStorage.removeItem(`video-sort/cook-job/${tag}`));
+  }, [tag]);
+
   const videoMap = useMemo(
     () =>
       Object.fromEntries(
-        state.videos.map((video) => [video.thumbnail_name, video])
+        state.videos.map((video) => [video.id, video])
       ),
     [state.videos]
   );
@@ -237,46 +366,27 @@ export function Editor({ state, tag }: EditorProps) {
         sizes={sizes}
         open={showCook}
         onClose={() => setShowCook(false)}
-        onCook={async (encoding) => {
+        onCook={async (encoding, options) => {
           setShowCook(false);
           setLoading(true);
           setCookStatus("Getting ready to cook...");
           try {
-            const statuses: Record<string, string> = {};
-            let data = "";
-            for await (const status of cook(
-              projectState.clips.map(
-                ({ start, end, thumb, overrideRotation }) => ({
-                  start,
-                  end,
-                  thumbnail_name: thumb,
-                  override_rotation: overrideRotation ?? null,
-                })
-              ),
+            // the cook settings are saved with the project so it can be
+            // cooked the same way again
+            await saveProject({
+              clips: toCookItems(projectState.clips),
               encoding,
-              `video-sort-${tag}`
-            )) {
-              data += status;
-              while (true) {
-                const lineEnd = data.match(/\r?\n|\r/);
-                if (lineEnd?.index === undefined) break;
-                const line = data.slice(0, lineEnd.index);
-                const match = line.match(/\[(\d+)\] /);
-                const [cls, stat] = match
-                  ? [match[1], line.slice(match[0].length)]
-                  : ["+", line]; // concat doesnt prefix its output
-                if (stat) statuses[cls] = stat;
-                data = data.slice(lineEnd.index + lineEnd[0].length);
-              }
-              setCookStatus(
-                Object.entries(statuses)
-                  .map(([key, status]) => `[${key}] ${status}`)
-                  .join("\n")
-              );
+              ...options,
+            });
+            const id = projectRef.current?.id;
+            if (!id) {
+              throw new Error("The project couldn't be saved.");
             }
-            alert(
-              `Successfully saved to video-sort-${tag}.mp4 in your Downloads folder.`
-            );
+            const { jobId, log } = await cookProject(id);
+            await followCook(jobId, log);
+          } catch (error) {
+            setCookStatus("");
+            alert(`Couldn't cook: ${(error as Error).message}`);
           } finally {
             setLoading(false);
           }
@@ -291,7 +401,7 @@ export function Editor({ state, tag }: EditorProps) {
         <div className="preview-placeholder">
           {videos.map((video) => {
             let rotate = 0;
-            if (viewingClip?.clip.thumb === video.thumbnail_name) {
+            if (viewingClip?.clip.thumb === video.id) {
               const origRot = video.probe?.rotation ?? "Unrotated";
               const clipRot = viewingClip.clip.overrideRotation ?? origRot;
               rotate = rotToAngle[origRot] - rotToAngle[clipRot];
@@ -301,13 +411,13 @@ export function Editor({ state, tag }: EditorProps) {
                 preload="none"
                 src={getPreviewUrl(video).toString()}
                 poster={getThumbnailUrl(video).toString()}
-                key={video.thumbnail_name}
+                key={video.id}
                 ref={(elem) => {
-                  if (elem) videoRefs.current[video.thumbnail_name] = elem;
+                  if (elem) videoRefs.current[video.id] = elem;
                 }}
                 style={{
                   visibility:
-                    viewingClip?.clip.thumb === video.thumbnail_name
+                    viewingClip?.clip.thumb === video.id
                       ? "visible"
                       : "hidden",
                   ...(rotate !== 0
@@ -410,7 +520,7 @@ export function Editor({ state, tag }: EditorProps) {
       <div className="palette">
         {videos.map((video) => (
           <button
-            key={video.thumbnail_name}
+            key={video.id}
             className="palette-item"
             onClick={() => {
               if (video.probe) {
@@ -421,7 +531,7 @@ export function Editor({ state, tag }: EditorProps) {
                     ...p.clips,
                     {
                       id: crypto.randomUUID(),
-                      thumb: video.thumbnail_name,
+                      thumb: video.id,
                       start: 0,
                       end: duration,
                     },
@@ -433,7 +543,7 @@ export function Editor({ state, tag }: EditorProps) {
           >
             <img src={getThumbnailUrl(video).toString()} />
             {projectState.clips.some(
-              (c) => c.thumb === video.thumbnail_name
+              (c) => c.thumb === video.id
             ) && <div className="used-indicator">✅</div>}
             {!video.probe && <div className="unavail-indicator">⛔</div>}
           </button>
@@ -442,6 +552,9 @@ export function Editor({ state, tag }: EditorProps) {
       <div className={`cook-status ${loading ? "cook-status-visible" : ""}`}>
         <div className="spinner" />
         <pre>{cookStatus}</pre>
+        {cookJob ? (
+          <button onClick={() => cancelJob(cookJob)}>Cancel</button>
+        ) : null}
       </div>
     </div>
   );
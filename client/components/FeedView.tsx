@@ -9,7 +9,7 @@ export function FeedView({ videos }: FeedViewProps) {
   return (
     <div className="feed-view">
       {videos.map((video) => (
-        <FeedItem key={video.thumbnail_name} video={video} />
+        <FeedItem key={video.id} video={video} />
       ))}
     </div>
   );
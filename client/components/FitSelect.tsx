@@ -0,0 +1,48 @@
+import { Fit } from "../api";
+
+const presets: Record<string, { label: string; fit: Fit }> = {
+  blur: { label: "Blurred background", fit: { Blur: {} } },
+  black: { label: "Black bars", fit: { Letterbox: { color: "black" } } },
+  white: { label: "White bars", fit: { Letterbox: { color: "white" } } },
+  crop: { label: "Crop to fill", fit: { Crop: { x: 0.5, y: 0.5 } } },
+  "crop-start": {
+    label: "Crop to fill, keep left/top",
+    fit: { Crop: { x: 0, y: 0 } },
+  },
+  "crop-end": {
+    label: "Crop to fill, keep right/bottom",
+    fit: { Crop: { x: 1, y: 1 } },
+  },
+};
+
+const presetFor = (fit: Fit | undefined) =>
+  Object.entries(presets).find(
+    ([, preset]) => JSON.stringify(preset.fit) === JSON.stringify(fit)
+  )?.[0];
+
+export type FitSelectProps = {
+  value: Fit | undefined;
+  onChange: (fit: Fit | undefined) => void;
+  /** label for not picking one, if that's allowed */
+  defaultLabel?: string;
+};
+
+/** How clips with a different aspect ratio fill the frame. */
+export function FitSelect({ value, onChange, defaultLabel }: FitSelectProps) {
+  return (
+    <select
+      value={presetFor(value) ?? ""}
+      onChange={(e) => {
+        const preset = presets[e.currentTarget.value];
+        onChange(preset?.fit);
+      }}
+    >
+      {defaultLabel !== undefined && <option value="">{defaultLabel}</option>}
+      {Object.entries(presets).map(([key, { label }]) => (
+        <option key={key} value={key}>
+          {label}
+        </option>
+      ))}
+    </select>
+  );
+}
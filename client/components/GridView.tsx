@@ -33,9 +33,7 @@ export function GridView({ videos }: GridViewProps) {
                 return;
               }
               const tag = String(new FormData(e.currentTarget).get("tag"));
-              const videos = Array.from(selected, (thumbnail_name) => ({
-                thumbnail_name,
-              }));
+              const videos = Array.from(selected, (id) => ({ id }));
               if (submitter.value === "+") {
                 addTag(videos, tag).then(setState);
               } else if (submitter.value === "−") {
@@ -73,20 +71,20 @@ export function GridView({ videos }: GridViewProps) {
         {videos.map((video) => {
           return (
             <button
-              key={video.thumbnail_name}
+              key={video.id}
               className={`thumbnail ${
-                selected.has(video.thumbnail_name) ? "selected" : ""
+                selected.has(video.id) ? "selected" : ""
               }`}
               onClick={() =>
                 selectMode
                   ? setSelected((selected) =>
-                      selected.has(video.thumbnail_name)
+                      selected.has(video.id)
                         ? new Set(
                             [...selected].filter(
-                              (th) => th !== video.thumbnail_name
+                              (id) => id !== video.id
                             )
                           )
-                        : new Set([...selected, video.thumbnail_name])
+                        : new Set([...selected, video.id])
                     )
                   : showVideo(video)
               }
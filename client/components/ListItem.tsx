@@ -1,5 +1,11 @@
 import { FormEvent, useState } from "react";
-import { deleteVideo, getThumbnailUrl, setNote, Video } from "../api";
+import {
+  deleteVideo,
+  getThumbnailUrl,
+  restoreFromTrash,
+  setNote,
+  Video,
+} from "../api";
 import { useSetState } from "../contexts/state";
 import { useVideoContext } from "../contexts/video";
 import { extractFilename, formatSize } from "../util";
@@ -12,9 +18,11 @@ const fmt = new Intl.DateTimeFormat([], {
 
 type ListItemProps = {
   video: Video;
+  /** cooked videos with clips from this one */
+  usedIn: Video[];
 };
 
-export function ListItem({ video }: ListItemProps) {
+export function ListItem({ video, usedIn }: ListItemProps) {
   const showVideo = useVideoContext();
 
   const setState = useSetState();
@@ -46,16 +54,22 @@ export function ListItem({ video }: ListItemProps) {
         <div className="list-item-filename">
           <span>
             {extractFilename(video).replace(".mp4", "") + " "}
-            <button
-              onClick={() => {
-                if (confirm(`delete ${extractFilename(video)} fr?`)) {
-                  deleteVideo(video).then(setState);
-                }
-              }}
-              className="deletebtn"
-            >
-              Delete
-            </button>
+            {video.trashed ? (
+              <button onClick={() => restoreFromTrash(video).then(setState)}>
+                Restore
+              </button>
+            ) : (
+              <button
+                onClick={() => {
+                  if (confirm(`delete ${extractFilename(video)} fr?`)) {
+                    deleteVideo(video).then(setState);
+                  }
+                }}
+                className="deletebtn"
+              >
+                Delete
+              </button>
+            )}
           </span>
           <button onClick={handleCopyFilename}>📋</button>
         </div>
@@ -63,6 +77,17 @@ export function ListItem({ video }: ListItemProps) {
           {fmt.format(video.mtime.secs_since_epoch * 1000)} &middot;{" "}
           {formatSize(video.size)}
         </div>
+        {video.cooked_from ? (
+          <div className="time">
+            Cooked from {video.cooked_from.sources.length} clip
+            {video.cooked_from.sources.length === 1 ? "" : "s"}
+          </div>
+        ) : null}
+        {usedIn.length > 0 ? (
+          <div className="time">
+            Used in {usedIn.map(extractFilename).join(", ")}
+          </div>
+        ) : null}
         <TagEdit video={video} hideSize />
         {/* <div className="list-item-note">
           {isEditing ? (
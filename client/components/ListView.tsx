@@ -1,15 +1,34 @@
+import { useMemo } from "react";
 import { Video } from "../api";
 import { ListItem } from "./ListItem";
 
 type ListViewProps = {
   videos: Video[];
+  /** every video, to find the cooked videos each one was used in */
+  allVideos: Video[];
 };
 
-export function ListView({ videos }: ListViewProps) {
+export function ListView({ videos, allVideos }: ListViewProps) {
+  const usedIn = useMemo(() => {
+    const usedIn: Record<string, Video[]> = {};
+    for (const video of allVideos) {
+      const ids = new Set(video.cooked_from?.sources.map(({ id }) => id));
+      for (const id of ids) {
+        usedIn[id] ??= [];
+        usedIn[id].push(video);
+      }
+    }
+    return usedIn;
+  }, [allVideos]);
+
   return (
     <div className="list-view">
       {videos.map((video) => (
-        <ListItem key={video.thumbnail_name} video={video} />
+        <ListItem
+          key={video.id}
+          video={video}
+          usedIn={usedIn[video.id] ?? []}
+        />
       ))}
     </div>
   );
@@ -1,3 +1,5 @@
+import { redo, undo } from "../api";
+import { useSetState } from "../contexts/state";
 import { Filter, Sort, ViewMode } from "../types";
 
 type NavbarProps = {
@@ -19,8 +21,28 @@ export function Navbar({
   onSort,
   tags,
 }: NavbarProps) {
+  const setState = useSetState();
+
   return (
     <div className="navbar">
+      <button
+        onClick={() =>
+          undo()
+            .then(setState)
+            .catch((error) => alert(error.message))
+        }
+      >
+        Undo
+      </button>
+      <button
+        onClick={() =>
+          redo()
+            .then(setState)
+            .catch((error) => alert(error.message))
+        }
+      >
+        Redo
+      </button>
       <button
         onClick={() => onViewMode({ mode: "list" })}
         disabled={viewMode.mode === "list"}
@@ -61,7 +83,8 @@ export function Navbar({
         onChange={(e) =>
           onFilter(
             e.currentTarget.value === "none" ||
-              e.currentTarget.value === "tagless"
+              e.currentTarget.value === "tagless" ||
+              e.currentTarget.value === "trash"
               ? { mode: e.currentTarget.value }
               : {
                   mode: "with-tag",
@@ -72,6 +95,7 @@ export function Navbar({
       >
         <option value="none">Default</option>
         <option value="tagless">No tags</option>
+        <option value="trash">Trash</option>
         <hr />
         {tags.map((tag) => (
           <option value={`with-tag:${tag}`} key={tag}>
@@ -1,10 +1,11 @@
-import { useMemo } from "react";
+import { useEffect, useMemo, useState } from "react";
 import {
   deleteVideosByTag,
-  moveForYouTube,
+  getStowTargets,
   renameTag,
   restoreFiles,
   State,
+  stowVideos,
   Video,
 } from "../api";
 import { useSetState } from "../contexts/state";
@@ -15,10 +16,24 @@ export type TagSelectProps = {
 };
 export function TagSelect({ state }: TagSelectProps) {
   const setState = useSetState();
+  const [stowTargets, setStowTargets] = useState<string[]>([]);
+
+  useEffect(() => {
+    getStowTargets().then((targets) =>
+      setStowTargets(
+        Object.entries(targets)
+          .filter(([, target]) => target.dir !== null)
+          .map(([name]) => name)
+      )
+    );
+  }, []);
 
   const tags = useMemo(() => {
     const tags: Record<string, Video[]> = {};
     for (const video of state.videos) {
+      if (video.trashed) {
+        continue;
+      }
       for (const tag of video.tags) {
         tags[tag] ??= [];
         tags[tag].push(video);
@@ -90,19 +105,29 @@ export function TagSelect({ state }: TagSelectProps) {
                 Restore
               </button>
             ) : (
-              <button
-                onClick={() => {
+              <select
+                value=""
+                onChange={(e) => {
+                  const target = e.currentTarget.value;
                   if (
+                    target &&
                     confirm(
-                      `are you sure you want to move ${tag}'s videos to the Downloads folder?`
+                      `are you sure you want to move ${tag}'s videos to ${target}?`
                     )
                   ) {
-                    moveForYouTube(tag).then(setState);
+                    stowVideos(tag, target)
+                      .then(setState)
+                      .catch((error) => alert(error.message));
                   }
                 }}
               >
-                Move
-              </button>
+                <option value="">Move to...</option>
+                {stowTargets.map((target) => (
+                  <option value={target} key={target}>
+                    {target}
+                  </option>
+                ))}
+              </select>
             )}
             <button
               onClick={() => {
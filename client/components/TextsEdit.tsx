@@ -0,0 +1,58 @@
+import { TextOverlay } from "../api";
+
+export type TextsEditProps = {
+  value: TextOverlay[];
+  onChange: (texts: TextOverlay[]) => void;
+};
+
+/** Edits the captions drawn over a clip or title card. */
+export function TextsEdit({ value, onChange }: TextsEditProps) {
+  const update = (i: number, text: TextOverlay) =>
+    onChange(value.map((old, j) => (i === j ? text : old)));
+  return (
+    <div className="texts-edit">
+      {value.map((text, i) => (
+        <div key={i} className="preview-actions">
+          <input
+            type="text"
+            placeholder="Text"
+            value={text.text}
+            onChange={(e) =>
+              update(i, { ...text, text: e.currentTarget.value })
+            }
+          />
+          <select
+            value={text.position ?? "Bottom"}
+            onChange={(e) => {
+              const position = e.currentTarget.value;
+              if (
+                position === "Top" ||
+                position === "Center" ||
+                position === "Bottom"
+              ) {
+                update(i, { ...text, position });
+              }
+            }}
+          >
+            <option value="Top">Top</option>
+            <option value="Center">Center</option>
+            <option value="Bottom">Bottom</option>
+          </select>
+          <input
+            type="color"
+            value={text.color?.startsWith("#") ? text.color : "#ffffff"}
+            onChange={(e) =>
+              update(i, { ...text, color: e.currentTarget.value })
+            }
+          />
+          <button onClick={() => onChange(value.filter((_, j) => i !== j))}>
+            Remove
+          </button>
+        </div>
+      ))}
+      <button onClick={() => onChange([...value, { text: "" }])}>
+        Add text
+      </button>
+    </div>
+  );
+}
@@ -1,8 +1,10 @@
 import { CSSProperties, memo, useEffect, useRef } from "react";
-import { Rotation, Video } from "../api";
+import { Rotation, Transition, Video } from "../api";
 import { Clip } from "../types";
 import { RangeSlider } from "./RangeSlider";
-import { formatSeconds, rotToAngle } from "../util";
+import { FitSelect } from "./FitSelect";
+import { TextsEdit } from "./TextsEdit";
+import { clipDuration, formatSeconds, rotToAngle } from "../util";
 import { Video as VideoComp } from "./Video";
 
 type TrimmerProps = {
@@ -62,6 +64,13 @@ function Trimmer_({
     };
   }, [clip?.end]);
 
+  // preview at the clip's speed
+  useEffect(() => {
+    if (videoRef.current) {
+      videoRef.current.playbackRate = clip?.speed ?? 1;
+    }
+  }, [clip?.speed]);
+
   if (!clip || !video) {
     return <dialog ref={dialogRef} className="modal trimmer-container" />;
   }
@@ -111,10 +120,21 @@ function Trimmer_({
         <VideoComp video={video} videoRef={videoRef} preview />
       </div>
       <div className="trimmer-controls">
+        <div className="preview-actions">
+          <FitSelect
+            value={clip.fit}
+            onChange={(fit) => onUpdate({ ...clip, fit })}
+            defaultLabel="Fit like the rest of the video"
+          />
+        </div>
         <div className="trimmer-info">
           <div>Start: {formatSeconds(clip.start)}</div>
           <div>End: {formatSeconds(clip.end)}</div>
-          <div>Duration: {formatSeconds(clip.end - clip.start)}</div>
+          <div>
+            Duration: {formatSeconds(clip.end - clip.start)}
+            {clip.speed !== undefined &&
+              ` (${formatSeconds(clipDuration(clip))} at ${clip.speed}×)`}
+          </div>
         </div>
 
         <div className="range-slider-container">
@@ -192,6 +212,192 @@ function Trimmer_({
           </select>
           <button onClick={() => preview("end")}>Play near End</button>
         </div>
+        <div className="preview-actions">
+          <select
+            value={clip.speed ?? 1}
+            onChange={(e) => {
+              const speed = Number(e.currentTarget.value);
+              onUpdate({ ...clip, speed: speed !== 1 ? speed : undefined });
+            }}
+          >
+            {[0.25, 0.5, 0.75, 1, 1.25, 1.5, 2, 3, 4].map((speed) => (
+              <option key={speed} value={speed}>
+                {speed}× speed
+              </option>
+            ))}
+          </select>
+          <label>
+            <input
+              type="checkbox"
+              checked={clip.reverse ?? false}
+              onChange={(e) =>
+                onUpdate({
+                  ...clip,
+                  reverse: e.currentTarget.checked || undefined,
+                })
+              }
+            />
+            Reverse
+          </label>
+        </div>
+        <div className="preview-actions">
+          <select
+            value={clip.transition?.kind ?? ""}
+            onChange={(e) => {
+              const kind = e.currentTarget.value;
+              if (kind === "") {
+                onUpdate({ ...clip, transition: undefined });
+              } else if (
+                kind === "Crossfade" ||
+                kind === "FadeBlack" ||
+                kind === "Wipe"
+              ) {
+                onUpdate({
+                  ...clip,
+                  transition: {
+                    kind,
+                    duration: clip.transition?.duration ?? 1,
+                  },
+                });
+              }
+            }}
+          >
+            <option value="">Cut to next clip</option>
+            <option value="Crossfade">Crossfade</option>
+            <option value="FadeBlack">Fade through black</option>
+            <option value="Wipe">Wipe</option>
+          </select>
+          {clip.transition && (
+            <label>
+              <input
+                type="number"
+                min={0.1}
+                step={0.1}
+                value={clip.transition.duration}
+                onChange={(e) => {
+                  const duration = e.currentTarget.valueAsNumber;
+                  if (clip.transition && duration > 0) {
+                    const transition: Transition = {
+                      ...clip.transition,
+                      duration,
+                    };
+                    onUpdate({ ...clip, transition });
+                  }
+                }}
+              />
+              s
+            </label>
+          )}
+        </div>
+        <div className="preview-actions">
+          <label>
+            <input
+              type="checkbox"
+              checked={clip.mute ?? false}
+              onChange={(e) =>
+                onUpdate({
+                  ...clip,
+                  mute: e.currentTarget.checked || undefined,
+                })
+              }
+            />
+            Mute
+          </label>
+          <label>
+            Volume{" "}
+            <input
+              type="range"
+              min={0}
+              max={2}
+              step={0.05}
+              value={clip.volume ?? 1}
+              disabled={clip.mute}
+              onChange={(e) =>
+                onUpdate({ ...clip, volume: e.currentTarget.valueAsNumber })
+              }
+            />
+          </label>
+          {(["fadeIn", "fadeOut"] as const).map((field) => (
+            <label key={field}>
+              {field === "fadeIn" ? "Fade in" : "Fade out"}{" "}
+              <input
+                type="number"
+                min={0}
+                step={0.1}
+                value={clip[field] ?? 0}
+                onChange={(e) => {
+                  const seconds = e.currentTarget.valueAsNumber;
+                  if (seconds >= 0) {
+                    onUpdate({ ...clip, [field]: seconds || undefined });
+                  }
+                }}
+              />
+              s
+            </label>
+          ))}
+        </div>
+        <TextsEdit
+          value={clip.texts ?? []}
+          onChange={(texts) => onUpdate({ ...clip, texts })}
+        />
+        <div className="preview-actions">
+          <label>
+            <input
+              type="checkbox"
+              checked={clip.card !== undefined}
+              onChange={(e) =>
+                onUpdate({
+                  ...clip,
+                  card: e.currentTarget.checked
+                    ? { duration: 2, texts: [{ text: "", position: "Center" }] }
+                    : undefined,
+                })
+              }
+            />
+            Title card before this clip
+          </label>
+          {clip.card && (
+            <>
+              <select
+                value={"Frame" in (clip.card.background ?? {}) ? "frame" : ""}
+                onChange={(e) => {
+                  if (!clip.card) return;
+                  const background =
+                    e.currentTarget.value === "frame"
+                      ? { Frame: { id: clip.thumb, time: clip.start } }
+                      : undefined;
+                  onUpdate({ ...clip, card: { ...clip.card, background } });
+                }}
+              >
+                <option value="">Black</option>
+                <option value="frame">Blurred first frame</option>
+              </select>
+              <label>
+                <input
+                  type="number"
+                  min={0.1}
+                  step={0.1}
+                  value={clip.card.duration}
+                  onChange={(e) => {
+                    const duration = e.currentTarget.valueAsNumber;
+                    if (clip.card && duration > 0) {
+                      onUpdate({ ...clip, card: { ...clip.card, duration } });
+                    }
+                  }}
+                />
+                s
+              </label>
+            </>
+          )}
+        </div>
+        {clip.card && (
+          <TextsEdit
+            value={clip.card.texts}
+            onChange={(texts) =>
+              clip.card && onUpdate({ ...clip, card: { ...clip.card, texts } })
+            }
+          />
+        )}
         <div className="trimmer-info">
           Original Duration: {formatSeconds(duration)}
         </div>
@@ -1,22 +1,37 @@
-import { Rotation } from "./api";
+import { Fit, Rotation, TextOverlay, TitleCard, Transition } from "./api";
 
 export type ViewMode = { mode: "list" } | { mode: "feed" } | { mode: "grid" };
 
 export type Filter =
   | { mode: "none" }
   | { mode: "tagless" }
+  | { mode: "trash" }
   | { mode: "with-tag"; tag: string };
 
 export type Sort = { by: "mtime" | "size"; desc: boolean };
 
 export type Clip = {
   id: string;
-  /** references `thumbnail` in `state.videos` */
+  /** references `id` in `state.videos` */
   thumb: string;
   // in seconds
   start: number;
   end: number;
   overrideRotation?: Rotation;
+  /** into the next clip */
+  transition?: Transition;
+  /** overrides the cook's fit */
+  fit?: Fit;
+  texts?: TextOverlay[];
+  /** shown right before this clip */
+  card?: TitleCard;
+  volume?: number;
+  mute?: boolean;
+  fadeIn?: number;
+  fadeOut?: number;
+  /** 0.25 to 4 */
+  speed?: number;
+  reverse?: boolean;
 };
 
 export type ProjectState = {
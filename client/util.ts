@@ -1,4 +1,5 @@
-import { Rotation, Video } from "./api";
+import { CookItem, Rotation, Video } from "./api";
+import { Clip } from "./types";
 
 export function extractFilename(video: Video) {
   return video.path.split("/").pop() || video.path;
@@ -62,3 +63,62 @@ export const rotToAngle: Record<Rotation, number> = {
 
 export const map = <A, B>(v: A | null, fn: (v: A) => B | null): B | null =>
   v === null ? null : fn(v);
+
+/** How long the clip is in the cooked video, after changing its speed. */
+export const clipDuration = (clip: Clip) =>
+  (clip.end - clip.start) / (clip.speed ?? 1);
+
+/** Turns the editor's clips into what the server cooks and saves. */
+export const toCookItems = (clips: Clip[]): CookItem[] =>
+  clips.flatMap((clip): CookItem[] => [
+    ...(clip.card ? [{ card: clip.card }] : []),
+    {
+      start: clip.start,
+      end: clip.end,
+      id: clip.thumb,
+      override_rotation: clip.overrideRotation ?? null,
+      transition: clip.transition ?? null,
+      fit: clip.fit ?? null,
+      texts: (clip.texts ?? []).filter(({ text }) => text.trim()),
+      volume: clip.volume,
+      mute: clip.mute,
+      fade_in: clip.fadeIn,
+      fade_out: clip.fadeOut,
+      speed: clip.speed,
+      reverse: clip.reverse,
+    },
+  ]);
+
+/**
+ * The other way around, for a project saved on the server. Title cards go
+ * with the clip after them.
+ */
+export function fromCookItems(items: CookItem[]): Clip[] {
+  const clips: Clip[] = [];
+  let card: Clip["card"];
+  for (const item of items) {
+    if ("card" in item) {
+      card = item.card;
+      continue;
+    }
+    clips.push({
+      id: crypto.randomUUID(),
+      thumb: item.id,
+      start: item.start,
+      end: item.end,
+      overrideRotation: item.override_rotation ?? undefined,
+      transition: item.transition ?? undefined,
+      fit: item.fit ?? undefined,
+      texts: item.texts,
+      card,
+      volume: item.volume,
+      mute: item.mute,
+      fadeIn: item.fade_in,
+      fadeOut: item.fade_out,
+      speed: item.speed,
+      reverse: item.reverse,
+    });
+    card = undefined;
+  }
+  return clips;
+}
@@ -0,0 +1,264 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::fs;
+
+use crate::{
+    common::DIR_PATH,
+    http_handler::make_filter::edge_path,
+    util::{FNV_OFFSET_BASIS, MyResult, fnv1a},
+};
+
+/// What a clip can render to: the clip itself, then the pieces that go into
+/// transitions (see [`edge_path`]).
+const PARTS: [Option<&str>; 3] = [None, Some("head"), Some("tail")];
+
+pub fn segment_cache_dir() -> PathBuf {
+    Path::new(DIR_PATH).join("cache").join("segments")
+}
+
+/// Names a rendered clip by everything that went into rendering it. The
+/// program version is part of it, since filters change between versions.
+pub fn segment_key(description: &str) -> String {
+    let hash = fnv1a(env!("CARGO_PKG_VERSION").as_bytes(), FNV_OFFSET_BASIS);
+    format!("{:016x}", fnv1a(description.as_bytes(), hash))
+}
+
+/// Rendered clips from earlier cooks, so later ones only render what changed.
+/// Each entry is a folder named by [`segment_key`]. Once it's over
+/// `max_bytes`, the least recently used entries get deleted.
+#[derive(Clone)]
+pub struct SegmentCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+fn part_name(part: Option<&str>) -> String {
+    match part {
+        Some(part) => format!("clip.{part}.mp4"),
+        None => String::from("clip.mp4"),
+    }
+}
+
+fn part_path(output_path: &str, part: Option<&str>) -> PathBuf {
+    match part {
+        Some(part) => edge_path(output_path, part),
+        None => PathBuf::from(output_path),
+    }
+}
+
+/// Hard links if it can, since the work folder and cache are usually on the
+/// same drive. Nothing writes to either copy afterwards, so sharing is fine.
+async fn link_or_copy(from: &Path, to: &Path) -> MyResult<()> {
+    match fs::remove_file(to).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err)?,
+        _ => {}
+    }
+    if fs::hard_link(from, to).await.is_err() {
+        fs::copy(from, to).await?;
+    }
+    Ok(())
+}
+
+/// (path, size, last used) of an entry.
+async fn entry_info(path: PathBuf) -> MyResult<(PathBuf, u64, SystemTime)> {
+    let mut size = 0;
+    let mut files = fs::read_dir(&path).await?;
+    while let Some(file) = files.next_entry().await? {
+        size += file.metadata().await?.len();
+    }
+    // restoring bumps the clip's modified time
+    let last_used = fs::metadata(path.join(part_name(None))).await?.modified()?;
+    Ok((path, size, last_used))
+}
+
+impl SegmentCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    /// Whether there's a clip under `key` to restore.
+    pub async fn contains(&self, key: &str) -> MyResult<bool> {
+        Ok(self.max_bytes != 0 && fs::try_exists(self.dir.join(key).join(part_name(None))).await?)
+    }
+
+    /// Copies a cached clip to `output_path`, along with its transition
+    /// pieces. Returns whether it was there.
+    pub async fn restore(&self, key: &str, output_path: &str) -> MyResult<bool> {
+        if !self.contains(key).await? {
+            return Ok(false);
+        }
+        let entry = self.dir.join(key);
+        let clip = entry.join(part_name(None));
+        for part in PARTS {
+            let cached = entry.join(part_name(part));
+            if fs::try_exists(&cached).await? {
+                link_or_copy(&cached, &part_path(output_path, part)).await?;
+            }
+        }
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&clip)
+            .await?
+            .into_std()
+            .await
+            .set_modified(SystemTime::now())?;
+        Ok(true)
+    }
+
+    /// Saves a rendered clip and its transition pieces under `key`.
+    pub async fn store(&self, key: &str, output_path: &str) -> MyResult<()> {
+        let entry = self.dir.join(key);
+        if self.max_bytes == 0 || fs::try_exists(&entry).await? {
+            return Ok(());
+        }
+        // so other cooks never see half an entry
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_nanos();
+        let partial = self.dir.join(format!("{key}.{nanos}.partial"));
+        fs::create_dir_all(&partial).await?;
+        let result = async {
+            for part in PARTS {
+                let rendered = part_path(output_path, part);
+                if fs::try_exists(&rendered).await? {
+                    link_or_copy(&rendered, &partial.join(part_name(part))).await?;
+                }
+            }
+            fs::rename(&partial, &entry).await?;
+            Ok(())
+        }
+        .await;
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&partial).await;
+        }
+        result
+    }
+
+    /// The entries, least recently used first, and leftovers from stores that
+    /// didn't finish. Broken entries get removed.
+    async fn entries(&self) -> MyResult<(Vec<(PathBuf, u64, SystemTime)>, Vec<PathBuf>)> {
+        let mut entries = Vec::new();
+        let mut partial = Vec::new();
+        let mut dir = match fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok((entries, partial)),
+            Err(err) => Err(err)?,
+        };
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().ends_with(".partial") {
+                partial.push(path);
+                continue;
+            }
+            match entry_info(path.clone()).await {
+                Ok(info) => entries.push(info),
+                // e.g. a stray file, or a folder missing its clip. nothing can
+                // use it, so it shouldn't stop the rest from being cleaned up
+                Err(err) => {
+                    eprintln!("[cache] Removing broken entry {}: {err}", path.display());
+                    if fs::remove_dir_all(&path).await.is_err() {
+                        let _ = fs::remove_file(&path).await;
+                    }
+                }
+            }
+        }
+        entries.sort_by_key(|(_, _, last_used)| *last_used);
+        Ok((entries, partial))
+    }
+
+    /// Deletes the least recently used entries until the cache fits. Returns
+    /// how many bytes were freed.
+    pub async fn evict(&self) -> MyResult<u64> {
+        let (entries, _) = self.entries().await?;
+        let mut total = entries.iter().map(|(_, size, _)| size).sum::<u64>();
+        let mut freed = 0;
+        for (path, size, _) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            fs::remove_dir_all(path).await?;
+            total -= size;
+            freed += size;
+        }
+        Ok(freed)
+    }
+
+    /// Deletes everything. Returns how many entries there were and how many
+    /// bytes that freed.
+    pub async fn clear(&self) -> MyResult<(usize, u64)> {
+        let (entries, partial) = self.entries().await?;
+        let freed = entries.iter().map(|(_, size, _)| size).sum();
+        for path in partial
+            .into_iter()
+            .chain(entries.iter().map(|(path, _, _)| path.clone()))
+        {
+            fs::remove_dir_all(path).await?;
+        }
+        Ok((entries.len(), freed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_segment_key() {
+        assert_eq!(segment_key("a"), segment_key("a"));
+        assert_ne!(segment_key("a"), segment_key("b"));
+        assert_eq!(segment_key("a").len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_segment_cache() {
+        let dir = tempdir().unwrap();
+        let work = dir.path().join("work");
+        fs::create_dir_all(&work).await.unwrap();
+        let cache = SegmentCache::new(dir.path().join("cache"), 10);
+        fs::create_dir_all(dir.path().join("cache")).await.unwrap();
+
+        let clip0 = work.join("clip0.mp4").to_string_lossy().to_string();
+        fs::write(&clip0, b"first").await.unwrap();
+        fs::write(work.join("clip0.tail.mp4"), b"t").await.unwrap();
+        assert!(!cache.restore("a", &clip0).await.unwrap());
+        cache.store("a", &clip0).await.unwrap();
+
+        let clip1 = work.join("clip1.mp4").to_string_lossy().to_string();
+        assert!(cache.restore("a", &clip1).await.unwrap());
+        assert_eq!(fs::read(&clip1).await.unwrap(), b"first");
+        assert_eq!(fs::read(work.join("clip1.tail.mp4")).await.unwrap(), b"t");
+        assert!(!work.join("clip1.head.mp4").exists());
+
+        // a is 6 bytes, so adding b (5 bytes) goes over the limit
+        let entry_a = dir.path().join("cache/a/clip.mp4");
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::options()
+            .write(true)
+            .open(&entry_a)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+        let clip2 = work.join("clip2.mp4").to_string_lossy().to_string();
+        fs::write(&clip2, b"other").await.unwrap();
+        cache.store("b", &clip2).await.unwrap();
+        assert_eq!(cache.evict().await.unwrap(), 6);
+        assert!(!cache.restore("a", &clip1).await.unwrap());
+        assert!(cache.restore("b", &clip1).await.unwrap());
+
+        // junk in the cache folder gets cleaned up instead of failing
+        std::fs::create_dir(dir.path().join("cache/junk")).unwrap();
+        std::fs::write(dir.path().join("cache/stray.txt"), b"hi").unwrap();
+        assert_eq!(cache.evict().await.unwrap(), 0);
+        assert!(!dir.path().join("cache/junk").exists());
+        assert!(!dir.path().join("cache/stray.txt").exists());
+        std::fs::create_dir(dir.path().join("cache/junk")).unwrap();
+        assert_eq!(cache.clear().await.unwrap(), (1, 5));
+        assert!(!cache.restore("b", &clip1).await.unwrap());
+    }
+}
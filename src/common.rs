@@ -1,35 +1,168 @@
-use std::{collections::HashSet, path::PathBuf, sync::Arc, time::SystemTime};
+use std::{
+    collections::HashSet,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use serde::{Deserialize, Serialize};
-use tokio::{fs, sync::RwLock};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
-use crate::util::MyResult;
+use crate::{
+    http_handler::defs::Project,
+    jobs::Jobs,
+    journal::Journal,
+    migrations::SCHEMA_VERSION,
+    storage::Storage,
+    util::{FNV_OFFSET_BASIS, MyResult, fnv1a, move_file},
+};
 
 pub const DIR_PATH: &str = "./.video-sort";
 /// my phone has 2 Gold and 6 Silver cores apparently so I'll hope it uses the silver one to save the gold for the rest of my phone's brain cells
 pub const MAX_CONCURRENT_FFMPEG: usize = 4;
 
-pub type SharedState = Arc<RwLock<State>>;
+pub type SharedState = Arc<AppState>;
+
+/// The state everything reads from, plus where changes to it get saved.
+pub struct AppState {
+    state: RwLock<State>,
+    storage: Box<dyn Storage>,
+    /// Saves can come from many tasks at once. Only letting one run at a time
+    /// means a save of an older snapshot can't finish after, and overwrite, a
+    /// newer one.
+    save_lock: Mutex<()>,
+    journal: Mutex<Journal>,
+    jobs: Jobs,
+}
+
+impl AppState {
+    pub fn new(state: State, storage: Box<dyn Storage>, journal: Journal) -> Self {
+        Self {
+            state: RwLock::new(state),
+            storage,
+            save_lock: Mutex::new(()),
+            journal: Mutex::new(journal),
+            jobs: Jobs::new(Path::new(DIR_PATH).join("jobs")),
+        }
+    }
+
+    /// State for tests that saves to JSON and keeps its journal in `dir`.
+    #[cfg(test)]
+    pub async fn for_tests(state: State, dir: &Path) -> SharedState {
+        let journal = Journal::load(&dir.join("journal.json")).await.unwrap();
+        Arc::new(Self::new(
+            state,
+            Box::new(crate::storage::JsonStorage::new(dir)),
+            journal,
+        ))
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().await
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().await
+    }
+
+    /// Don't take this while holding the state lock; undo takes them in the
+    /// other order.
+    pub async fn journal(&self) -> MutexGuard<'_, Journal> {
+        self.journal.lock().await
+    }
+
+    pub fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    /// Saves everything.
+    pub async fn save(&self) -> MyResult<()> {
+        let _guard = self.save_lock.lock().await;
+        // snapshot only after getting the lock so the newest state always wins
+        let write = self.storage.save(&*self.state.read().await);
+        write.await
+    }
+
+    /// Saves just the videos with the given IDs, including deleting the ones
+    /// that are gone. Cheaper than [`AppState::save`] for some storage kinds.
+    pub async fn save_videos(&self, ids: &[String]) -> MyResult<()> {
+        let _guard = self.save_lock.lock().await;
+        let write = self.storage.save_videos(&*self.state.read().await, ids);
+        write.await
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Video {
+    /// stable ID for the video that survives moves and renames. see
+    /// [`content_id`]. empty for videos from before IDs existed until
+    /// [`assign_missing_ids`] gets to them
+    #[serde(default)]
+    pub id: String,
     path: PathBuf,
-    /// usable as an ID for the video
+    /// file name of the thumbnail in the thumbs folder. this used to be the ID
+    /// but could collide
     pub thumbnail_name: String,
     pub tags: HashSet<String>,
     pub note: String,
     pub mtime: SystemTime,
     pub size: u64,
     pub probe: Option<ProbeResult>,
-    /// currently unused, but will be used for stowing videos in Termux to avoid
-    /// persecution by Google Photos, or for making it easier to find a
-    /// particular video in an app's file selector
+    /// whether the file was moved to one of the stow targets in the config, e.g.
+    /// to hide it in Termux from persecution by Google Photos, or to make it
+    /// easier to find in an app's file selector
     stow_state: StowState,
+    /// set while the video is in the trash. see `trash`
+    #[serde(default)]
+    trashed: Option<Trashed>,
+    /// what went into it, if it was cooked by the app
+    #[serde(default)]
+    pub cooked_from: Option<Provenance>,
+}
+
+/// Where a cooked video came from, so the originals it used can be found.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// the cook job that made it
+    pub job: String,
+    /// the saved project that was cooked, if any
+    pub project: Option<String>,
+    /// every clip, in order
+    pub sources: Vec<SourceClip>,
+}
+
+/// The part of an original video a cooked clip was cut from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SourceClip {
+    pub id: String,
+    pub thumbnail_name: String,
+    /// in seconds
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Trashed {
+    /// where the file got moved to in the trash folder
+    path: PathBuf,
+    at: SystemTime,
 }
 
 impl Video {
-    pub fn new(path: PathBuf, thumbnail_name: String, mtime: SystemTime, size: u64) -> Self {
+    pub fn new(
+        id: String,
+        path: PathBuf,
+        thumbnail_name: String,
+        mtime: SystemTime,
+        size: u64,
+    ) -> Self {
         Self {
+            id,
             path,
             thumbnail_name,
             tags: HashSet::new(),
@@ -38,15 +171,26 @@ impl Video {
             size,
             probe: None,
             stow_state: StowState::Original,
+            trashed: None,
+            cooked_from: None,
         }
     }
 
+    /// whether the ID in a request refers to this video. thumbnail names are
+    /// still accepted for clients from before IDs existed
+    pub fn matches_id(&self, id: &str) -> bool {
+        self.id == id || self.thumbnail_name == id
+    }
+
     // returns the path to the current location of the video file contents, which may not
     // be its original path
     pub fn current_loc(&self) -> &PathBuf {
+        if let Some(trashed) = &self.trashed {
+            return &trashed.path;
+        }
         match &self.stow_state {
             StowState::Original => &self.path,
-            StowState::Elsewhere(path) => path,
+            StowState::Stowed { path, .. } => path,
         }
     }
 
@@ -58,33 +202,118 @@ impl Video {
         )
     }
 
-    pub async fn move_file(&mut self, new_path: PathBuf) -> MyResult<()> {
-        match self.stow_state {
-            StowState::Original => {
-                fs::rename(&self.path, &new_path).await?;
-                self.stow_state = StowState::Elsewhere(new_path);
-            }
-            StowState::Elsewhere(_) => Err("i'm already elsewhere")?,
+    /// name of the stow target the file is in, if it's not in its original
+    /// location
+    pub fn stow_target(&self) -> Option<&str> {
+        match &self.stow_state {
+            StowState::Original => None,
+            StowState::Stowed { target, .. } => Some(target),
+        }
+    }
+
+    /// Moves the file to `new_path` in the given stow target. If it's already
+    /// stowed somewhere else, it gets moved straight from there.
+    pub async fn stow(&mut self, target: String, new_path: PathBuf) -> MyResult<()> {
+        if self.trashed.is_some() {
+            Err("i'm in the trash")?;
         }
+        // moving would silently replace it
+        if fs::try_exists(&new_path).await? {
+            Err(format!("{} already exists", new_path.display()))?;
+        }
+        move_file(self.current_loc(), &new_path).await?;
+        self.stow_state = StowState::Stowed {
+            target,
+            path: new_path,
+        };
         Ok(())
     }
 
     pub async fn restore_file(&mut self) -> MyResult<()> {
+        if self.trashed.is_some() {
+            Err("i'm in the trash")?;
+        }
         match &self.stow_state {
             StowState::Original => {}
-            StowState::Elsewhere(path) => {
-                fs::rename(path, &self.path).await?;
+            StowState::Stowed { path, .. } => {
+                move_file(path, &self.path).await?;
             }
         }
         self.stow_state = StowState::Original;
         Ok(())
     }
+
+    /// When the video was moved to the trash, if it's there.
+    pub fn trashed_at(&self) -> Option<SystemTime> {
+        self.trashed.as_ref().map(|trashed| trashed.at)
+    }
+
+    /// Moves the file into `trash_dir`. Does nothing if it's already there.
+    pub async fn trash(&mut self, trash_dir: &Path) -> MyResult<()> {
+        if self.trashed.is_some() {
+            return Ok(());
+        }
+        let extension = self
+            .current_loc()
+            .extension()
+            .map_or_else(String::new, |ext| format!(".{}", ext.to_string_lossy()));
+        let path = trash_dir.join(format!("{}{extension}", self.id));
+        move_file(self.current_loc(), &path).await?;
+        self.trashed = Some(Trashed {
+            path,
+            at: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    /// Moves the file out of the trash back to where it was.
+    pub async fn untrash(&mut self) -> MyResult<()> {
+        let Some(trashed) = self.trashed.take() else {
+            return Ok(());
+        };
+        if let Err(err) = move_file(&trashed.path, self.current_loc()).await {
+            self.trashed = Some(trashed);
+            Err(err)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the file to where it is in `snapshot`, taking its stow and trash
+    /// state. Used by undo/redo.
+    pub async fn move_like(&mut self, snapshot: &Video) -> MyResult<()> {
+        if self.current_loc() != snapshot.current_loc() {
+            if let Some(parent) = snapshot.current_loc().parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            move_file(self.current_loc(), snapshot.current_loc()).await?;
+        }
+        self.take_location(snapshot);
+        Ok(())
+    }
+
+    /// Takes where the file is from `other`, e.g. a copy of this video whose
+    /// file was moved without the state locked.
+    pub fn take_location(&mut self, other: &Video) {
+        self.stow_state = other.stow_state.clone();
+        self.trashed = other.trashed.clone();
+    }
+
+    /// Points the video at where its file turned up after being moved outside
+    /// the app. It's back among the originals there, even if it was stowed or
+    /// trashed before.
+    pub fn relocate(&mut self, path: PathBuf, mtime: SystemTime, size: u64) {
+        self.path = path;
+        self.stow_state = StowState::Original;
+        self.trashed = None;
+        self.mtime = mtime;
+        self.size = size;
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum StowState {
     Original,
-    Elsewhere(PathBuf),
+    Stowed { target: String, path: PathBuf },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -101,6 +330,12 @@ pub struct ProbeResult {
     pub color_primaries: Option<String>,
     pub bit_rate: u32, // oops bitrate does not matter
     pub audio: Option<AudioProbeResult>,
+    // only used to tell if clips can be stream copied. older probes don't have
+    // them
+    #[serde(default)]
+    pub codec: Option<String>,
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -133,22 +368,118 @@ impl Rotation {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct State {
+    /// format of this file. see `migrations`
+    pub schema: u32,
     pub videos: Vec<Video>,
     pub version: Option<String>,
+    /// folders passed to `add`, which `sync` rescans for new videos
+    #[serde(default)]
+    pub roots: Vec<PathBuf>,
+    /// edits saved from the editor
+    #[serde(default)]
+    pub projects: Vec<Project>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            schema: SCHEMA_VERSION,
+            videos: Vec::new(),
+            version: None,
+            roots: Vec::new(),
+            projects: Vec::new(),
+        }
+    }
+}
+
+impl State {
+    pub fn find_video(&self, id: &str) -> Option<&Video> {
+        self.videos.iter().find(|video| video.matches_id(id))
+    }
+
+    pub fn find_video_mut(&mut self, id: &str) -> Option<&mut Video> {
+        self.videos.iter_mut().find(|video| video.matches_id(id))
+    }
+
+    pub fn find_project(&self, id: &str) -> Option<&Project> {
+        self.projects.iter().find(|project| project.id == id)
+    }
+
+    /// Appends a number to the ID if another video already has it, e.g. when
+    /// the same file was copied to two places.
+    pub fn unique_id(&self, id: String) -> String {
+        let taken = |id: &str| self.videos.iter().any(|video| video.id == id);
+        if !taken(&id) {
+            return id;
+        }
+        (2..)
+            .map(|n| format!("{id}-{n}"))
+            .find(|id| !taken(id))
+            .expect("ran out of numbers")
+    }
+}
+
+/// Whether `video_id` came from the [`content_id`] `id`, including the
+/// numbered IDs [`State::unique_id`] gives copies of the same file.
+pub fn same_content(video_id: &str, id: &str) -> bool {
+    video_id.strip_prefix(id).is_some_and(|rest| {
+        rest.is_empty()
+            || rest
+                .strip_prefix('-')
+                .is_some_and(|n| n.parse::<u32>().is_ok())
+    })
 }
 
-pub async fn save_state(state: &State) -> MyResult<()> {
-    fs::write(
-        format!("{DIR_PATH}/state.json"),
-        serde_json::to_string_pretty(state)?,
-    )
-    .await?;
-    Ok(())
+/// How much of the start and end of a file gets hashed for its ID. Hashing
+/// entire videos would take forever on a phone.
+const ID_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// Derives an ID from the file's size and the bytes at its start and end, so
+/// it stays the same if the file is moved or renamed.
+pub async fn content_id(path: &Path) -> MyResult<String> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let mut hash = fnv1a(&size.to_le_bytes(), FNV_OFFSET_BASIS);
+    let mut buffer = vec![0; size.min(ID_SAMPLE_SIZE) as usize];
+    file.read_exact(&mut buffer).await?;
+    hash = fnv1a(&buffer, hash);
+    if size > ID_SAMPLE_SIZE {
+        let tail_size = (size - ID_SAMPLE_SIZE).min(ID_SAMPLE_SIZE);
+        buffer.truncate(tail_size as usize);
+        file.seek(SeekFrom::End(-(tail_size as i64))).await?;
+        file.read_exact(&mut buffer).await?;
+        hash = fnv1a(&buffer, hash);
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+/// Gives videos added before IDs existed an ID. Their thumbnails keep their
+/// old names. Returns whether anything changed.
+pub async fn assign_missing_ids(state: &mut State) -> bool {
+    let mut changed = false;
+    for i in 0..state.videos.len() {
+        if !state.videos[i].id.is_empty() {
+            continue;
+        }
+        let id = match content_id(state.videos[i].current_loc()).await {
+            Ok(id) => id,
+            // the file is gone, but it still needs an ID so it can be deleted
+            // from the app
+            Err(_) => format!(
+                "{:016x}",
+                fnv1a(state.videos[i].thumbnail_name.as_bytes(), FNV_OFFSET_BASIS)
+            ),
+        };
+        state.videos[i].id = state.unique_id(id);
+        changed = true;
+    }
+    changed
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::migrations::migrate;
     use std::fs::File;
     use tempfile::tempdir;
 
@@ -163,6 +494,7 @@ mod tests {
     #[test]
     fn test_video_display_name() {
         let video = Video::new(
+            "0123456789abcdef".to_string(),
             PathBuf::from("/path/to/video.mp4"),
             "video.mp4".to_string(),
             SystemTime::now(),
@@ -179,6 +511,7 @@ mod tests {
         File::create(&original_path).unwrap();
 
         let mut video = Video::new(
+            "0123456789abcdef".to_string(),
             original_path.clone(),
             "original.mp4".to_string(),
             SystemTime::now(),
@@ -188,10 +521,25 @@ mod tests {
         // Test move
         assert!(original_path.exists());
         assert!(!new_path.exists());
-        video.move_file(new_path.clone()).await.unwrap();
+        video
+            .stow("for-youtube".to_string(), new_path.clone())
+            .await
+            .unwrap();
         assert!(!original_path.exists());
         assert!(new_path.exists());
         assert_eq!(video.current_loc(), &new_path);
+        assert_eq!(video.stow_target(), Some("for-youtube"));
+
+        // Test move between targets
+        let other_path = dir.path().join("other.mp4");
+        video
+            .stow("hidden".to_string(), other_path.clone())
+            .await
+            .unwrap();
+        assert!(!new_path.exists());
+        assert!(other_path.exists());
+        assert_eq!(video.stow_target(), Some("hidden"));
+        let new_path = other_path;
 
         // Test restore
         video.restore_file().await.unwrap();
@@ -199,4 +547,99 @@ mod tests {
         assert!(!new_path.exists());
         assert_eq!(video.current_loc(), &original_path);
     }
+
+    #[tokio::test]
+    async fn test_video_trash_and_untrash() {
+        let dir = tempdir().unwrap();
+        let original_path = dir.path().join("original.mov");
+        let trash_dir = dir.path().join("trash");
+        std::fs::create_dir(&trash_dir).unwrap();
+        File::create(&original_path).unwrap();
+
+        let mut video = Video::new(
+            "0123456789abcdef".to_string(),
+            original_path.clone(),
+            "0123456789abcdef.jpg".to_string(),
+            SystemTime::now(),
+            12345,
+        );
+        video.trash(&trash_dir).await.unwrap();
+        let trashed_path = trash_dir.join("0123456789abcdef.mov");
+        assert!(!original_path.exists());
+        assert!(trashed_path.exists());
+        assert_eq!(video.current_loc(), &trashed_path);
+        assert!(video.trashed_at().is_some());
+        assert!(
+            video
+                .stow("hidden".to_string(), dir.path().join("new.mov"))
+                .await
+                .is_err()
+        );
+
+        video.untrash().await.unwrap();
+        assert!(original_path.exists());
+        assert_eq!(video.current_loc(), &original_path);
+        assert!(video.trashed_at().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_content_id() {
+        let dir = tempdir().unwrap();
+        let small = dir.path().join("small.mp4");
+        std::fs::write(&small, b"tiny video").unwrap();
+        let id = content_id(&small).await.unwrap();
+        assert_eq!(id.len(), 16);
+
+        // moving the file keeps the ID
+        let moved = dir.path().join("moved.mp4");
+        std::fs::rename(&small, &moved).unwrap();
+        assert_eq!(content_id(&moved).await.unwrap(), id);
+
+        // the middle of large files is skipped, but the end isn't
+        let large = dir.path().join("large.mp4");
+        let mut contents = vec![0; 3 * ID_SAMPLE_SIZE as usize];
+        std::fs::write(&large, &contents).unwrap();
+        let large_id = content_id(&large).await.unwrap();
+        contents[ID_SAMPLE_SIZE as usize + 5] = 1;
+        std::fs::write(&large, &contents).unwrap();
+        assert_eq!(content_id(&large).await.unwrap(), large_id);
+        *contents.last_mut().unwrap() = 1;
+        std::fs::write(&large, &contents).unwrap();
+        assert_ne!(content_id(&large).await.unwrap(), large_id);
+    }
+
+    #[tokio::test]
+    async fn test_assign_missing_ids() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.mp4");
+        let copy = dir.path().join("b.mp4");
+        std::fs::write(&path, b"video").unwrap();
+        std::fs::write(&copy, b"video").unwrap();
+        // state.json entries from before IDs existed
+        let videos = [&path, &copy, &dir.path().join("gone.mp4")]
+            .iter()
+            .map(|path| {
+                serde_json::json!({
+                    "path": path,
+                    "thumbnail_name": format!("{}.jpg", path.display()),
+                    "tags": [],
+                    "note": "",
+                    "mtime": { "secs_since_epoch": 0, "nanos_since_epoch": 0 },
+                    "size": 5,
+                    "probe": null,
+                    "stow_state": "Original",
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut doc = serde_json::json!({ "videos": videos, "version": "0.12.3" });
+        migrate(&mut doc).unwrap();
+        let mut state: State = serde_json::from_value(doc).unwrap();
+
+        assert!(assign_missing_ids(&mut state).await);
+        let id = content_id(&path).await.unwrap();
+        assert_eq!(state.videos[0].id, id);
+        assert_eq!(state.videos[1].id, format!("{id}-2"));
+        assert_eq!(state.videos[2].id.len(), 16);
+        assert!(!assign_missing_ids(&mut state).await);
+    }
 }
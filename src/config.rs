@@ -0,0 +1,402 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::fs;
+
+use crate::{
+    common::{DIR_PATH, Video},
+    util::{MyResult, glob_match},
+};
+
+/// Settings read from `.video-sort/config.json`. Every field is optional in the
+/// file; missing ones fall back to the defaults below.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// file extensions (without the dot) that `add` picks up. case insensitive
+    pub extensions: Vec<String>,
+    /// file and folder names that `add` skips. supports `*` and `?` wildcards,
+    /// e.g. `.trashed-*`
+    pub exclude: Vec<String>,
+    /// deleted videos stay in the trash for this many days before they're
+    /// deleted for real
+    pub trash_retention_days: u64,
+    /// places videos can be moved to, by name. added to the built-in ones,
+    /// which can be changed by using the same name
+    #[serde(deserialize_with = "over_default_targets")]
+    pub stow_targets: BTreeMap<String, StowTarget>,
+    /// encoding settings cooks can pick from, by name
+    pub presets: BTreeMap<String, EncodingPreset>,
+    /// music files for cooks have to be in here, and their paths are relative
+    /// to it. videos in the library can always be used
+    pub music_dir: PathBuf,
+    /// rendered clips are kept in `.video-sort/cache/segments` so later cooks
+    /// can reuse them, up to this many megabytes. 0 turns it off
+    pub segment_cache_mb: u64,
+}
+
+fn over_default_targets<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, StowTarget>, D::Error> {
+    let mut targets = Config::default().stow_targets;
+    targets.extend(BTreeMap::deserialize(deserializer)?);
+    Ok(targets)
+}
+
+/// A folder videos can be moved into, e.g. Downloads so they're easy to find
+/// when uploading to YouTube.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StowTarget {
+    pub dir: PathBuf,
+    /// file name for the moved video. `{id}`, `{name}` (the original file
+    /// name), `{stem}` (file name without extension), and `{ext}` get replaced
+    pub template: String,
+}
+
+impl StowTarget {
+    pub fn path_for(&self, video: &Video) -> PathBuf {
+        let name = video.display_name();
+        let (stem, ext) = name.rsplit_once('.').unwrap_or((&name, ""));
+        let file_name = self
+            .template
+            .replace("{id}", &video.id)
+            .replace("{stem}", stem)
+            .replace("{ext}", ext)
+            .replace("{name}", &name);
+        self.dir.join(file_name)
+    }
+}
+
+/// How cooked videos get encoded. Missing fields keep what cooks did before
+/// presets: h264 with `-preset veryfast -crf 18`, AAC, and the size and frame
+/// rate of the clips.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EncodingPreset {
+    /// overrides the size picked in the cook settings
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// constant frame rate. clips keep their own if missing
+    pub fps: Option<f64>,
+    /// an ffmpeg video encoder, e.g. `libx264` or `libx265`
+    pub video_codec: String,
+    /// left to the encoder if missing, e.g. for hardware encoders that don't
+    /// have one
+    pub crf: Option<u32>,
+    /// the encoder's speed preset, e.g. `veryfast`
+    pub preset: Option<String>,
+    /// an ffmpeg audio encoder
+    pub audio_codec: String,
+    /// e.g. `128k`. left to the encoder if missing
+    pub audio_bitrate: Option<String>,
+}
+
+impl Default for EncodingPreset {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            fps: None,
+            video_codec: String::from("libx264"),
+            crf: Some(18),
+            preset: Some(String::from("veryfast")),
+            audio_codec: String::from("aac"),
+            audio_bitrate: None,
+        }
+    }
+}
+
+/// Speed presets x264 and x265 share.
+const X26X_PRESETS: [&str; 10] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+    "placebo",
+];
+
+impl EncodingPreset {
+    /// Whether the clips' own h264 can be copied instead of re-encoded.
+    pub fn can_copy_h264(&self) -> bool {
+        self.video_codec == "libx264" && self.fps.is_none()
+    }
+
+    /// Checks the settings make sense and that the local ffmpeg has the
+    /// encoders.
+    pub fn check(&self, encoders: &Encoders) -> MyResult<()> {
+        for (name, size) in [("Width", self.width), ("Height", self.height)] {
+            if let Some(size) = size.filter(|size| !(16..=7680).contains(size) || size % 2 != 0) {
+                Err(format!(
+                    "{name} {size} has to be an even number from 16 to 7680."
+                ))?;
+            }
+        }
+        if let Some(fps) = self.fps.filter(|fps| !(*fps > 0.0 && *fps <= 240.0)) {
+            Err(format!("Frame rate {fps} isn't from 0 to 240."))?;
+        }
+        if !encoders.video.contains(&self.video_codec) {
+            Err(format!(
+                "This ffmpeg doesn't have a video encoder called {:?}.",
+                self.video_codec
+            ))?;
+        }
+        if !encoders.audio.contains(&self.audio_codec) {
+            Err(format!(
+                "This ffmpeg doesn't have an audio encoder called {:?}.",
+                self.audio_codec
+            ))?;
+        }
+        let x26x = matches!(self.video_codec.as_str(), "libx264" | "libx265");
+        let max_crf = if x26x { 51 } else { 63 };
+        if let Some(crf) = self.crf.filter(|crf| *crf > max_crf) {
+            Err(format!("CRF {crf} is over {max_crf}."))?;
+        }
+        if let Some(preset) = &self.preset {
+            let valid = if x26x {
+                X26X_PRESETS.contains(&preset.as_str())
+            } else {
+                !preset.is_empty() && preset.chars().all(|c| c.is_ascii_alphanumeric())
+            };
+            if !valid {
+                Err(format!(
+                    "{:?} isn't a preset {} has.",
+                    preset, self.video_codec
+                ))?;
+            }
+        }
+        if let Some(bitrate) = &self.audio_bitrate {
+            let digits = bitrate.strip_suffix(['k', 'M']).unwrap_or(bitrate);
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                Err(format!("Audio bitrate {bitrate:?} should look like 128k."))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The encoders the local ffmpeg has, from `ffmpeg -encoders`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Encoders {
+    pub video: Vec<String>,
+    pub audio: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            extensions: ["mp4", "mov", "mkv", "webm", "3gp"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            // android hides deleted files as .trashed-<timestamp>-<name> and
+            // files still being written as .pending-<timestamp>-<name>
+            exclude: [".thumbnails", ".trashed-*", ".pending-*"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            trash_retention_days: 30,
+            stow_targets: BTreeMap::from([
+                (
+                    String::from("for-youtube"),
+                    StowTarget {
+                        dir: PathBuf::from("./storage/downloads/for-youtube/"),
+                        template: String::from("yt_{id}.{ext}"),
+                    },
+                ),
+                // Termux's own storage, which Google Photos can't see
+                (
+                    String::from("hidden"),
+                    StowTarget {
+                        dir: PathBuf::from(format!("{DIR_PATH}/stowed/")),
+                        template: String::from("{name}"),
+                    },
+                ),
+            ]),
+            presets: BTreeMap::from([
+                (
+                    String::from("TikTok 1080x1920 30fps"),
+                    EncodingPreset {
+                        width: Some(1080),
+                        height: Some(1920),
+                        fps: Some(30.0),
+                        crf: Some(20),
+                        audio_bitrate: Some(String::from("128k")),
+                        ..EncodingPreset::default()
+                    },
+                ),
+                (
+                    String::from("YouTube Shorts"),
+                    EncodingPreset {
+                        width: Some(1080),
+                        height: Some(1920),
+                        fps: Some(60.0),
+                        audio_bitrate: Some(String::from("192k")),
+                        ..EncodingPreset::default()
+                    },
+                ),
+                // smaller files for keeping, but slow on a phone
+                (
+                    String::from("archive HEVC"),
+                    EncodingPreset {
+                        video_codec: String::from("libx265"),
+                        crf: Some(22),
+                        preset: Some(String::from("medium")),
+                        audio_bitrate: Some(String::from("192k")),
+                        ..EncodingPreset::default()
+                    },
+                ),
+            ]),
+            music_dir: PathBuf::from("./storage/music/"),
+            segment_cache_mb: 2048,
+        }
+    }
+}
+
+impl Config {
+    pub async fn load() -> MyResult<Self> {
+        match fs::read_to_string(format!("{DIR_PATH}/config.json")).await {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    pub fn is_video(&self, path: &Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy();
+            self.extensions
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(&ext))
+        })
+    }
+
+    pub fn is_excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|pattern| glob_match(pattern, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_video() {
+        let config = Config::default();
+        assert!(config.is_video(Path::new("/a/b.mp4")));
+        assert!(config.is_video(Path::new("/a/b.MOV")));
+        assert!(config.is_video(Path::new("b.3gp")));
+        assert!(!config.is_video(Path::new("/a/b.jpg")));
+        assert!(!config.is_video(Path::new("/a/mp4")));
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let config = Config::default();
+        assert!(config.is_excluded(".thumbnails"));
+        assert!(config.is_excluded(".trashed-1712345678-VID_1.mp4"));
+        assert!(!config.is_excluded("VID_1.mp4"));
+        assert!(!config.is_excluded("thumbnails"));
+    }
+
+    #[test]
+    fn test_stow_target_path_for() {
+        let video = Video::new(
+            String::from("0123456789abcdef"),
+            PathBuf::from("storage/dcim/Camera/PXL_1.mp4"),
+            String::from("0123456789abcdef.jpg"),
+            std::time::SystemTime::UNIX_EPOCH,
+            1,
+        );
+        let target = |template: &str| StowTarget {
+            dir: PathBuf::from("storage/downloads/"),
+            template: template.to_string(),
+        };
+        assert_eq!(
+            target("yt_{id}.{ext}").path_for(&video),
+            PathBuf::from("storage/downloads/yt_0123456789abcdef.mp4")
+        );
+        assert_eq!(
+            target("{stem} ({id}).{ext}").path_for(&video),
+            PathBuf::from("storage/downloads/PXL_1 (0123456789abcdef).mp4")
+        );
+        assert_eq!(
+            target("{name}").path_for(&video),
+            PathBuf::from("storage/downloads/PXL_1.mp4")
+        );
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let config: Config = serde_json::from_str(r#"{ "extensions": ["avi"] }"#).unwrap();
+        assert_eq!(config.extensions, vec!["avi"]);
+        assert_eq!(config.exclude, Config::default().exclude);
+        assert_eq!(config.trash_retention_days, 30);
+    }
+
+    #[test]
+    fn test_stow_targets_keep_defaults() {
+        let config: Config = serde_json::from_str(
+            r#"{ "stow_targets": { "hidden": { "dir": "elsewhere", "template": "{id}" },
+                "tiktok": { "dir": "tiktok", "template": "{name}" } } }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.stow_targets.keys().collect::<Vec<_>>(),
+            ["for-youtube", "hidden", "tiktok"]
+        );
+        assert_eq!(config.stow_targets["hidden"].dir, Path::new("elsewhere"));
+    }
+
+    #[test]
+    fn test_preset_check() {
+        let encoders = Encoders {
+            video: vec![String::from("libx264"), String::from("libx265")],
+            audio: vec![String::from("aac")],
+        };
+        for preset in Config::default().presets.values() {
+            preset.check(&encoders).unwrap();
+        }
+        let bad = [
+            EncodingPreset {
+                width: Some(1081),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                fps: Some(0.0),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                video_codec: String::from("libsvtav1"),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                audio_codec: String::from("libopus"),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                crf: Some(52),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                preset: Some(String::from("ludicrous")),
+                ..EncodingPreset::default()
+            },
+            EncodingPreset {
+                audio_bitrate: Some(String::from("loud")),
+                ..EncodingPreset::default()
+            },
+        ];
+        for preset in bad {
+            assert!(preset.check(&encoders).is_err(), "{preset:?}");
+        }
+    }
+}
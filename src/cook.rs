@@ -0,0 +1,600 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use serde::Serialize;
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    sync::Semaphore,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    cache::{SegmentCache, segment_cache_dir, segment_key},
+    common::{DIR_PATH, MAX_CONCURRENT_FFMPEG, ProbeResult},
+    config::{Config, EncodingPreset},
+    fmt::faded,
+    http_handler::{
+        defs::Transition,
+        make_filter::{
+            ClipPlan, ClipStep, Edges, Measurement, Measurements, edge_path, make_card, make_clip,
+            make_transition, measure_clip, measurements_needed, mix_music, plan_edges,
+        },
+        probe::defs::{CookItem, CookMusic},
+    },
+    jobs::Job,
+    progress::{CookProgress, FfmpegProgress, Progress, ProgressParser},
+    util::{BoxedError, MyResult},
+};
+
+/// Runs ffmpeg, logging its errors with each line prefixed by `prefix` and
+/// passing its `-progress pipe:1` output to `on_progress`. ffmpeg gets killed
+/// if `cancel` fires first.
+async fn run_ffmpeg(
+    job: &Job,
+    cancel: &CancellationToken,
+    mut command: Command,
+    prefix: &str,
+    on_progress: impl Fn(&FfmpegProgress) -> Progress,
+) -> MyResult<()> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn()?;
+    let stdout = child.stdout.take().ok_or("no stdout??")?;
+    let stderr = child.stderr.take().ok_or("no stderr??")?;
+    let errors = async {
+        let mut lines = BufReader::new(stderr).lines();
+        while let Some(line) = lines.next_line().await? {
+            job.log(&format!("{prefix}{line}"));
+        }
+        Ok::<_, BoxedError>(())
+    };
+    let progress = async {
+        let mut parser = ProgressParser::default();
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            if let Some(progress) = parser.feed(&line) {
+                job.progress(&on_progress(&progress));
+            }
+        }
+        Ok::<_, BoxedError>(())
+    };
+    let run = async {
+        tokio::try_join!(errors, progress)?;
+        Ok::<_, BoxedError>(child.wait().await?)
+    };
+    let status = tokio::select! {
+        status = run => status?,
+        // dropping the child kills it
+        _ = cancel.cancelled() => Err("cancelled")?,
+    };
+    if !status.success() {
+        Err(format!("ffmpeg failed with status: {status}"))?;
+    }
+    Ok(())
+}
+
+fn lock(progress: &Mutex<CookProgress>) -> std::sync::MutexGuard<'_, CookProgress> {
+    progress.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// What's worked out about the clips before any of them get made.
+struct Layout {
+    edges: Vec<Edges>,
+    /// for the segment cache
+    keys: Vec<String>,
+    /// (clip before, transition) of each transition
+    transitions: Vec<(usize, Transition)>,
+    /// of the whole video. clips overlap in transitions
+    duration: f64,
+}
+
+fn layout(clips: &[CookItem], encoding: &ProbeResult, preset: &EncodingPreset) -> MyResult<Layout> {
+    let edges = plan_edges(clips)?;
+    let keys = clips
+        .iter()
+        .zip(&edges)
+        .map(|(clip, edges)| {
+            segment_key(&format!(
+                "{} {encoding:?} {preset:?} {edges:?}",
+                clip.describe()
+            ))
+        })
+        .collect();
+    let transitions = clips
+        .iter()
+        .enumerate()
+        .take(clips.len().saturating_sub(1))
+        .filter_map(|(i, clip)| clip.transition().map(|transition| (i, transition)))
+        .collect::<Vec<_>>();
+    let duration = clips.iter().map(CookItem::duration).sum::<f64>()
+        - transitions
+            .iter()
+            .map(|(_, transition)| transition.duration)
+            .sum::<f64>();
+    Ok(Layout {
+        edges,
+        keys,
+        transitions,
+        duration,
+    })
+}
+
+async fn load_cache() -> MyResult<SegmentCache> {
+    Ok(SegmentCache::new(
+        segment_cache_dir(),
+        Config::load().await?.segment_cache_mb * 1024 * 1024,
+    ))
+}
+
+fn clip_path(work_dir: &str, i: usize) -> String {
+    format!("{work_dir}/clip{i}.mp4")
+}
+
+/// Without `measure`, clips get planned as if they couldn't be stream copied
+/// or normalized, and the measurements that would've changed that come back
+/// instead.
+async fn make_item(
+    clip: &CookItem,
+    encoding: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &str,
+    edges: &Edges,
+    measure: bool,
+) -> MyResult<(ClipPlan, Vec<Measurement>)> {
+    match clip {
+        CookItem::Clip(clip) if measure => {
+            let measured = measure_clip(clip, encoding, preset, edges).await?;
+            let plan = make_clip(clip, encoding, preset, output_path, edges, &measured)?;
+            Ok((plan, Vec::new()))
+        }
+        CookItem::Clip(clip) => {
+            let plan = make_clip(
+                clip,
+                encoding,
+                preset,
+                output_path,
+                edges,
+                &Measurements::default(),
+            )?;
+            Ok((plan, measurements_needed(clip, encoding, preset, edges)))
+        }
+        CookItem::Card(card) => Ok((
+            make_card(card, encoding, preset, output_path, edges).await?,
+            Vec::new(),
+        )),
+    }
+}
+
+/// Only the overlapping parts get encoded again.
+fn transition_command(
+    work_dir: &str,
+    i: usize,
+    transition: &Transition,
+    encoding: &ProbeResult,
+    preset: &EncodingPreset,
+) -> Command {
+    make_transition(
+        transition,
+        encoding,
+        preset,
+        &edge_path(&clip_path(work_dir, i), "tail"),
+        &edge_path(&clip_path(work_dir, i + 1), "head"),
+        Path::new(&format!("{work_dir}/transition{i}.mp4")),
+    )
+}
+
+/// The concat demuxer's list of clips and transitions, relative to the work
+/// folder.
+fn concat_list(clip_count: usize, transitions: &[(usize, Transition)]) -> String {
+    // note: without -safe 0, ffmpeg concat will reject file paths
+    // with a . in it
+    (0..clip_count)
+        .map(|i| {
+            if transitions.iter().any(|(before, _)| *before == i) {
+                format!("file 'clip{i}.mp4'\nfile 'transition{i}.mp4'\n")
+            } else {
+                format!("file 'clip{i}.mp4'\n")
+            }
+        })
+        .collect()
+}
+
+fn concat_command(concat_path: &str, output: &Path) -> Command {
+    let mut command = Command::new("ffmpeg");
+    command.arg("-v").arg("error");
+    command.arg("-nostats").arg("-progress").arg("pipe:1");
+    command.arg("-f").arg("concat");
+    command.arg("-i").arg(concat_path);
+    command.arg("-c").arg("copy");
+    command.arg("-y");
+    command.arg(output);
+    command
+}
+
+/// Music gets mixed into the concatted video, so then concat makes a file in
+/// the work folder.
+fn concat_output(work_dir: &str, music: Option<&CookMusic>, out_path: &Path) -> PathBuf {
+    if music.is_some() {
+        PathBuf::from(format!("{work_dir}/concat.mp4"))
+    } else {
+        out_path.to_path_buf()
+    }
+}
+
+async fn cook_in(
+    job: &Arc<Job>,
+    clips: Vec<CookItem>,
+    music: Option<CookMusic>,
+    encoding: &ProbeResult,
+    preset: &EncodingPreset,
+    name: &str,
+    work_dir: &str,
+) -> MyResult<PathBuf> {
+    eprintln!(
+        "{}",
+        faded(&format!("[cook] Generating {} clips...", clips.len()))
+    );
+    job.log(&format!("[cook] Generating {} clips...", clips.len()));
+
+    fs::create_dir_all(segment_cache_dir()).await?;
+    let cache = load_cache().await?;
+    let Layout {
+        edges,
+        keys,
+        transitions,
+        duration,
+    } = layout(&clips, encoding, preset)?;
+    let mut cook_progress = CookProgress::new(
+        clips.iter().map(CookItem::duration).collect(),
+        transitions
+            .iter()
+            .map(|(i, transition)| (*i, transition.duration))
+            .collect(),
+        Instant::now(),
+    );
+    if music.is_some() {
+        cook_progress = cook_progress.with_music();
+    }
+    let progress = Arc::new(Mutex::new(cook_progress));
+
+    // stops the other clips once one fails
+    let cancel = job.cancellation().child_token();
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FFMPEG));
+    let clip_count = clips.len();
+    let handles = clips
+        .into_iter()
+        .zip(edges)
+        .zip(keys)
+        .enumerate()
+        .map(|(i, ((clip, edges), key))| {
+            let semaphore = semaphore.clone();
+            let job = job.clone();
+            let cancel = cancel.clone();
+            let progress = progress.clone();
+            let encoding = encoding.clone();
+            let preset = preset.clone();
+            let cache = cache.clone();
+            let output_path = clip_path(work_dir, i);
+            tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                if cancel.is_cancelled() {
+                    Err("cancelled")?;
+                }
+                let result = async {
+                    match cache.restore(&key, &output_path).await {
+                        Ok(true) => {
+                            job.log(&format!("[cook] clip {i} from cache"));
+                            let done = FfmpegProgress {
+                                out_time: clip.duration(),
+                                speed: None,
+                                end: true,
+                            };
+                            job.progress(&lock(&progress).update_clip(i, &done, Instant::now()));
+                            return Ok(());
+                        }
+                        Ok(false) => {}
+                        // e.g. another cook evicted it, so just render it again
+                        Err(err) => job.log(&format!("[cook] couldn't reuse clip {i}: {err}")),
+                    }
+                    let (ClipPlan { files, steps }, _) =
+                        make_item(&clip, &encoding, &preset, &output_path, &edges, true).await?;
+                    for (path, contents) in files {
+                        fs::write(path, contents).await?;
+                    }
+                    let last = steps.len().saturating_sub(1);
+                    for (j, ClipStep { command, offset }) in steps.into_iter().enumerate() {
+                        eprintln!("{}", faded(&format!("[cook.{i}] {command:?}")));
+                        run_ffmpeg(&job, &cancel, command, &format!("[{i}] "), |update| {
+                            let update = FfmpegProgress {
+                                out_time: offset + update.out_time,
+                                speed: update.speed,
+                                end: update.end && j == last,
+                            };
+                            lock(&progress).update_clip(i, &update, Instant::now())
+                        })
+                        .await?;
+                    }
+                    // not worth failing the cook over
+                    if let Err(err) = cache.store(&key, &output_path).await {
+                        job.log(&format!("[cook] couldn't cache clip {i}: {err}"));
+                    }
+                    Ok::<_, BoxedError>(())
+                }
+                .await;
+                if result.is_err() {
+                    cancel.cancel();
+                }
+                result
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut first_error = None;
+    for (i, handle) in handles.into_iter().enumerate() {
+        let error = match handle.await {
+            Err(err) => format!("Unexpected join error in clip {i}: {err:?}"),
+            Ok(Err(err)) => format!("Clip {i} failed: {err}"),
+            Ok(Ok(())) => continue,
+        };
+        eprintln!("[cook.{i}] {error}");
+        first_error.get_or_insert(error);
+    }
+    if let Some(error) = first_error {
+        Err(error)?;
+    }
+    eprintln!("{}", faded("[cook] Clip generation complete."));
+    if let Err(err) = cache.evict().await {
+        job.log(&format!(
+            "[cook] couldn't clean up the segment cache: {err}"
+        ));
+    }
+
+    for (j, (i, transition)) in transitions.iter().enumerate() {
+        let command = transition_command(work_dir, *i, transition, encoding, preset);
+        eprintln!("{}", faded(&format!("[cook.{i}] {command:?}")));
+        run_ffmpeg(
+            job,
+            job.cancellation(),
+            command,
+            &format!("[{i}] "),
+            |update| lock(&progress).update_transition(j, update, Instant::now()),
+        )
+        .await?;
+    }
+
+    let concat_path = format!("{work_dir}/concat.txt");
+    fs::write(&concat_path, concat_list(clip_count, &transitions)).await?;
+    let out_path = output_path(name);
+    let concat_out = concat_output(work_dir, music.as_ref(), &out_path);
+    let command = concat_command(&concat_path, &concat_out);
+    eprintln!("{}", faded(&format!("[cook] {command:?}")));
+    // concat doesn't get a prefix
+    run_ffmpeg(job, job.cancellation(), command, "", |update| {
+        lock(&progress).update_concat(update, Instant::now())
+    })
+    .await?;
+    if let Some(music) = &music {
+        let command = mix_music(music, encoding, preset, &concat_out, duration, &out_path)?;
+        eprintln!("{}", faded(&format!("[cook] {command:?}")));
+        run_ffmpeg(job, job.cancellation(), command, "[music] ", |update| {
+            lock(&progress).update_music(update, Instant::now())
+        })
+        .await?;
+    }
+    eprintln!("[cook] Bon appetit! {}", out_path.display());
+    Ok(out_path)
+}
+
+/// Where a cook called `name` ends up.
+pub fn output_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("./storage/downloads/{name}.mp4"))
+}
+
+/// Turns the clips into a single video in Downloads, returning its path. Clips
+/// are made in a work folder that gets cleaned up afterwards, even on failure.
+pub async fn cook(
+    job: Arc<Job>,
+    clips: Vec<CookItem>,
+    music: Option<CookMusic>,
+    encoding: ProbeResult,
+    preset: EncodingPreset,
+    name: String,
+) -> MyResult<PathBuf> {
+    let work_dir = format!("{DIR_PATH}/work/{}", job.id);
+    fs::create_dir_all(&work_dir).await?;
+    let result = cook_in(&job, clips, music, &encoding, &preset, &name, &work_dir).await;
+    if let Err(err) = fs::remove_dir_all(work_dir).await {
+        eprintln!("[cook] failed to clean up workspace: {err}")
+    }
+    result
+}
+
+/// An ffmpeg run, as it'd be typed out.
+#[derive(Serialize, Debug)]
+pub struct PlannedCommand {
+    pub argv: Vec<String>,
+    /// the `-filter_complex` or `-af`, so it can be read on its own
+    pub filter: Option<String>,
+}
+
+impl From<&Command> for PlannedCommand {
+    fn from(command: &Command) -> Self {
+        let command = command.as_std();
+        let argv = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        let filter = argv
+            .windows(2)
+            .find(|pair| pair[0] == "-filter_complex" || pair[0] == "-af")
+            .map(|pair| pair[1].clone());
+        Self { argv, filter }
+    }
+}
+
+/// A file the commands read, like a concat list.
+#[derive(Serialize, Debug)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedClip {
+    pub output: String,
+    /// in seconds, after speed changes
+    pub duration: f64,
+    pub cache_key: String,
+    /// if so, a cook would copy it from the segment cache instead of running
+    /// `steps`
+    pub cached: bool,
+    /// to write before running `steps`
+    pub files: Vec<PlannedFile>,
+    pub steps: Vec<PlannedCommand>,
+    /// what the cook would measure first, which can change `steps`
+    pub pending: Vec<Measurement>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedTransition {
+    /// the clip it comes after
+    pub after: usize,
+    pub command: PlannedCommand,
+}
+
+/// Everything a cook would run, in order.
+#[derive(Serialize, Debug)]
+pub struct CookPlan {
+    pub work_dir: String,
+    pub output: PathBuf,
+    /// in seconds, with transitions overlapping
+    pub duration: f64,
+    pub clips: Vec<PlannedClip>,
+    pub transitions: Vec<PlannedTransition>,
+    pub concat_list: PlannedFile,
+    pub concat: PlannedCommand,
+    pub music: Option<PlannedCommand>,
+}
+
+/// Works out what [`cook`] would run, without running or writing anything.
+/// Probing clips for keyframes and measuring their loudness changes their
+/// commands, so that only happens with `measure`, and otherwise it's left in
+/// each clip's `pending`. The work folder is `work/plan` rather than a job's.
+pub async fn plan(
+    clips: Vec<CookItem>,
+    music: Option<CookMusic>,
+    encoding: &ProbeResult,
+    preset: &EncodingPreset,
+    name: &str,
+    measure: bool,
+) -> MyResult<CookPlan> {
+    let work_dir = format!("{DIR_PATH}/work/plan");
+    let cache = load_cache().await?;
+    let Layout {
+        edges,
+        keys,
+        transitions,
+        duration,
+    } = layout(&clips, encoding, preset)?;
+
+    let mut planned = Vec::new();
+    for (i, ((clip, edges), key)) in clips.iter().zip(edges).zip(keys).enumerate() {
+        let output = clip_path(&work_dir, i);
+        let (ClipPlan { files, steps }, pending) =
+            make_item(clip, encoding, preset, &output, &edges, measure)
+                .await
+                .map_err(|err| format!("Clip {i} failed: {err}"))?;
+        planned.push(PlannedClip {
+            output,
+            duration: clip.duration(),
+            cached: cache.contains(&key).await?,
+            cache_key: key,
+            files: files
+                .into_iter()
+                .map(|(path, contents)| PlannedFile { path, contents })
+                .collect(),
+            steps: steps
+                .iter()
+                .map(|step| PlannedCommand::from(&step.command))
+                .collect(),
+            pending,
+        });
+    }
+
+    let concat_path = format!("{work_dir}/concat.txt");
+    let out_path = output_path(name);
+    let concat_out = concat_output(&work_dir, music.as_ref(), &out_path);
+    let music = match &music {
+        Some(music) => Some(PlannedCommand::from(&mix_music(
+            music,
+            encoding,
+            preset,
+            &concat_out,
+            duration,
+            &out_path,
+        )?)),
+        None => None,
+    };
+    Ok(CookPlan {
+        transitions: transitions
+            .iter()
+            .map(|(i, transition)| PlannedTransition {
+                after: *i,
+                command: PlannedCommand::from(&transition_command(
+                    &work_dir, *i, transition, encoding, preset,
+                )),
+            })
+            .collect(),
+        concat_list: PlannedFile {
+            path: PathBuf::from(&concat_path),
+            contents: concat_list(clips.len(), &transitions),
+        },
+        concat: PlannedCommand::from(&concat_command(&concat_path, &concat_out)),
+        music,
+        clips: planned,
+        output: out_path,
+        duration,
+        work_dir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_handler::defs::TransitionKind;
+
+    #[test]
+    fn test_concat_list() {
+        let transition = Transition {
+            kind: TransitionKind::Crossfade,
+            duration: 0.5,
+        };
+        assert_eq!(
+            concat_list(3, &[(1, transition)]),
+            "file 'clip0.mp4'\nfile 'clip1.mp4'\nfile 'transition1.mp4'\nfile 'clip2.mp4'\n"
+        );
+    }
+
+    #[test]
+    fn test_planned_command() {
+        let command = concat_command("work/concat.txt", Path::new("out.mp4"));
+        let planned = PlannedCommand::from(&command);
+        assert_eq!(planned.argv[0], "ffmpeg");
+        assert_eq!(planned.argv.last().map(String::as_str), Some("out.mp4"));
+        assert_eq!(planned.filter, None);
+
+        let mut command = Command::new("ffmpeg");
+        command.arg("-filter_complex").arg("[0:v] null [outv]");
+        let planned = PlannedCommand::from(&command);
+        assert_eq!(planned.filter.as_deref(), Some("[0:v] null [outv]"));
+    }
+}
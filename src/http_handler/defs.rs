@@ -1,6 +1,14 @@
-use serde::{Deserialize, Serialize};
+use std::{fmt::Display, path::PathBuf, time::SystemTime};
 
-use crate::common::{ProbeResult, Rotation, Video};
+use serde::{Deserialize, Deserializer, Serialize, de};
+use serde_json::Value;
+
+use crate::{
+    common::{ProbeResult, Rotation, State, Video},
+    config::EncodingPreset,
+    journal::VideoFailure,
+    util::MyResult,
+};
 
 #[derive(Deserialize, Debug)]
 pub struct VideoMetadataEditReq {
@@ -16,13 +24,19 @@ pub struct RenameTagRequest {
 
 #[derive(Deserialize, Debug)]
 pub enum VideoSelectRequest {
+    Id(String),
+    Ids(Vec<String>),
+    /// from before videos had IDs
     Thumbnail(String),
+    /// from before videos had IDs
     Thumbnails(Vec<String>),
     Tag(String),
 }
 impl VideoSelectRequest {
     pub fn match_video(&self, video: &Video) -> bool {
         match self {
+            VideoSelectRequest::Id(id) => video.matches_id(id),
+            VideoSelectRequest::Ids(ids) => ids.iter().any(|id| video.matches_id(id)),
             VideoSelectRequest::Thumbnail(thumbnail_name) => {
                 video.thumbnail_name == *thumbnail_name
             }
@@ -33,28 +47,540 @@ impl VideoSelectRequest {
         }
     }
 }
+/// for the undo history
+impl Display for VideoSelectRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoSelectRequest::Id(id) | VideoSelectRequest::Thumbnail(id) => {
+                write!(f, "video {id}")
+            }
+            VideoSelectRequest::Ids(ids) | VideoSelectRequest::Thumbnails(ids) => {
+                write!(f, "{} videos", ids.len())
+            }
+            VideoSelectRequest::Tag(tag) => write!(f, "videos tagged {tag}"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StowRequest {
+    /// name of the stow target in the config
+    pub target: String,
+    pub videos: VideoSelectRequest,
+}
 
 #[derive(Serialize, Debug)]
 pub struct JsonError {
     pub error: String,
 }
 
+/// Some of the videos couldn't be changed. The others were, and `state` is how
+/// things are now.
+#[derive(Serialize, Debug)]
+pub struct PartialFailure<'a> {
+    pub error: String,
+    pub failed: Vec<VideoFailure>,
+    pub state: &'a State,
+}
+
+impl<'a> PartialFailure<'a> {
+    pub fn new(failed: Vec<VideoFailure>, state: &'a State) -> Self {
+        let mut error = format!(
+            "Couldn't change {} video{}:",
+            failed.len(),
+            if failed.len() == 1 { "" } else { "s" }
+        );
+        for failure in &failed {
+            error.push_str(&format!("\n{}: {}", failure.name, failure.error));
+        }
+        Self {
+            error,
+            failed,
+            state,
+        }
+    }
+}
+
+/// A request that didn't make sense. `error` sums up the problems for
+/// showing to people.
+#[derive(Serialize, Debug)]
+pub struct InvalidRequest {
+    pub error: String,
+    pub problems: Vec<Problem>,
+}
+
+impl InvalidRequest {
+    pub fn new(problems: Vec<Problem>) -> Self {
+        Self {
+            error: problems
+                .iter()
+                .map(|problem| match problem.field.as_str() {
+                    "" => problem.message.clone(),
+                    field => format!("{field}: {}", problem.message),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            problems,
+        }
+    }
+}
+
+/// Something wrong with one part of a request.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Problem {
+    /// where it is in the request, e.g. `clips[2].end`. empty if it's about
+    /// the whole thing
+    pub field: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct PreparePreviewReq {
     pub tag: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Clip {
     pub start: f64,
     pub end: f64,
-    pub thumbnail_name: String,
+    /// ID of the source video
+    #[serde(alias = "thumbnail_name")]
+    pub id: String,
     pub override_rotation: Option<Rotation>,
+    /// into the next clip. ignored on the last clip
+    #[serde(default)]
+    pub transition: Option<Transition>,
+    /// overrides the cook's `fit`
+    #[serde(default)]
+    pub fit: Option<Fit>,
+    #[serde(default)]
+    pub texts: Vec<TextOverlay>,
+    /// `volume`, `mute`, `fade_in` and `fade_out`
+    #[serde(flatten)]
+    pub audio: ClipAudio,
+    /// `speed` and `reverse`
+    #[serde(flatten)]
+    pub playback: Playback,
 }
 
-#[derive(Deserialize, Debug)]
+/// How fast and which way a clip plays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Playback {
+    /// from 0.25 to 4, 2 being twice as fast
+    pub speed: f64,
+    pub reverse: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            reverse: false,
+        }
+    }
+}
+
+/// What to do with a clip's audio.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ClipAudio {
+    /// 1 leaves it as is, 0.5 is half as loud
+    pub volume: f64,
+    /// replaces the audio with silence
+    pub mute: bool,
+    /// in seconds
+    pub fade_in: f64,
+    pub fade_out: f64,
+}
+
+impl Default for ClipAudio {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            mute: false,
+            fade_in: 0.0,
+            fade_out: 0.0,
+        }
+    }
+}
+
+/// Text drawn over a clip or title card.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct TextOverlay {
+    pub text: String,
+    /// seconds into the clip
+    pub start: f64,
+    /// until the clip ends if missing
+    pub end: Option<f64>,
+    pub position: TextPosition,
+    /// font size, as a fraction of the video's height
+    pub size: f64,
+    /// anything ffmpeg understands, e.g. `white` or `#ff8800`
+    pub color: String,
+}
+
+impl Default for TextOverlay {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            start: 0.0,
+            end: None,
+            position: TextPosition::Bottom,
+            size: 0.06,
+            color: String::from("white"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextPosition {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// A clip that's generated instead of cut from a video, with text on it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TitleCard {
+    /// in seconds
+    pub duration: f64,
+    #[serde(default)]
+    pub background: CardBackground,
+    #[serde(default)]
+    pub texts: Vec<TextOverlay>,
+    /// into the next clip
+    #[serde(default)]
+    pub transition: Option<Transition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum CardBackground {
+    /// anything ffmpeg understands, e.g. `black` or `#ff8800`
+    Color(String),
+    /// a blurred frame from a video, `time` seconds in
+    Frame { id: String, time: f64 },
+}
+
+impl Default for CardBackground {
+    fn default() -> Self {
+        CardBackground::Color(String::from("black"))
+    }
+}
+
+/// Either a clip or a title card. Title cards look like `{"card": {...}}`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CookReqItem {
+    Card { card: TitleCard },
+    Clip(Clip),
+}
+
+#[derive(Deserialize)]
+struct CardItem {
+    card: TitleCard,
+}
+
+impl CookReqItem {
+    /// Picks which it is by whether there's a `card`, so the error says what's
+    /// wrong with that one rather than that it's neither, and where.
+    pub fn from_value(value: Value) -> Result<Self, serde_path_to_error::Error<serde_json::Error>> {
+        if value.get("card").is_some() {
+            serde_path_to_error::deserialize(value).map(|CardItem { card }| Self::Card { card })
+        } else {
+            serde_path_to_error::deserialize(value).map(Self::Clip)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CookReqItem {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::from_value(Value::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionKind {
+    Crossfade,
+    FadeBlack,
+    Wipe,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    /// in seconds. the clips overlap by this much
+    pub duration: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CookReq {
-    pub clips: Vec<Clip>,
+    pub clips: Vec<CookReqItem>,
     pub encoding: ProbeResult,
     pub name: String,
+    /// `fit`, `music`, `loudness` and `preset`
+    #[serde(flatten)]
+    pub options: CookOptions,
+}
+
+/// Cook settings that apply to the whole video.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CookOptions {
+    /// for clips that don't have their own
+    pub fit: Fit,
+    /// played under the whole video
+    pub music: Option<Music>,
+    /// evens out how loud each clip is if set
+    pub loudness: Option<Loudness>,
+    /// how to encode it. the default preset if missing
+    pub preset: Option<PresetChoice>,
+}
+
+/// An edit saved on the server, so it can be picked up on another device and
+/// cooked again later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Project {
+    pub id: String,
+    pub created: SystemTime,
+    pub updated: SystemTime,
+    #[serde(flatten)]
+    pub data: ProjectData,
+}
+
+/// The parts of a [`Project`] that can be edited, which is also what
+/// `POST /projects` and `PUT /projects/{id}` take.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectData {
+    pub name: String,
+    #[serde(default)]
+    pub clips: Vec<CookReqItem>,
+    /// the output's size and stream settings. missing until it's picked
+    #[serde(default)]
+    pub encoding: Option<ProbeResult>,
+    /// file name in Downloads, without the `.mp4`
+    pub output_name: String,
+    #[serde(flatten)]
+    pub options: CookOptions,
+}
+
+impl Project {
+    /// What to cook for the project.
+    pub fn cook_req(&self) -> MyResult<CookReq> {
+        let Some(encoding) = &self.data.encoding else {
+            Err(format!(
+                "Pick a size for {} before cooking it.",
+                self.data.name
+            ))?
+        };
+        Ok(CookReq {
+            clips: self.data.clips.clone(),
+            encoding: encoding.clone(),
+            name: self.data.output_name.clone(),
+            options: self.data.options.clone(),
+        })
+    }
+}
+
+/// Either the name of a preset in the config, or the settings themselves.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum PresetChoice {
+    Named(String),
+    Custom(EncodingPreset),
+}
+
+/// EBU R128 targets for ffmpeg's `loudnorm`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Loudness {
+    /// integrated loudness in LUFS, from -70 to -5
+    pub target: f64,
+    /// max true peak in dBTP, from -9 to 0
+    pub true_peak: f64,
+    /// loudness range in LU, from 1 to 50
+    pub range: f64,
+}
+
+impl Default for Loudness {
+    fn default() -> Self {
+        Self {
+            target: -16.0,
+            true_peak: -1.5,
+            range: 11.0,
+        }
+    }
+}
+
+/// A background track, mixed in with the clips' audio.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Music {
+    pub source: MusicSource,
+    /// seconds into the track to start from
+    #[serde(default)]
+    pub start: f64,
+    /// how loud the track is, 1 being as is
+    #[serde(default = "default_music_volume")]
+    pub volume: f64,
+    /// starts the track over (from the very beginning) if it's shorter than
+    /// the video. otherwise the rest is silent
+    #[serde(default = "default_true", rename = "loop")]
+    pub repeat: bool,
+    /// seconds to fade out at the end of the video
+    #[serde(default = "default_music_fade_out")]
+    pub fade_out: f64,
+}
+
+fn default_music_volume() -> f64 {
+    0.3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_music_fade_out() -> f64 {
+    2.0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MusicSource {
+    /// the audio from a video in the library
+    Video { id: String },
+    /// an audio or video file in the config's `music_dir`
+    File { path: PathBuf },
+}
+
+/// What to do with clips whose aspect ratio doesn't match the video's.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Fit {
+    Blur(BlurFit),
+    Letterbox(LetterboxFit),
+    Crop(CropFit),
+}
+
+impl Default for Fit {
+    fn default() -> Self {
+        Fit::Blur(BlurFit::default())
+    }
+}
+
+/// Fills the bars with a blurred, zoomed in copy of the clip.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct BlurFit {
+    /// the copy is shrunk by this much before blurring, which is faster
+    pub downscale: u32,
+    /// blur sigma, as a fraction of the shrunk width
+    pub blur: f64,
+}
+
+impl Default for BlurFit {
+    fn default() -> Self {
+        Self {
+            downscale: 3,
+            blur: 0.1,
+        }
+    }
+}
+
+/// Bars in a solid colour.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LetterboxFit {
+    /// anything ffmpeg understands, e.g. `black` or `#ff8800`
+    pub color: String,
+}
+
+impl Default for LetterboxFit {
+    fn default() -> Self {
+        Self {
+            color: String::from("black"),
+        }
+    }
+}
+
+/// Zooms in to fill the frame, cutting off the sides or top and bottom.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CropFit {
+    /// which part to keep, from 0 (left/top) to 1 (right/bottom). anything
+    /// but 0.5 pans off centre
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Default for CropFit {
+    fn default() -> Self {
+        Self { x: 0.5, y: 0.5 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_project_round_trip() {
+        let data: ProjectData = serde_json::from_value(json!({
+            "name": "beach",
+            "output_name": "video-sort-beach",
+            "clips": [
+                {"card": {"duration": 2}},
+                {"id": "abc", "start": 1, "end": 3, "override_rotation": null, "speed": 2}
+            ],
+            "loudness": {},
+            "preset": "YouTube Shorts"
+        }))
+        .unwrap();
+        let project = Project {
+            id: String::from("p1"),
+            created: SystemTime::UNIX_EPOCH,
+            updated: SystemTime::UNIX_EPOCH,
+            data,
+        };
+        assert!(project.cook_req().is_err());
+
+        let saved = serde_json::to_string(&project).unwrap();
+        let mut loaded: Project = serde_json::from_str(&saved).unwrap();
+        assert_eq!(loaded.data.clips.len(), 2);
+        let CookReqItem::Clip(clip) = &loaded.data.clips[1] else {
+            panic!("expected a clip");
+        };
+        assert_eq!(clip.id, "abc");
+        assert_eq!(clip.playback.speed, 2.0);
+        assert!(matches!(
+            loaded.data.options.preset,
+            Some(PresetChoice::Named(ref name)) if name == "YouTube Shorts"
+        ));
+
+        loaded.data.encoding = Some(ProbeResult {
+            width: 1080,
+            height: 1920,
+            duration: 0.0,
+            rotation: Rotation::Unrotated,
+            pix_fmt: String::from("yuv420p"),
+            color_space: None,
+            color_transfer: None,
+            color_primaries: None,
+            bit_rate: 0,
+            audio: None,
+            codec: None,
+            profile: None,
+        });
+        let request = loaded.cook_req().unwrap();
+        assert_eq!(request.name, "video-sort-beach");
+        assert_eq!(request.options.loudness, Some(Loudness::default()));
+    }
 }
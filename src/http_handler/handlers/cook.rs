@@ -1,189 +1,825 @@
 use std::{
-    process::Stdio,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
 };
 
-use futures_util::TryStreamExt;
-use http_body_util::{BodyExt, StreamBody};
-use hyper::{
-    Response, StatusCode,
-    body::{Buf, Bytes, Frame},
-};
-use tokio::{
-    fs,
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
-    sync::{Semaphore, mpsc},
-};
-use tokio_stream::wrappers::ReceiverStream;
-use tokio_util::io::ReaderStream;
+use hyper::StatusCode;
+use serde_json::Value;
 
 use crate::{
-    common::{DIR_PATH, MAX_CONCURRENT_FFMPEG, SharedState},
-    fmt::faded,
+    common::{ProbeResult, Provenance, SharedState, SourceClip, State, Video},
+    config::{Config, EncodingPreset},
+    cook::{self, cook, output_path},
     http_handler::{
-        defs::CookReq,
-        make_filter::make_clip,
-        probe::defs::CookClip,
-        util::{CORS, MyResponse, Req},
+        defs::{
+            CardBackground, CookReq, CookReqItem, InvalidRequest, MusicSource, PresetChoice,
+            Problem, Project,
+        },
+        handlers::jobs::stream_log,
+        make_filter::{
+            check_audio, check_color, check_fit, check_loudness, check_music, check_playback,
+            check_text, edges_of,
+        },
+        probe::{
+            defs::{CardSource, CookCard, CookClip, CookItem, CookMusic},
+            probe_encoders,
+        },
+        util::{MyResponse, Req, build_json_error, build_json_response, read_json},
     },
-    util::BoxedError,
+    jobs::Job,
+    register::register_cooked,
+    util::MyResult,
 };
 
-pub async fn handle(req: Req, state: SharedState) -> MyResponse {
-    let request: CookReq = serde_json::from_reader(req.collect().await?.aggregate().reader())?;
-
-    let work_dir = format!(
-        "{DIR_PATH}/work/{}",
-        SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis()
-    );
-    fs::create_dir_all(&work_dir).await?;
-
-    eprintln!(
-        "{}",
-        faded(&format!(
-            "[cook] Generating {} clips...",
-            request.clips.len()
-        ))
-    );
-
-    let (tx, rx) = mpsc::channel::<std::io::Result<Bytes>>(100);
-    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FFMPEG));
-    let clips = {
-        let state = state.read().await;
-        request
-            .clips
-            .into_iter()
-            .filter_map(|clip| {
-                state
+/// Longest output name, in characters. Android chokes on file names much
+/// longer than this.
+const MAX_NAME_LEN: usize = 120;
+
+/// Probed durations get rounded, so clips can end a tiny bit past them.
+const DURATION_LEEWAY: f64 = 0.05;
+
+/// Smallest and largest output width or height. 7680 is 8K.
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 7680;
+
+fn invalid(status: StatusCode, problems: Vec<Problem>) -> MyResponse {
+    build_json_error(status, &InvalidRequest::new(problems))
+}
+
+/// Makes `name` safe to use as a file name in Downloads, so it can't have
+/// slashes or other characters Android doesn't allow, or start with a dot
+/// (e.g. `..`). Returns `None` if there's nothing left.
+fn sanitize_name(name: &str) -> Option<String> {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "/\\:*?\"<>|".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .take(MAX_NAME_LEN)
+        .collect::<String>();
+    let name = name
+        .trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// `start` to `end` has to be within a video that's `duration` seconds long.
+fn check_range(field: &str, start: f64, end: f64, duration: f64, problems: &mut Vec<Problem>) {
+    // written so NaN fails too
+    if !(start >= 0.0 && start < end) {
+        problems.push(Problem::new(
+            format!("{field}.start"),
+            format!("Has to be at least 0 and before the end ({end})."),
+        ));
+    } else if end > duration + DURATION_LEEWAY {
+        problems.push(Problem::new(
+            format!("{field}.end"),
+            format!("Goes past the end of the video ({duration}s)."),
+        ));
+    }
+}
+
+/// Adds a problem with `field` if `result` is an error.
+fn add_problem(field: impl Into<String>, result: MyResult<()>, problems: &mut Vec<Problem>) {
+    if let Err(err) = result {
+        problems.push(Problem::new(field, err.to_string()));
+    }
+}
+
+/// Finds the video that `field` refers to, which has to be in the library and
+/// not in the trash.
+fn find_source<'a>(
+    field: String,
+    id: &str,
+    state: &'a State,
+    problems: &mut Vec<Problem>,
+) -> Option<&'a Video> {
+    match state.find_video(id) {
+        None => problems.push(Problem::new(
+            field,
+            format!("There's no video with ID {id}."),
+        )),
+        Some(video) if video.trashed_at().is_some() => {
+            problems.push(Problem::new(field, format!("Video {id} is in the trash.")))
+        }
+        Some(video) => return Some(video),
+    }
+    None
+}
+
+/// Finds everything wrong with the request before any ffmpeg runs, and
+/// sanitizes its name. Whether another cook is making the same thing is left
+/// to [`Jobs::start`](crate::jobs::Jobs::start).
+fn validate(request: &mut CookReq, state: &State, music_dir: &Path) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    match sanitize_name(&request.name) {
+        Some(name) => {
+            let path = output_path(&name);
+            if path.exists()
+                && !state
                     .videos
                     .iter()
-                    .find(|video| video.thumbnail_name == clip.thumbnail_name)
-                    .and_then(|video| {
-                        video.probe.as_ref().map(|probe| CookClip {
-                            video_path: video.current_loc().to_path_buf(),
-                            probe: probe.clone(),
-                            start: clip.start,
-                            end: clip.end,
-                            override_rotation: clip.override_rotation.clone(),
-                        })
-                    })
-            })
-            .collect::<Vec<_>>()
+                    .any(|video| *video.current_loc() == path && video.cooked_from.is_some())
+            {
+                // cooking again over an old cook is fine
+                problems.push(Problem::new(
+                    "name",
+                    format!("{name}.mp4 is already in Downloads, and it wasn't cooked here."),
+                ));
+            }
+            request.name = name;
+        }
+        None => problems.push(Problem::new(
+            "name",
+            "Needs some characters that can go in a file name.",
+        )),
+    }
+
+    for (field, size) in [
+        ("encoding.width", request.encoding.width),
+        ("encoding.height", request.encoding.height),
+    ] {
+        if !(MIN_SIZE..=MAX_SIZE).contains(&size) || size % 2 != 0 {
+            problems.push(Problem::new(
+                field,
+                format!("Has to be an even number from {MIN_SIZE} to {MAX_SIZE}."),
+            ));
+        }
+    }
+    add_problem("fit", check_fit(&request.options.fit), &mut problems);
+    if let Some(loudness) = &request.options.loudness {
+        add_problem("loudness", check_loudness(loudness), &mut problems);
+    }
+
+    if request.clips.is_empty() {
+        problems.push(Problem::new("clips", "Add at least one clip."));
+    }
+    // how long each item is and its transition, for checking transitions
+    let mut timings = Vec::new();
+    for (i, item) in request.clips.iter().enumerate() {
+        match item {
+            CookReqItem::Clip(clip) => {
+                let field = format!("clips[{i}]");
+                let speed = check_playback(&clip.playback);
+                let speed_ok = speed.is_ok();
+                add_problem(format!("{field}.speed"), speed, &mut problems);
+                let duration = (clip.end - clip.start) / clip.playback.speed;
+                timings.push((duration, clip.transition));
+                if let Some(fit) = &clip.fit {
+                    add_problem(format!("{field}.fit"), check_fit(fit), &mut problems);
+                }
+                let Some(video) =
+                    find_source(format!("{field}.id"), &clip.id, state, &mut problems)
+                else {
+                    continue;
+                };
+                let Some(probe) = &video.probe else {
+                    problems.push(Problem::new(
+                        format!("{field}.id"),
+                        format!("Video {} hasn't been probed yet.", clip.id),
+                    ));
+                    continue;
+                };
+                let before = problems.len();
+                check_range(&field, clip.start, clip.end, probe.duration, &mut problems);
+                // the rest depend on how long the clip is
+                if problems.len() > before || !speed_ok {
+                    continue;
+                }
+                add_problem(
+                    format!("{field}.audio"),
+                    check_audio(&clip.audio, duration),
+                    &mut problems,
+                );
+                for (j, text) in clip.texts.iter().enumerate() {
+                    add_problem(
+                        format!("{field}.texts[{j}]"),
+                        check_text(text, duration),
+                        &mut problems,
+                    );
+                }
+            }
+            CookReqItem::Card { card } => {
+                let field = format!("clips[{i}].card");
+                timings.push((card.duration, card.transition));
+                if !(card.duration > 0.0 && card.duration.is_finite()) {
+                    problems.push(Problem::new(
+                        format!("{field}.duration"),
+                        "Has to be more than 0.",
+                    ));
+                    continue;
+                }
+                match &card.background {
+                    CardBackground::Color(color) => add_problem(
+                        format!("{field}.background"),
+                        check_color(color),
+                        &mut problems,
+                    ),
+                    CardBackground::Frame { id, time } => {
+                        let field = format!("{field}.background");
+                        if let Some(video) = find_source(field.clone(), id, state, &mut problems) {
+                            let duration = video
+                                .probe
+                                .as_ref()
+                                .map_or(f64::INFINITY, |probe| probe.duration);
+                            if !(*time >= 0.0 && *time <= duration) {
+                                problems.push(Problem::new(
+                                    field,
+                                    format!("{time}s isn't within the video."),
+                                ));
+                            }
+                        }
+                    }
+                }
+                for (j, text) in card.texts.iter().enumerate() {
+                    add_problem(
+                        format!("{field}.texts[{j}]"),
+                        check_text(text, card.duration),
+                        &mut problems,
+                    );
+                }
+            }
+        }
+    }
+    // only worth checking once each item's length makes sense
+    if problems.is_empty()
+        && let Err((i, message)) = edges_of(&timings)
+    {
+        problems.push(Problem::new(format!("clips[{i}].transition"), message));
+    }
+
+    if let Some(music) = &mut request.options.music {
+        match &mut music.source {
+            MusicSource::Video { id } => {
+                find_source(String::from("music.source"), id, state, &mut problems);
+            }
+            MusicSource::File { path } => match music_file(music_dir, path) {
+                Some(found) => *path = found,
+                None => problems.push(Problem::new(
+                    "music.source",
+                    format!(
+                        "{} isn't a file in the music folder ({}).",
+                        path.display(),
+                        music_dir.display()
+                    ),
+                )),
+            },
+        }
+        add_problem(
+            "music",
+            check_music(music.start, music.volume, music.fade_out),
+            &mut problems,
+        );
+    }
+    problems
+}
+
+/// Where `path` is if it's a file in `music_dir`, so requests can't read
+/// anything else on the server. Relative paths are from `music_dir`.
+fn music_file(music_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let music_dir = std::fs::canonicalize(music_dir).ok()?;
+    // resolves `..` and symlinks that lead out of it
+    let found = std::fs::canonicalize(music_dir.join(path)).ok()?;
+    (found.starts_with(&music_dir) && found.is_file()).then_some(found)
+}
+
+/// Turns a validated request into what gets cooked, and notes where each clip
+/// came from.
+fn prepare(
+    request: &mut CookReq,
+    state: &State,
+) -> MyResult<(Vec<CookItem>, Option<CookMusic>, Vec<SourceClip>)> {
+    let music = match request.options.music.take() {
+        Some(music) => Some(CookMusic {
+            path: match music.source {
+                MusicSource::Video { id } => state
+                    .find_video(&id)
+                    .ok_or("music went missing")?
+                    .current_loc()
+                    .to_path_buf(),
+                MusicSource::File { path } => path,
+            },
+            start: music.start,
+            volume: music.volume,
+            repeat: music.repeat,
+            fade_out: music.fade_out,
+        }),
+        None => None,
     };
-    let clip_count = clips.len();
-    let handles = clips
+    let mut sources = Vec::new();
+    let clips = std::mem::take(&mut request.clips)
         .into_iter()
-        .enumerate()
-        .map(|(i, clip)| {
-            let semaphore = semaphore.clone();
-            let tx = tx.clone();
-            let work_dir = work_dir.clone();
-            let encoding = request.encoding.clone();
-            tokio::spawn(async move {
-                let _permit = semaphore.acquire_owned().await?;
-                let mut command = make_clip(&clip, &encoding, &format!("{work_dir}/clip{i}.mp4"))?;
-                eprintln!("{}", faded(&format!("[cook.{i}] {command:?}")));
-                command.stderr(Stdio::piped());
-                let mut child = command.spawn()?;
-                let mut reader =
-                    BufReader::new(child.stderr.take().ok_or("no stderr??")?).split(b'\r');
-                while let Some(line) = reader.next_segment().await.transpose() {
-                    tx.send(line.map(|line| {
-                        Bytes::from(format!("[{i}] {}\n", String::from_utf8_lossy(&line)))
+        .map(|item| {
+            Ok(match item {
+                CookReqItem::Clip(clip) => {
+                    let video = state.find_video(&clip.id).ok_or("video went missing")?;
+                    sources.push(SourceClip {
+                        id: video.id.clone(),
+                        thumbnail_name: video.thumbnail_name.clone(),
+                        start: clip.start,
+                        end: clip.end,
+                    });
+                    CookItem::Clip(Box::new(CookClip {
+                        // so clips asked for by thumbnail name share the cache
+                        id: video.id.clone(),
+                        video_path: video.current_loc().to_path_buf(),
+                        probe: video.probe.clone().ok_or("probe went missing")?,
+                        start: clip.start,
+                        end: clip.end,
+                        override_rotation: clip.override_rotation,
+                        transition: clip.transition,
+                        fit: clip.fit.unwrap_or_else(|| request.options.fit.clone()),
+                        texts: clip.texts,
+                        audio: clip.audio,
+                        loudness: request.options.loudness.clone(),
+                        playback: clip.playback,
                     }))
-                    .await?;
-                }
-                match child.wait().await {
-                    Ok(status) if status.success() => {}
-                    Ok(status) => Err(format!("[cook] ffmpeg failed with status: {status}"))?,
-                    Err(err) => Err(format!("[cook] ffmpeg failed to run: {err}"))?,
                 }
-                Ok::<(), BoxedError>(())
+                CookReqItem::Card { card } => CookItem::Card(CookCard {
+                    duration: card.duration,
+                    background: match card.background {
+                        CardBackground::Color(color) => CardSource::Color(color),
+                        CardBackground::Frame { id, time } => CardSource::Frame {
+                            video_path: state
+                                .find_video(&id)
+                                .ok_or("video went missing")?
+                                .current_loc()
+                                .to_path_buf(),
+                            time,
+                        },
+                    },
+                    texts: card.texts,
+                    transition: card.transition,
+                }),
             })
         })
-        .collect::<Vec<_>>();
-
-    let concat_path = format!("{work_dir}/concat.txt");
-    fs::write(
-        &concat_path,
-        // note: without -safe 0, ffmpeg concat will reject file paths
-        // with a . in it
-        (0..clip_count)
-            .map(|i| format!("file 'clip{i}.mp4'\n"))
-            .collect::<String>(),
-    )
-    .await?;
-    let out_path = format!("./storage/downloads/{}.mp4", request.name);
-    let mut command = Command::new("ffmpeg");
-    command.arg("-v").arg("error");
-    command.arg("-stats");
-    command.arg("-f").arg("concat");
-    command.arg("-i").arg(&concat_path);
-    command.arg("-c").arg("copy");
-    command.arg("-y");
-    command.arg(&out_path);
-    command.stderr(Stdio::piped());
-
-    tokio::spawn(async move {
-        let mut failed = false;
-        for (i, handle) in handles.into_iter().enumerate() {
-            match handle.await {
-                Err(err) => {
-                    eprintln!("[cook.{i}] Unexpected join error in clip:\n{err:?}");
-                    failed = true;
+        .collect::<MyResult<Vec<_>>>()?;
+    Ok((clips, music, sources))
+}
+
+/// A request that's been checked and is ready to cook.
+pub struct Prepared {
+    pub name: String,
+    /// ID of the saved project it's from
+    pub project: Option<String>,
+    pub clips: Vec<CookItem>,
+    pub music: Option<CookMusic>,
+    pub encoding: ProbeResult,
+    pub preset: EncodingPreset,
+    /// for [`Provenance`]
+    pub sources: Vec<SourceClip>,
+}
+
+/// Where in the request a deserializing error is, under `prefix`.
+fn problem_at(prefix: &str, err: serde_path_to_error::Error<serde_json::Error>) -> Problem {
+    let path = err.path().to_string();
+    let field = match (prefix, path.as_str()) {
+        (_, ".") => prefix.to_string(),
+        ("", _) => path,
+        _ => format!("{prefix}.{path}"),
+    };
+    Problem::new(field, err.into_inner().to_string())
+}
+
+/// Deserializes a [`CookReq`] one clip at a time, so a bad clip says which it
+/// is.
+fn parse_request(mut body: Value) -> Result<CookReq, Problem> {
+    let items = match body.get_mut("clips") {
+        Some(Value::Array(items)) => std::mem::take(items),
+        _ => Vec::new(),
+    };
+    let mut request: CookReq =
+        serde_path_to_error::deserialize(body).map_err(|err| problem_at("", err))?;
+    request.clips = items
+        .into_iter()
+        .enumerate()
+        .map(|(i, item)| {
+            CookReqItem::from_value(item).map_err(|err| problem_at(&format!("clips[{i}]"), err))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(request)
+}
+
+/// Checks a [`CookReq`], or `{"project": "<id>"}` to cook a saved project, and
+/// gets it ready to cook. If it doesn't make sense, the error is the status to
+/// respond with and everything that's wrong.
+pub async fn check(
+    body: Value,
+    state: &SharedState,
+) -> MyResult<Result<Prepared, (StatusCode, Vec<Problem>)>> {
+    let project = body
+        .get("project")
+        .and_then(Value::as_str)
+        .map(String::from);
+    let mut request = match &project {
+        Some(id) => {
+            let request = state.read().await.find_project(id).map(Project::cook_req);
+            match request {
+                Some(Ok(request)) => request,
+                Some(Err(err)) => {
+                    return Ok(Err((
+                        StatusCode::BAD_REQUEST,
+                        vec![Problem::new("encoding", err.to_string())],
+                    )));
                 }
-                Ok(Err(err)) => {
-                    eprintln!("[cook.{i}] Unexpected error in clip:\n{err:?}");
-                    failed = true;
+                None => {
+                    return Ok(Err((
+                        StatusCode::NOT_FOUND,
+                        vec![Problem::new(
+                            "project",
+                            format!("There's no project with ID {id}."),
+                        )],
+                    )));
                 }
-                Ok(Ok(_)) => {}
             }
         }
-        if failed {
-            eprintln!("{}", faded(&format!("[cook] Clip generation failed.")));
-            return;
-        }
-        eprintln!("{}", faded(&format!("[cook] Clip generation complete.")));
-
-        eprintln!("{}", faded(&format!("[cook] {command:?}")));
-        let mut child = match command.spawn() {
-            Ok(child) => child,
-            Err(err) => {
-                eprintln!("[cook] Spawning concat failed: {err:?}.");
-                return;
+        None => match parse_request(body) {
+            Ok(request) => request,
+            Err(problem) => return Ok(Err((StatusCode::BAD_REQUEST, vec![problem]))),
+        },
+    };
+
+    let mut config = Config::load().await?;
+    let preset = match request.options.preset.take() {
+        None => EncodingPreset::default(),
+        Some(PresetChoice::Custom(preset)) => preset,
+        Some(PresetChoice::Named(name)) => match config.presets.remove(&name) {
+            Some(preset) => preset,
+            None => {
+                return Ok(Err((
+                    StatusCode::BAD_REQUEST,
+                    vec![Problem::new(
+                        "preset",
+                        format!("There's no preset named {name}."),
+                    )],
+                )));
             }
+        },
+    };
+    if let Err(err) = preset.check(&probe_encoders().await?) {
+        return Ok(Err((
+            StatusCode::BAD_REQUEST,
+            vec![Problem::new("preset", err.to_string())],
+        )));
+    }
+    if let Some(width) = preset.width {
+        request.encoding.width = width;
+    }
+    if let Some(height) = preset.height {
+        request.encoding.height = height;
+    }
+
+    let state_ref = state.read().await;
+    let problems = validate(&mut request, &state_ref, &config.music_dir);
+    if !problems.is_empty() {
+        return Ok(Err((StatusCode::BAD_REQUEST, problems)));
+    }
+    let (clips, music, sources) = prepare(&mut request, &state_ref)?;
+    Ok(Ok(Prepared {
+        name: request.name,
+        project,
+        clips,
+        music,
+        encoding: request.encoding,
+        preset,
+        sources,
+    }))
+}
+
+/// Cooks it as `job`, then adds the video to the library.
+pub async fn run(job: Arc<Job>, prepared: Prepared, state: SharedState) {
+    let Prepared {
+        name,
+        project,
+        clips,
+        music,
+        encoding,
+        preset,
+        sources,
+    } = prepared;
+    let result = cook(job.clone(), clips, music, encoding, preset, name).await;
+    if let Ok(path) = &result {
+        let provenance = Provenance {
+            job: job.id.clone(),
+            project,
+            sources,
         };
-        let Some(stderr) = child.stderr.take() else {
-            eprintln!("[cook] concat child doesnt have stderr??");
-            return;
+        // the cook still worked even if this didn't
+        if let Err(err) = register_cooked(path.clone(), provenance, &state).await {
+            job.log(&format!(
+                "[cook] Couldn't add the video to the library: {err}"
+            ));
+        }
+    }
+    if let Err(err) = state.jobs().finish(&job, result).await {
+        eprintln!("[cook] Failed to save job {}: {err:?}", job.id);
+    }
+}
+
+/// Starts cooking in the background as a job and streams its log. The job ID
+/// is in the `X-Job-Id` header, so the client can reattach with
+/// `/jobs/{id}/log` if it gets disconnected.
+///
+/// Takes what [`check`] does. Requests that don't make sense get a 4xx with an
+/// [`InvalidRequest`].
+pub async fn handle(req: Req, state: SharedState) -> MyResponse {
+    let body = match read_json(req).await? {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let prepared = match check(body, &state).await? {
+        Ok(prepared) => prepared,
+        Err((status, problems)) => return invalid(status, problems),
+    };
+    let Some(job) = state
+        .jobs()
+        .start(prepared.name.clone(), prepared.project.clone())
+        .await?
+    else {
+        return invalid(
+            StatusCode::CONFLICT,
+            vec![Problem::new(
+                "name",
+                format!("Another cook is already making {}.mp4.", prepared.name),
+            )],
+        );
+    };
+    let response = stream_log(&job);
+    tokio::spawn(run(job, prepared, state));
+    response
+}
+
+/// Returns the [`CookPlan`](cook::CookPlan) for what [`handle`] would cook, without cooking
+/// it. With `?measure`, clips get probed and measured first.
+pub async fn plan(req: Req, state: SharedState) -> MyResponse {
+    let measure = req
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|param| param == "measure"));
+    let body = match read_json(req).await? {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let Prepared {
+        name,
+        clips,
+        music,
+        encoding,
+        preset,
+        ..
+    } = match check(body, &state).await? {
+        Ok(prepared) => prepared,
+        Err((status, problems)) => return invalid(status, problems),
+    };
+    match cook::plan(clips, music, &encoding, &preset, &name, measure).await {
+        Ok(plan) => build_json_response(&plan),
+        // e.g. a transition that's longer than its clip
+        Err(err) => invalid(
+            StatusCode::BAD_REQUEST,
+            vec![Problem::new("", err.to_string())],
+        ),
+    }
+}
+
+/// The encoding presets in the config.
+pub async fn presets() -> MyResponse {
+    build_json_response(&Config::load().await?.presets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{ProbeResult, Rotation, Video},
+        http_handler::defs::Loudness,
+    };
+    use serde_json::json;
+    use std::{path::PathBuf, time::SystemTime};
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("beach day").as_deref(), Some("beach day"));
+        assert_eq!(sanitize_name("../a/b:c").as_deref(), Some("_a_b_c"));
+        assert_eq!(sanitize_name(" .hidden ").as_deref(), Some("hidden"));
+        assert_eq!(sanitize_name(&"a".repeat(200)).unwrap().len(), MAX_NAME_LEN);
+        assert_eq!(sanitize_name(".. "), None);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let encoding = json!({
+            "width": 1080, "height": 1920, "duration": 0, "rotation": "Unrotated",
+            "pix_fmt": "yuv420p", "color_space": null, "color_transfer": null,
+            "color_primaries": null, "bit_rate": 0, "audio": null
+        });
+        let problem = |clips: Value| {
+            parse_request(json!({"name": "a", "encoding": encoding, "clips": clips})).unwrap_err()
         };
+        let clip = json!({"id": "abc", "start": 0, "end": 1, "override_rotation": null});
 
-        let mut reader_stream = ReaderStream::new(stderr);
-        while let Some(chunk) = tokio_stream::StreamExt::next(&mut reader_stream).await {
-            // Send the raw chunk directly to the channel
-            if let Err(_) = tx.send(chunk).await {
-                break;
-            }
-        }
-        match child.wait().await {
-            Ok(status) if status.success() => eprintln!("[cook] Bon appetit! {out_path}"),
-            Ok(status) => eprintln!("[cook] ffmpeg failed with status: {status}"),
-            Err(err) => eprintln!("[cook] ffmpeg failed to run: {err}"),
-        }
-        if let Err(err) = fs::remove_dir_all(work_dir).await {
-            eprintln!("[cook] failed to clean up workspace: {err}")
-        }
-    });
+        let bad = problem(json!([clip, {"id": "abc", "start": "0", "end": 1}]));
+        assert_eq!(bad.field, "clips[1].start");
+        assert!(bad.message.contains("expected f64"), "{}", bad.message);
+        let bad =
+            problem(json!([clip, clip, {"card": {"duration": 1, "texts": [{"size": "big"}]}}]));
+        assert_eq!(bad.field, "clips[2].card.texts[0].size");
+        let bad = problem(json!([{"start": 0, "end": 1}]));
+        assert_eq!(bad.field, "clips[0]");
+        assert!(bad.message.contains("`id`"), "{}", bad.message);
+        assert_eq!(
+            parse_request(json!({"name": "a", "encoding": {}, "clips": []}))
+                .unwrap_err()
+                .field,
+            "encoding"
+        );
+
+        let request = parse_request(json!({
+            "name": "a", "encoding": encoding, "clips": [clip, {"card": {"duration": 1}}]
+        }))
+        .unwrap();
+        assert!(matches!(request.clips[1], CookReqItem::Card { .. }));
+    }
 
-    let stream = ReceiverStream::new(rx);
-    let stream_body = StreamBody::new(stream.map_ok(Frame::data));
-    let boxed_body = BodyExt::boxed(stream_body);
+    #[tokio::test]
+    async fn test_validate() {
+        let dir = tempfile::tempdir().unwrap();
+        let probed = |id: &str, path: PathBuf| {
+            let mut video = Video::new(
+                String::from(id),
+                path,
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                5,
+            );
+            video.probe = Some(ProbeResult {
+                width: 1920,
+                height: 1080,
+                duration: 10.0,
+                rotation: Rotation::Unrotated,
+                pix_fmt: String::from("yuv420p"),
+                color_space: None,
+                color_transfer: None,
+                color_primaries: None,
+                bit_rate: 0,
+                audio: None,
+                codec: None,
+                profile: None,
+            });
+            video
+        };
+        let music_dir = dir.path().join("music");
+        std::fs::create_dir(&music_dir).unwrap();
+        std::fs::write(music_dir.join("song.mp3"), b"song").unwrap();
+        let old_path = dir.path().join("old.mp4");
+        std::fs::write(&old_path, b"video").unwrap();
+        let mut old = probed("old", old_path);
+        old.trash(dir.path()).await.unwrap();
+        let state = State {
+            videos: vec![probed("abc", "abc.mp4".into()), old],
+            ..State::default()
+        };
+        let encoding = json!({
+            "width": 1080, "height": 1920, "duration": 0, "rotation": "Unrotated",
+            "pix_fmt": "yuv420p", "color_space": null, "color_transfer": null,
+            "color_primaries": null, "bit_rate": 0, "audio": null
+        });
+        let request = |name: &str, clips: Value| -> CookReq {
+            serde_json::from_value(json!({
+                "name": name,
+                "encoding": encoding,
+                "clips": clips,
+            }))
+            .unwrap()
+        };
+        let fields = |problems: Vec<Problem>| -> Vec<String> {
+            problems.into_iter().map(|problem| problem.field).collect()
+        };
+
+        let mut ok = request(
+            "validate test/ok",
+            json!([
+                {"id": "abc", "start": 1, "end": 10.01, "override_rotation": null},
+                {"card": {"duration": 2}}
+            ]),
+        );
+        assert!(validate(&mut ok, &state, &music_dir).is_empty());
+        assert_eq!(ok.name, "validate test_ok");
+
+        let mut bad = request(
+            "..",
+            json!([
+                {"id": "abc", "start": 5, "end": 4, "override_rotation": null},
+                {"id": "abc", "start": 5, "end": 11, "override_rotation": null},
+                {"id": "nope", "start": 0, "end": 1, "override_rotation": null},
+                {"card": {"duration": 0}}
+            ]),
+        );
+        assert_eq!(
+            fields(validate(&mut bad, &state, &music_dir)),
+            [
+                "name",
+                "clips[0].start",
+                "clips[1].end",
+                "clips[2].id",
+                "clips[3].card.duration"
+            ]
+        );
+        assert_eq!(
+            fields(validate(&mut request("a", json!([])), &state, &music_dir)),
+            ["clips"]
+        );
 
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/plain; charset=utf-8")
-        .header("Access-Control-Allow-Origin", CORS)
-        .body(boxed_body)?)
+        // what would otherwise fail partway through cooking
+        let mut bad = request(
+            "filters",
+            json!([
+                {"id": "abc", "start": 0, "end": 4, "override_rotation": null, "speed": 8},
+                {"id": "abc", "start": 0, "end": 4, "override_rotation": null,
+                    "fade_in": 3, "fade_out": 3},
+                {"id": "abc", "start": 0, "end": 4, "override_rotation": null,
+                    "fit": {"Crop": {"x": 2, "y": 0}}, "texts": [{"text": " "}]},
+                {"card": {"duration": 1, "background": {"Color": "red:x"}}},
+                {"id": "old", "start": 0, "end": 1, "override_rotation": null}
+            ]),
+        );
+        bad.encoding.width = 0;
+        bad.encoding.height = 1081;
+        bad.options.loudness = Some(Loudness {
+            target: 0.0,
+            ..Loudness::default()
+        });
+        assert_eq!(
+            fields(validate(&mut bad, &state, &music_dir)),
+            [
+                "encoding.width",
+                "encoding.height",
+                "loudness",
+                "clips[0].speed",
+                "clips[1].audio",
+                "clips[2].fit",
+                "clips[2].texts[0]",
+                "clips[3].card.background",
+                "clips[4].id"
+            ]
+        );
+
+        let mut bad = request(
+            "transition",
+            json!([
+                {"id": "abc", "start": 0, "end": 1, "override_rotation": null,
+                    "transition": {"kind": "Crossfade", "duration": 2}},
+                {"card": {"duration": 2}}
+            ]),
+        );
+        assert_eq!(
+            fields(validate(&mut bad, &state, &music_dir)),
+            ["clips[0].transition"]
+        );
+
+        // music files have to be in the music folder
+        let outside = dir.path().join("outside.mp3");
+        std::fs::write(&outside, b"secret").unwrap();
+        std::os::unix::fs::symlink(&outside, music_dir.join("link.mp3")).unwrap();
+        let music = |path: &Path| {
+            let mut with_music = request(
+                "music",
+                json!([{"id": "abc", "start": 0, "end": 1, "override_rotation": null}]),
+            );
+            with_music.options.music =
+                Some(serde_json::from_value(json!({"source": {"File": {"path": path}}})).unwrap());
+            let problems = fields(validate(&mut with_music, &state, &music_dir));
+            (problems, with_music.options.music.unwrap().source)
+        };
+        let (problems, source) = music(Path::new("song.mp3"));
+        assert!(problems.is_empty());
+        assert!(matches!(
+            source,
+            MusicSource::File { path } if path == music_dir.canonicalize().unwrap().join("song.mp3")
+        ));
+        assert!(music(&music_dir.join("song.mp3")).0.is_empty());
+        for path in [
+            outside.as_path(),
+            Path::new("../outside.mp3"),
+            Path::new("link.mp3"),
+            Path::new("missing.mp3"),
+            Path::new("."),
+        ] {
+            assert_eq!(music(path).0, ["music.source"], "{}", path.display());
+        }
+
+        // the cache is keyed by ID, so clips asked for by thumbnail name get it
+        let mut by_name = request(
+            "by name",
+            json!([{"id": "abc.jpg", "start": 1, "end": 2, "override_rotation": null}]),
+        );
+        let (clips, _, _) = prepare(&mut by_name, &state).unwrap();
+        let CookItem::Clip(clip) = &clips[0] else {
+            panic!("expected a clip");
+        };
+        assert_eq!(clip.id, "abc");
+    }
 }
@@ -1,46 +1,38 @@
-use std::io::ErrorKind;
-
+use futures_util::FutureExt;
 use http_body_util::BodyExt;
 use hyper::body::Buf;
 use tokio::fs;
 
 use crate::{
-    common::{DIR_PATH, SharedState, save_state},
+    common::SharedState,
     http_handler::{
         defs::VideoSelectRequest,
-        util::{MyResponse, Req, build_json_response},
+        util::{MyResponse, Req, build_state_response},
     },
+    trash::trash_dir,
 };
 
+/// Moves the videos to the trash. They get deleted for real once they've been
+/// there long enough, or when the trash is emptied.
 pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     let request: VideoSelectRequest =
         serde_json::from_reader(req.collect().await?.aggregate().reader())?;
-    let deleted_videos = {
-        let mut state = state.write().await;
-        let (deleted, remaining) = state
-            .videos
-            .drain(..)
-            .partition(|video| request.match_video(video));
-        state.videos = remaining;
-        deleted
-    };
-    if !deleted_videos.is_empty() {
-        for video in &deleted_videos {
-            // remove preview video
-            fs::remove_file(&format!("{DIR_PATH}/thumbs/{}.mp4", video.thumbnail_name))
-                .await
-                .or_else(|err| {
-                    if err.kind() == ErrorKind::NotFound {
-                        Ok(())
-                    } else {
-                        Err(err)
-                    }
-                })?;
-            fs::remove_file(&format!("{DIR_PATH}/thumbs/{}", video.thumbnail_name)).await?;
-            fs::remove_file(&video.current_loc()).await?;
-            println!("D {:?}", video.display_name());
-        }
-        save_state(&*state.read().await).await?;
-    }
-    build_json_response(&*state.read().await)
+    let trash_dir = trash_dir();
+    fs::create_dir_all(&trash_dir).await?;
+    let failed = state
+        .change_videos(
+            format!("Delete {request}"),
+            |video| request.match_video(video) && video.trashed_at().is_none(),
+            |video| {
+                let trash_dir = trash_dir.clone();
+                async move {
+                    video.trash(&trash_dir).await?;
+                    println!("T {:?}", video.display_name());
+                    Ok(())
+                }
+                .boxed()
+            },
+        )
+        .await?;
+    build_state_response(&state, failed).await
 }
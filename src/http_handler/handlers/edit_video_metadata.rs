@@ -2,11 +2,12 @@ use http_body_util::BodyExt;
 use hyper::body::Buf;
 
 use crate::{
-    common::{SharedState, save_state},
+    common::SharedState,
     http_handler::{
         defs::{JsonError, VideoMetadataEditReq},
         util::{MyResponse, Req, build_json_response},
     },
+    journal::Operation,
 };
 
 pub enum ReqType {
@@ -18,11 +19,16 @@ pub enum ReqType {
 pub async fn handle(req: Req, state: SharedState, req_type: ReqType) -> MyResponse {
     let request: VideoMetadataEditReq =
         serde_json::from_reader(req.collect().await?.aggregate().reader())?;
-    let success = {
+    let mut operation = Operation::new(match req_type {
+        ReqType::Add => format!("Add tag {} to {}", request.tag_or_note, request.target),
+        ReqType::Remove => format!("Remove tag {} from {}", request.tag_or_note, request.target),
+        ReqType::EditNote => format!("Edit note of {}", request.target),
+    });
+    {
         let mut state = state.write().await;
-        let mut success = false;
         for video in &mut state.videos {
             if request.target.match_video(video) {
+                let before = video.clone();
                 match req_type {
                     ReqType::Add => {
                         video.tags.insert(request.tag_or_note.clone());
@@ -32,20 +38,17 @@ pub async fn handle(req: Req, state: SharedState, req_type: ReqType) -> MyRespon
                     }
                     ReqType::EditNote => video.note = request.tag_or_note.clone(),
                 }
-                success = true;
+                operation.record(Some(before), Some(video.clone()));
             }
         }
-        success
-    };
-    if success {
-        save_state(&*state.read().await).await?;
+    }
+    if !operation.changes.is_empty() {
+        state.save_videos(&operation.ids()).await?;
+        state.record(operation).await?;
         build_json_response(&*state.read().await)
     } else {
         build_json_response(&JsonError {
-            error: format!(
-                "Unable to find video by thumbnail name {:?}",
-                request.target
-            ),
+            error: format!("Unable to find video by {:?}", request.target),
         })
     }
 }
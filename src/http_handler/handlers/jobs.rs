@@ -0,0 +1,100 @@
+use futures_util::TryStreamExt;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::{
+    Response, StatusCode,
+    body::{Bytes, Frame},
+};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    common::SharedState,
+    http_handler::{
+        defs::JsonError,
+        util::{CORS, MyResponse, build_json_error, build_json_response, build_text_response},
+    },
+    jobs::Job,
+};
+
+/// Job logs are one JSON event per line.
+const NDJSON: &str = "application/x-ndjson";
+
+/// Streams the job's log from the start, then follows it until the job ends.
+pub fn stream_log(job: &Job) -> MyResponse {
+    let (so_far, receiver) = job.attach();
+    let (tx, rx) = mpsc::channel::<std::io::Result<Bytes>>(100);
+    tokio::spawn(async move {
+        if tx.send(Ok(so_far)).await.is_err() {
+            return;
+        }
+        let Some(mut receiver) = receiver else {
+            return;
+        };
+        loop {
+            let chunk = match receiver.recv().await {
+                Ok(chunk) => chunk,
+                // too slow to keep up, so some progress lines got skipped
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            if tx.send(Ok(chunk)).await.is_err() {
+                break;
+            }
+        }
+    });
+    let stream_body = StreamBody::new(ReceiverStream::new(rx).map_ok(Frame::data));
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", NDJSON)
+        .header("Access-Control-Allow-Origin", CORS)
+        .header("Access-Control-Expose-Headers", "X-Job-Id")
+        .header("X-Job-Id", &job.id)
+        .body(BodyExt::boxed(stream_body))?)
+}
+
+fn not_found(id: &str) -> MyResponse {
+    build_text_response(StatusCode::NOT_FOUND, format!("No job with ID {id}."))
+}
+
+pub async fn list(state: SharedState) -> MyResponse {
+    build_json_response(&state.jobs().list().await?)
+}
+
+pub async fn get(state: SharedState, id: &str) -> MyResponse {
+    match state.jobs().get(id).await? {
+        Some(record) => build_json_response(&record),
+        None => not_found(id),
+    }
+}
+
+/// Follows a running job's log, or returns the whole log of a finished one.
+pub async fn log(state: SharedState, id: &str) -> MyResponse {
+    if let Some(job) = state.jobs().get_running(id) {
+        return stream_log(&job);
+    }
+    match state.jobs().saved_log(id).await? {
+        Some(log) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", NDJSON)
+            .header("Access-Control-Allow-Origin", CORS)
+            .body(Full::from(log).map_err(|e| match e {}).boxed())?),
+        None => not_found(id),
+    }
+}
+
+/// Kills the job's ffmpeg processes. The job's status becomes `Cancelled` once
+/// they've stopped.
+pub async fn cancel(state: SharedState, id: &str) -> MyResponse {
+    if state.jobs().cancel(id) {
+        return get(state, id).await;
+    }
+    match state.jobs().get(id).await? {
+        Some(_) => build_json_error(
+            StatusCode::CONFLICT,
+            &JsonError {
+                error: format!("Job {id} isn't running."),
+            },
+        ),
+        None => not_found(id),
+    }
+}
@@ -1,9 +1,14 @@
 pub mod cook;
 pub mod delete_videos;
 pub mod edit_video_metadata;
-pub mod move_to_downloads;
+pub mod jobs;
 pub mod probe_videos;
+pub mod projects;
 pub mod rename_tag;
 pub mod restore_from_downloads;
 pub mod serve_thumbnail;
 pub mod serve_video;
+pub mod stow;
+pub mod sync_videos;
+pub mod trash;
+pub mod undo;
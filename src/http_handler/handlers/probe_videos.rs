@@ -5,7 +5,7 @@ use hyper::body::Buf;
 use tokio::sync::Semaphore;
 
 use crate::{
-    common::{MAX_CONCURRENT_FFMPEG, SharedState, save_state},
+    common::{MAX_CONCURRENT_FFMPEG, SharedState},
     fmt::faded,
     http_handler::{
         defs::PreparePreviewReq,
@@ -52,13 +52,11 @@ pub async fn handle(req: Req, state: SharedState) -> MyResponse {
                 {
                     let mut state = state.write().await;
                     state
-                        .videos
-                        .iter_mut()
-                        .find(|v| v.thumbnail_name == video.thumbnail_name)
+                        .find_video_mut(&video.id)
                         .ok_or("cant find video i was making preview for")?
                         .probe = Some(result);
                 }
-                save_state(&*state.read().await).await?;
+                state.save_videos(&[video.id]).await?;
                 Ok::<(), BoxedError>(())
             });
             (display_name, handle)
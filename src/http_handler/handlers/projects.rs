@@ -0,0 +1,90 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::StatusCode;
+
+use crate::{
+    common::SharedState,
+    http_handler::{
+        defs::{Project, ProjectData},
+        util::{MyResponse, Req, build_json_response, build_text_response, read_json},
+    },
+};
+
+fn not_found(id: &str) -> MyResponse {
+    build_text_response(StatusCode::NOT_FOUND, format!("No project with ID {id}."))
+}
+
+pub async fn list(state: SharedState) -> MyResponse {
+    build_json_response(&state.read().await.projects)
+}
+
+pub async fn get(state: SharedState, id: &str) -> MyResponse {
+    match state.read().await.find_project(id) {
+        Some(project) => build_json_response(project),
+        None => not_found(id),
+    }
+}
+
+/// Saves a new project and returns it with its ID.
+pub async fn create(req: Req, state: SharedState) -> MyResponse {
+    let data: ProjectData = match read_json(req).await? {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let now = SystemTime::now();
+    let base = format!("{:x}", now.duration_since(UNIX_EPOCH)?.as_millis());
+    let project = {
+        let mut state = state.write().await;
+        let id = (1..)
+            .map(|n| match n {
+                1 => base.clone(),
+                n => format!("{base}-{n}"),
+            })
+            .find(|id| state.find_project(id).is_none())
+            .expect("ran out of numbers");
+        let project = Project {
+            id,
+            created: now,
+            updated: now,
+            data,
+        };
+        state.projects.push(project.clone());
+        project
+    };
+    state.save().await?;
+    build_json_response(&project)
+}
+
+/// Replaces everything about the project but its ID.
+pub async fn update(req: Req, state: SharedState, id: &str) -> MyResponse {
+    let data: ProjectData = match read_json(req).await? {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let project = {
+        let mut state = state.write().await;
+        let Some(project) = state.projects.iter_mut().find(|project| project.id == id) else {
+            return not_found(id);
+        };
+        project.data = data;
+        project.updated = SystemTime::now();
+        project.clone()
+    };
+    state.save().await?;
+    build_json_response(&project)
+}
+
+/// Returns the projects that are left.
+pub async fn delete(state: SharedState, id: &str) -> MyResponse {
+    let projects = {
+        let mut state = state.write().await;
+        let count = state.projects.len();
+        state.projects.retain(|project| project.id != id);
+        if state.projects.len() == count {
+            return not_found(id);
+        }
+        state.projects.clone()
+    };
+    state.save().await?;
+    build_json_response(&projects)
+}
@@ -2,24 +2,29 @@ use http_body_util::BodyExt;
 use hyper::body::Buf;
 
 use crate::{
-    common::{SharedState, save_state},
+    common::SharedState,
     http_handler::{
         defs::RenameTagRequest,
         util::{MyResponse, Req, build_json_response},
     },
+    journal::Operation,
 };
 
 pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     let request: RenameTagRequest =
         serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+    let mut operation = Operation::new(format!("Rename tag {} to {}", request.old, request.new));
     {
         let mut state = state.write().await;
         for video in &mut state.videos {
+            let before = video.clone();
             if video.tags.remove(&request.old) {
                 video.tags.insert(request.new.clone());
+                operation.record(Some(before), Some(video.clone()));
             }
         }
     }
-    save_state(&*state.read().await).await?;
+    state.save_videos(&operation.ids()).await?;
+    state.record(operation).await?;
     build_json_response(&*state.read().await)
 }
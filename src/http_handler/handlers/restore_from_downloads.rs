@@ -1,3 +1,4 @@
+use futures_util::FutureExt;
 use http_body_util::BodyExt;
 use hyper::body::Buf;
 
@@ -5,21 +6,23 @@ use crate::{
     common::SharedState,
     http_handler::{
         defs::VideoSelectRequest,
-        util::{MyResponse, Req, build_json_response},
+        util::{MyResponse, Req, build_state_response},
     },
 };
 
 pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     let request: VideoSelectRequest =
         serde_json::from_reader(req.collect().await?.aggregate().reader())?;
-    {
-        let mut state = state.write().await;
-        for video in &mut state.videos {
-            if !request.match_video(video) {
-                continue;
-            }
-            video.restore_file().await?;
-        }
-    }
-    build_json_response(&*state.read().await)
+    let failed = state
+        .change_videos(
+            format!("Restore {request}"),
+            |video| {
+                request.match_video(video)
+                    && video.stow_target().is_some()
+                    && video.trashed_at().is_none()
+            },
+            |video| video.restore_file().boxed(),
+        )
+        .await?;
+    build_state_response(&state, failed).await
 }
@@ -5,17 +5,25 @@ use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 
 use crate::{
-    common::DIR_PATH,
-    http_handler::util::{CORS, MyResponse, Req},
+    common::{DIR_PATH, SharedState},
+    http_handler::util::{CORS, MyResponse, Req, build_html_response, escape_html},
 };
 
-pub async fn handle(req: Req) -> MyResponse {
+pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     let path = req.uri().path();
-    let file = File::open(format!(
-        "{DIR_PATH}/thumbs/{}",
-        urlencoding::decode(&path[3..])?
-    ))
-    .await?;
+    let id = urlencoding::decode(&path[3..])?;
+    let Some(thumbnail_name) = state
+        .read()
+        .await
+        .find_video(&id)
+        .map(|video| video.thumbnail_name.clone())
+    else {
+        return build_html_response(
+            StatusCode::NOT_FOUND,
+            include_str!("../../static/404.html").replace("{PATH}", &escape_html(path)),
+        );
+    };
+    let file = File::open(format!("{DIR_PATH}/thumbs/{thumbnail_name}")).await?;
     let reader_stream = ReaderStream::new(file);
     let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data));
     let boxed_body = BodyExt::boxed(stream_body);
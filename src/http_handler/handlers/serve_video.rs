@@ -1,3 +1,5 @@
+use std::{io::ErrorKind, path::Path};
+
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
 use hyper::{Response, StatusCode, body::Frame};
@@ -12,15 +14,26 @@ use crate::{
     http_handler::util::{CORS, MyResponse, Req, build_html_response, escape_html},
 };
 
+fn video_content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("mov") => "video/quicktime",
+        Some("webm") => "video/webm",
+        Some("mkv") => "video/x-matroska",
+        Some("3gp") => "video/3gpp",
+        _ => "video/mp4",
+    }
+}
+
 pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     let path = req.uri().path();
-    let thumbnail_name = urlencoding::decode(&path[3..])?;
+    let id = urlencoding::decode(&path[3..])?;
     let Some(file_path) = ({
         let state = state.read().await;
         state
-            .videos
-            .iter()
-            .find(|video| video.thumbnail_name == thumbnail_name)
+            .find_video(&id)
             .map(|video| video.current_loc().clone())
     }) else {
         return build_html_response(
@@ -28,7 +41,18 @@ pub async fn handle(req: Req, state: SharedState) -> MyResponse {
             include_str!("../../static/404.html").replace("{PATH}", &escape_html(path)),
         );
     };
-    let mut file = File::open(file_path).await?;
+    let content_type = video_content_type(&file_path);
+    let mut file = match File::open(file_path).await {
+        Ok(file) => file,
+        // deleted outside the app; `sync --prune` will clean it up
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return build_html_response(
+                StatusCode::NOT_FOUND,
+                include_str!("../../static/404.html").replace("{PATH}", &escape_html(path)),
+            );
+        }
+        Err(err) => Err(err)?,
+    };
     let size = file.metadata().await?.len();
     let byte_range = req
         .headers()
@@ -60,7 +84,7 @@ pub async fn handle(req: Req, state: SharedState) -> MyResponse {
         } else {
             StatusCode::OK
         })
-        .header("Content-Type", "video/mp4")
+        .header("Content-Type", content_type)
         .header("Accept-Ranges", "bytes")
         .header("Access-Control-Allow-Origin", CORS)
         .header(
@@ -75,3 +99,16 @@ pub async fn handle(req: Req, state: SharedState) -> MyResponse {
     }
     Ok(response.body(boxed_body)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_content_type() {
+        assert_eq!(video_content_type(Path::new("a/b.mp4")), "video/mp4");
+        assert_eq!(video_content_type(Path::new("a/b.MOV")), "video/quicktime");
+        assert_eq!(video_content_type(Path::new("b.webm")), "video/webm");
+        assert_eq!(video_content_type(Path::new("b")), "video/mp4");
+    }
+}
@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use futures_util::FutureExt;
+use http_body_util::BodyExt;
+use hyper::{StatusCode, body::Buf};
+use serde::Serialize;
+use tokio::fs;
+
+use crate::{
+    common::SharedState,
+    config::Config,
+    http_handler::{
+        defs::{JsonError, StowRequest, VideoSelectRequest},
+        util::{MyResponse, Req, build_json_error, build_json_response, build_state_response},
+    },
+};
+
+async fn stow_videos(
+    state: SharedState,
+    target_name: String,
+    request: VideoSelectRequest,
+) -> MyResponse {
+    let config = Config::load().await?;
+    let Some(target) = config.stow_targets.get(&target_name) else {
+        return build_json_error(
+            StatusCode::NOT_FOUND,
+            &JsonError {
+                error: format!("There's no stow target named {target_name}."),
+            },
+        );
+    };
+    fs::create_dir_all(&target.dir).await?;
+    let failed = state
+        .change_videos(
+            format!("Stow {request} in {target_name}"),
+            |video| {
+                request.match_video(video)
+                    && video.trashed_at().is_none()
+                    && video.stow_target() != Some(&target_name)
+            },
+            |video| {
+                let (name, path) = (target_name.clone(), target.path_for(video));
+                async move { video.stow(name, path).await }.boxed()
+            },
+        )
+        .await?;
+    build_state_response(&state, failed).await
+}
+
+pub async fn handle(req: Req, state: SharedState) -> MyResponse {
+    let request: StowRequest = serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+    stow_videos(state, request.target, request.videos).await
+}
+
+/// `/for-youtube` from before stow targets were configurable
+pub async fn handle_for_youtube(req: Req, state: SharedState) -> MyResponse {
+    let request: VideoSelectRequest =
+        serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+    stow_videos(state, String::from("for-youtube"), request).await
+}
+
+#[derive(Serialize, Debug, Default)]
+struct TargetListing {
+    /// `None` if the target was removed from the config but still has videos
+    dir: Option<PathBuf>,
+    template: Option<String>,
+    videos: Vec<StowedVideo>,
+}
+
+#[derive(Serialize, Debug)]
+struct StowedVideo {
+    id: String,
+    path: PathBuf,
+}
+
+/// Lists the stow targets and which videos are in each.
+pub async fn list(state: SharedState) -> MyResponse {
+    let config = Config::load().await?;
+    let mut targets = config
+        .stow_targets
+        .into_iter()
+        .map(|(name, target)| {
+            (
+                name,
+                TargetListing {
+                    dir: Some(target.dir),
+                    template: Some(target.template),
+                    videos: Vec::new(),
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    for video in &state.read().await.videos {
+        let Some(target) = video.stow_target() else {
+            continue;
+        };
+        targets
+            .entry(target.to_string())
+            .or_default()
+            .videos
+            .push(StowedVideo {
+                id: video.id.clone(),
+                path: video.current_loc().clone(),
+            });
+    }
+    build_json_response(&targets)
+}
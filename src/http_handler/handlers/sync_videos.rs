@@ -0,0 +1,21 @@
+use http_body_util::BodyExt;
+
+use crate::{
+    common::SharedState,
+    config::Config,
+    http_handler::util::{MyResponse, Req, build_json_response},
+    sync::{SyncOptions, sync_videos},
+};
+
+pub async fn handle(req: Req, state: SharedState) -> MyResponse {
+    let body = req.collect().await?.to_bytes();
+    // an empty body just asks for a report
+    let options: SyncOptions = if body.is_empty() {
+        SyncOptions::default()
+    } else {
+        serde_json::from_slice(&body)?
+    };
+    let config = Config::load().await?;
+    let report = sync_videos(&options, &config, &state).await?;
+    build_json_response(&report)
+}
@@ -0,0 +1,30 @@
+use futures_util::FutureExt;
+use http_body_util::BodyExt;
+use hyper::body::Buf;
+
+use crate::{
+    common::SharedState,
+    http_handler::{
+        defs::VideoSelectRequest,
+        util::{MyResponse, Req, build_json_response, build_state_response},
+    },
+    trash::empty_trash,
+};
+
+pub async fn restore(req: Req, state: SharedState) -> MyResponse {
+    let request: VideoSelectRequest =
+        serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+    let failed = state
+        .change_videos(
+            format!("Restore {request} from the trash"),
+            |video| request.match_video(video) && video.trashed_at().is_some(),
+            |video| video.untrash().boxed(),
+        )
+        .await?;
+    build_state_response(&state, failed).await
+}
+
+pub async fn empty(state: SharedState) -> MyResponse {
+    empty_trash(&state).await?;
+    build_json_response(&*state.read().await)
+}
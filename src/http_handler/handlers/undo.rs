@@ -0,0 +1,37 @@
+use hyper::StatusCode;
+
+use crate::{
+    common::SharedState,
+    fmt::faded,
+    http_handler::{
+        defs::JsonError,
+        util::{MyResponse, build_json_error, build_json_response},
+    },
+};
+
+pub async fn handle(state: SharedState, redo: bool) -> MyResponse {
+    let result = if redo {
+        state.redo().await
+    } else {
+        state.undo().await
+    };
+    match result {
+        Ok(Some(description)) => {
+            let action = if redo { "Redid" } else { "Undid" };
+            eprintln!("{}", faded(&format!("[undo] {action} {description}")));
+            build_json_response(&*state.read().await)
+        }
+        Ok(None) => build_json_error(
+            StatusCode::CONFLICT,
+            &JsonError {
+                error: format!("Nothing to {}.", if redo { "redo" } else { "undo" }),
+            },
+        ),
+        Err(err) => build_json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &JsonError {
+                error: err.to_string(),
+            },
+        ),
+    }
+}
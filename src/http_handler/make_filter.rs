@@ -1,124 +1,573 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
 use tokio::process::Command;
 
-use crate::{common::ProbeResult, http_handler::probe::defs::CookClip, util::MyResult};
+use crate::{
+    common::{AudioProbeResult, ProbeResult, Rotation},
+    config::EncodingPreset,
+    http_handler::{
+        defs::{
+            BlurFit, ClipAudio, CropFit, Fit, LetterboxFit, Loudness, Playback, TextOverlay,
+            TextPosition, Transition, TransitionKind,
+        },
+        probe::defs::{
+            CardSource, CookCard, CookClip, CookItem, CookMusic, LoudnessMeasurement, LoudnormStats,
+        },
+    },
+    util::MyResult,
+};
 
-pub fn make_clip(
+/// `atempo` can only change the speed this much in one go.
+const MAX_ATEMPO: f64 = 2.0;
+/// Stream copying isn't worth the extra ffmpeg runs for less than this.
+const MIN_COPY_SECONDS: f64 = 2.0;
+/// ffprobe rounds keyframe times, so cuts stay this far from a keyframe to land
+/// on the right side of it.
+const CUT_MARGIN: f64 = 0.001;
+/// Clips can have different frame rates, but xfade needs them to match.
+const TRANSITION_FPS: u32 = 60;
+/// Title cards don't move, so this is plenty.
+const CARD_FPS: u32 = 30;
+/// Where Android keeps its fonts. ffmpeg in Termux can't find fonts by name.
+const ANDROID_FONT: &str = "/system/fonts/Roboto-Regular.ttf";
+
+/// One ffmpeg run towards making a clip. `offset` is where its output starts
+/// in the clip, for progress.
+pub struct ClipStep {
+    pub command: Command,
+    pub offset: f64,
+}
+
+/// What it takes to make a clip: files the steps read, which have to be
+/// written first, then the ffmpeg runs in order.
+pub struct ClipPlan {
+    pub files: Vec<(PathBuf, String)>,
+    pub steps: Vec<ClipStep>,
+}
+
+/// How much of the start and end of a clip goes into transitions.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Edges {
+    pub lead_in: f64,
+    pub lead_out: f64,
+}
+
+impl Edges {
+    fn is_empty(&self) -> bool {
+        self.lead_in == 0.0 && self.lead_out == 0.0
+    }
+}
+
+/// Works out the edges of each clip from the transitions between them.
+pub fn plan_edges(clips: &[CookItem]) -> MyResult<Vec<Edges>> {
+    let timings = clips
+        .iter()
+        .map(|clip| (clip.duration(), clip.transition()))
+        .collect::<Vec<_>>();
+    Ok(edges_of(&timings).map_err(|(_, err)| err)?)
+}
+
+/// [`plan_edges`] from how long each clip is and its transition into the next.
+/// Errors with the index of the clip that's wrong.
+pub fn edges_of(clips: &[(f64, Option<Transition>)]) -> Result<Vec<Edges>, (usize, String)> {
+    let mut edges = vec![Edges::default(); clips.len()];
+    for (i, pair) in clips.windows(2).enumerate() {
+        let Some(transition) = pair[0].1 else {
+            continue;
+        };
+        if transition.duration.is_nan() || transition.duration <= 0.0 {
+            return Err((
+                i,
+                format!("Transition after clip {i} needs a positive duration."),
+            ));
+        }
+        edges[i].lead_out = transition.duration;
+        edges[i + 1].lead_in = transition.duration;
+    }
+    for (i, ((duration, _), edges)) in clips.iter().zip(&edges).enumerate() {
+        // leave some of the clip that isn't transitioning
+        if edges.lead_in + edges.lead_out >= *duration {
+            return Err((
+                i,
+                format!("Transitions around clip {i} are longer than the clip."),
+            ));
+        }
+    }
+    Ok(edges)
+}
+
+/// Where the part of a clip that goes into a transition is written. `edge` is
+/// `head` or `tail`.
+pub fn edge_path(output_path: &str, edge: &str) -> PathBuf {
+    Path::new(output_path).with_extension(format!("{edge}.mp4"))
+}
+
+/// Something about a clip's source that [`make_clip`] needs ffprobe or ffmpeg
+/// to find out.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Measurement {
+    /// the first `loudnorm` pass, for clips that get normalized
+    Loudness,
+    /// keyframe times, for clips that could be stream copied
+    Keyframes,
+}
+
+/// What [`measure_clip`] found.
+#[derive(Debug, Default)]
+pub struct Measurements {
+    pub loudness: Option<LoudnessMeasurement>,
+    pub keyframes: Option<Vec<f64>>,
+}
+
+/// The h264 profile to copy the clip with, if it could be stream copied.
+/// Edges have to be cut where there might not be keyframes.
+fn copyable(
     clip: &CookClip,
     base_encode: &ProbeResult,
-    output_path: &str,
-) -> MyResult<Command> {
-    let mut command = Command::new("ffmpeg");
-    // only log errors and stats
-    command.arg("-v").arg("error");
-    command.arg("-stats");
+    preset: &EncodingPreset,
+    edges: &Edges,
+) -> Option<&'static str> {
+    copy_profile(clip, base_encode)
+        .filter(|_| edges.is_empty() && clip.texts.is_empty() && preset.can_copy_h264())
+}
 
-    command.arg("-display_rotation").arg("0");
-    command.arg("-i").arg(&clip.video_path);
+/// What [`measure_clip`] would run for the clip.
+pub fn measurements_needed(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    edges: &Edges,
+) -> Vec<Measurement> {
+    let mut needed = Vec::new();
+    if clip.loudness.is_some() && has_own_audio(clip, base_encode) {
+        needed.push(Measurement::Loudness);
+    }
+    if copyable(clip, base_encode, preset, edges).is_some() {
+        needed.push(Measurement::Keyframes);
+    }
+    needed
+}
 
-    let mut filters = String::new();
+/// Runs ffprobe and ffmpeg over the clip's source for what [`make_clip`] needs
+/// to know.
+pub async fn measure_clip(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    edges: &Edges,
+) -> MyResult<Measurements> {
+    let mut measurements = Measurements::default();
+    for measurement in measurements_needed(clip, base_encode, preset, edges) {
+        match measurement {
+            Measurement::Loudness => {
+                if let Some(target) = &clip.loudness {
+                    measurements.loudness = measure_loudness(clip, target).await?;
+                }
+            }
+            Measurement::Keyframes => match probe_keyframes(clip).await {
+                Ok(keyframes) => measurements.keyframes = Some(keyframes),
+                Err(err) => eprintln!("[cook] Couldn't find keyframes, re-encoding instead: {err}"),
+            },
+        }
+    }
+    Ok(measurements)
+}
 
-    if let Some(audio) = &base_encode.audio {
-        if clip.probe.audio.is_some() {
-            filters.push_str(&format!(
-                "[0:a] atrim = start={} : end={}, asetpts=PTS-STARTPTS [outa]; ",
-                clip.start, clip.end
+/// Plans the ffmpeg runs that make the clip. Clips that already match the
+/// encoding are mostly stream copied if `measured` has their keyframes, and
+/// everything else goes through the full filter graph. Edges that go into
+/// transitions are split off into their own files (see [`edge_path`]). This
+/// doesn't run or write anything.
+pub fn make_clip(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &str,
+    edges: &Edges,
+    measured: &Measurements,
+) -> MyResult<ClipPlan> {
+    let loudness = measured.loudness.as_ref();
+    if let (Some(profile), Some(keyframes)) = (
+        copyable(clip, base_encode, preset, edges),
+        &measured.keyframes,
+    ) && let Some(cut) = plan_cut(keyframes, clip.start, clip.end)
+    {
+        return stream_copy(
+            clip,
+            base_encode,
+            preset,
+            profile,
+            &cut,
+            output_path,
+            loudness,
+        );
+    }
+    let files = text_files(&clip.texts, output_path);
+    let command = encode_clip(
+        clip,
+        base_encode,
+        preset,
+        output_path,
+        edges,
+        &paths(&files),
+        loudness,
+    )?;
+    Ok(ClipPlan {
+        files,
+        steps: vec![ClipStep {
+            command,
+            offset: 0.0,
+        }],
+    })
+}
+
+/// Makes a title card the same way [`make_clip`] makes clips.
+pub async fn make_card(
+    card: &CookCard,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &str,
+    edges: &Edges,
+) -> MyResult<ClipPlan> {
+    let files = text_files(&card.texts, output_path);
+    let text_files = paths(&files);
+    let ProbeResult { width, height, .. } = base_encode;
+    let duration = card.duration;
+    let mut command = ffmpeg();
+    let mut filters = match &base_encode.audio {
+        Some(audio) => format!("{}; ", silence_filter(audio, duration)),
+        None => String::new(),
+    };
+    match &card.background {
+        CardSource::Color(color) => {
+            check_color(color)?;
+            command.arg("-f").arg("lavfi");
+            command.arg("-i").arg(format!(
+                "color = c={color} : s={width}x{height} : r={CARD_FPS} : d={duration}"
             ));
-        } else {
+            filters.push_str("[0:v] setsar = 1");
+        }
+        CardSource::Frame { video_path, time } => {
+            // let ffmpeg rotate it, unlike clips
+            command.arg("-ss").arg(time.to_string());
+            command.arg("-i").arg(video_path);
             filters.push_str(&format!(
-                "anullsrc = r={} : cl={}, atrim = start=0 : end={}",
-                audio.sample_rate,
-                audio.channel_layout,
-                clip.end - clip.start
+                "[0:v] trim = end_frame=1, setpts=PTS-STARTPTS, \
+                scale = {width}:{height} : force_original_aspect_ratio=increase, \
+                crop = {width}:{height}, gblur = sigma={}, \
+                tpad = stop_mode=clone : stop_duration={duration}, \
+                fps = {CARD_FPS}, trim = duration={duration}, setsar = 1",
+                width / 30
             ));
-            filters.push_str(", asetpts=PTS-STARTPTS [outa]; ");
         }
     }
+    if text_files.is_empty() {
+        filters.push_str(" [outv]");
+    } else {
+        filters.push_str(" [card]");
+        filters.push_str(&text_filter(
+            &card.texts,
+            &text_files,
+            duration,
+            &font(),
+            "card",
+        )?);
+    }
+    add_outputs(
+        &mut command,
+        filters,
+        base_encode,
+        preset,
+        output_path,
+        duration,
+        edges,
+    );
+    Ok(ClipPlan {
+        files,
+        steps: vec![ClipStep {
+            command,
+            offset: 0.0,
+        }],
+    })
+}
 
-    let ProbeResult { width, height, .. } = base_encode;
-    let aspect_ratio = *width as f64 / *height as f64;
-    let (original_width, original_height) = match &clip.override_rotation {
+/// ffmpeg that only logs errors, with progress on stdout.
+fn ffmpeg() -> Command {
+    let mut command = Command::new("ffmpeg");
+    command.arg("-v").arg("error");
+    command.arg("-nostats").arg("-progress").arg("pipe:1");
+    command
+}
+
+/// The clip's width and height before any filters.
+fn source_size(clip: &CookClip) -> (u32, u32) {
+    match &clip.override_rotation {
         // One of them is unrotated and the other is not, so we need to transpose the size
         Some(rot) if rot.transposed() != clip.probe.rotation.transposed() => {
             (clip.probe.height, clip.probe.width)
         }
         _ => (clip.probe.width, clip.probe.height),
+    }
+}
+
+/// Whether the clip's own audio ends up in the output, rather than silence.
+fn has_own_audio(clip: &CookClip, base_encode: &ProbeResult) -> bool {
+    base_encode.audio.is_some()
+        && clip.probe.audio.is_some()
+        && !clip.audio.mute
+        && clip.audio.volume != 0.0
+}
+
+/// Makes `[outa]` from input `input`'s audio, or silence if it's muted or has
+/// none. Empty if the encoding has no audio. `loudness` is from
+/// [`measure_loudness`] if the clip gets normalized.
+fn audio_filter(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    input: usize,
+    loudness: Option<&LoudnessMeasurement>,
+) -> MyResult<String> {
+    let ClipAudio {
+        volume,
+        fade_in,
+        fade_out,
+        ..
+    } = clip.audio;
+    let duration = clip.duration();
+    check_audio(&clip.audio, duration)?;
+    let Some(audio) = &base_encode.audio else {
+        return Ok(String::new());
     };
-    let my_aspect_ratio = original_width as f64 / original_height as f64;
-    let need_bg = my_aspect_ratio != aspect_ratio;
+    if !has_own_audio(clip, base_encode) {
+        return Ok(silence_filter(audio, duration));
+    }
+    let mut filter = format!(
+        "[{input}:a] atrim = start={} : end={}, asetpts=PTS-STARTPTS{}",
+        clip.start,
+        clip.end,
+        playback_filter(&clip.playback, true)?
+    );
+    if let (Some(target), Some(measured)) = (&clip.loudness, loudness) {
+        // loudnorm upsamples to 192 kHz
+        filter.push_str(&format!(
+            ", {} : measured_I={} : measured_TP={} : measured_LRA={} : \
+            measured_thresh={} : offset={} : linear=true, aresample = {}",
+            loudnorm(target)?,
+            measured.integrated,
+            measured.true_peak,
+            measured.range,
+            measured.threshold,
+            measured.offset,
+            audio.sample_rate
+        ));
+    }
+    if volume != 1.0 {
+        filter.push_str(&format!(", volume = {volume}"));
+    }
+    if fade_in > 0.0 {
+        filter.push_str(&format!(", afade = t=in : d={fade_in}"));
+    }
+    if fade_out > 0.0 {
+        filter.push_str(&format!(
+            ", afade = t=out : st={} : d={fade_out}",
+            duration - fade_out
+        ));
+    }
+    filter.push_str(" [outa]");
+    Ok(filter)
+}
 
-    // trim video
-    filters.push_str(&format!(
-        "[0:v] trim = start={} : end={}, setpts=PTS-STARTPTS",
-        clip.start, clip.end
-    ));
-    match clip
-        .override_rotation
-        .as_ref()
-        .unwrap_or(&clip.probe.rotation)
-    {
-        crate::common::Rotation::Unrotated => {}
-        crate::common::Rotation::Neg90 => filters.push_str(&format!(", transpose = dir=clock")),
-        crate::common::Rotation::Pos90 => filters.push_str(&format!(", transpose = dir=cclock")),
-        crate::common::Rotation::Neg180 => filters.push_str(&format!(", hflip, vflip")),
+/// The clip's volume and fades have to make sense for a clip that's `duration`
+/// seconds long.
+pub fn check_audio(audio: &ClipAudio, duration: f64) -> MyResult<()> {
+    let ClipAudio {
+        volume,
+        fade_in,
+        fade_out,
+        ..
+    } = *audio;
+    if !volume.is_finite() || volume < 0.0 {
+        Err(format!("Invalid volume {volume}."))?;
+    }
+    if fade_in.is_nan() || fade_out.is_nan() || fade_in < 0.0 || fade_out < 0.0 {
+        Err("Fades can't be negative.")?;
+    }
+    if fade_in + fade_out > duration {
+        Err(format!(
+            "Fades ({fade_in}s in, {fade_out}s out) are longer than the clip ({duration}s)."
+        ))?;
     }
+    Ok(())
+}
 
-    if need_bg {
-        filters.push_str(", split [v_trimmed] [v_trimmed_copy]; ");
-        filters.push_str(&format!(
-            "[v_trimmed] scale = {}, setsar = 1 [v_scaled]; ",
-            if my_aspect_ratio >= aspect_ratio {
-                // this clip is wider, use their width
-                format!("{width}:-1")
-            } else {
-                format!("-1:{height}")
+pub fn check_playback(playback: &Playback) -> MyResult<()> {
+    let speed = playback.speed;
+    if !(0.25..=4.0).contains(&speed) {
+        Err(format!("Speed {speed} isn't from 0.25 to 4."))?;
+    }
+    Ok(())
+}
+
+/// Reverses and speeds up or slows down the trimmed video or audio. Reversing
+/// keeps the whole clip in memory, so it's best on short clips.
+fn playback_filter(playback: &Playback, audio: bool) -> MyResult<String> {
+    check_playback(playback)?;
+    let Playback { speed, reverse } = *playback;
+    let mut filter = String::new();
+    if reverse {
+        filter.push_str(if audio { ", areverse" } else { ", reverse" });
+    }
+    if speed != 1.0 {
+        if audio {
+            for factor in atempo_factors(speed) {
+                filter.push_str(&format!(", atempo = {factor}"));
             }
-        ));
+        } else {
+            filter.push_str(&format!(", setpts=PTS/{speed}"));
+        }
+    }
+    Ok(filter)
+}
 
-        // create the blurred background:
-        // 1. crop the video to what is needed (crop =
-        //    width:height:x:y)
-        // 2. scale the video down
-        // 3. blur it
-        // 4. scale the video up
-        // 5. overlay the actual video
-        let cropped_width = original_width.min(original_height * width / height);
-        let cropped_height = original_height.min(original_width * height / width);
-        const DOWNSCALE_FACTOR: u32 = 3;
-        const BLUR_FACTOR: f64 = 0.1;
-        // split up long strings because they break rustfmt
-        filters.push_str(&format!(
-            "[v_trimmed_copy] crop = {cropped_width}:{cropped_height}:{}:{}, ",
-            (original_width - cropped_width) / 2,
-            (original_height - cropped_height) / 2,
-        ));
-        filters.push_str(&format!(
-            "scale = {}:{}, gblur = sigma={}, scale = {width}:{height}, ",
-            width / DOWNSCALE_FACTOR,
-            height / DOWNSCALE_FACTOR,
-            (width / DOWNSCALE_FACTOR) as f64 * BLUR_FACTOR,
-        ));
-        filters.push_str(&format!("setsar = 1 [v_blurred]; "));
-        filters.push_str(&format!("[v_blurred] [v_scaled] "));
-        filters.push_str(&format!(
-            "overlay = (main_w-overlay_w)/2:(main_h-overlay_h)/2 [outv]; "
-        ));
-    } else if original_width != *width || original_height != *height {
-        // aspect ratio is the same, just need to scale up/down
-        filters.push_str(&format!(", scale = {width}:{height}, setsar = 1 [outv]"));
-    } else {
-        filters.push_str(" [outv]");
+/// Splits `speed` into `atempo`s that each stay within what it can do.
+fn atempo_factors(speed: f64) -> Vec<f64> {
+    let mut factors = Vec::new();
+    let mut left = speed;
+    while left > MAX_ATEMPO {
+        factors.push(MAX_ATEMPO);
+        left /= MAX_ATEMPO;
+    }
+    while left < 1.0 / MAX_ATEMPO {
+        factors.push(1.0 / MAX_ATEMPO);
+        left *= MAX_ATEMPO;
+    }
+    factors.push(left);
+    factors
+}
+
+/// Makes `[outa]`, `duration` seconds of silence.
+fn silence_filter(audio: &AudioProbeResult, duration: f64) -> String {
+    format!(
+        "anullsrc = r={} : cl={}, atrim = start=0 : end={duration}, asetpts=PTS-STARTPTS [outa]",
+        audio.sample_rate, audio.channel_layout,
+    )
+}
+
+pub fn check_loudness(loudness: &Loudness) -> MyResult<()> {
+    let Loudness {
+        target,
+        true_peak,
+        range,
+    } = *loudness;
+    if !(-70.0..=-5.0).contains(&target) {
+        Err(format!(
+            "Loudness target {target} isn't from -70 to -5 LUFS."
+        ))?;
+    }
+    if !(-9.0..=0.0).contains(&true_peak) {
+        Err(format!("True peak {true_peak} isn't from -9 to 0 dBTP."))?;
+    }
+    if !(1.0..=50.0).contains(&range) {
+        Err(format!("Loudness range {range} isn't from 1 to 50 LU."))?;
     }
+    Ok(())
+}
 
-    command.arg("-filter_complex").arg(filters);
-    // specify what the outputs are
-    command.arg("-map").arg("[outv]");
-    if base_encode.audio.is_some() {
-        command.arg("-map").arg("[outa]");
+/// `loudnorm` with the targets, without the measurements.
+fn loudnorm(loudness: &Loudness) -> MyResult<String> {
+    check_loudness(loudness)?;
+    let Loudness {
+        target,
+        true_peak,
+        range,
+    } = *loudness;
+    Ok(format!(
+        "loudnorm = I={target} : TP={true_peak} : LRA={range}"
+    ))
+}
+
+/// Gets the stats out of the first `loudnorm` pass's log, which ends with
+/// them as JSON. `None` if the clip is silent, so there's nothing to
+/// normalize.
+fn parse_loudnorm(log: &str) -> MyResult<Option<LoudnessMeasurement>> {
+    let start = log.rfind('{').ok_or("loudnorm didn't print its stats")?;
+    let end = log[start..].find('}').ok_or("loudnorm stats got cut off")? + start;
+    let stats: LoudnormStats = serde_json::from_str(&log[start..=end])?;
+    let parse = |value: &str| {
+        value
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Bad loudnorm stat {value:?}."))
+    };
+    let measurement = LoudnessMeasurement {
+        integrated: parse(&stats.input_i)?,
+        true_peak: parse(&stats.input_tp)?,
+        range: parse(&stats.input_lra)?,
+        threshold: parse(&stats.input_thresh)?,
+        offset: parse(&stats.target_offset)?,
+    };
+    let finite = [
+        measurement.integrated,
+        measurement.true_peak,
+        measurement.range,
+        measurement.threshold,
+        measurement.offset,
+    ]
+    .iter()
+    .all(|value| value.is_finite());
+    Ok(finite.then_some(measurement))
+}
+
+/// The first `loudnorm` pass, over just the clip's audio.
+async fn measure_loudness(
+    clip: &CookClip,
+    target: &Loudness,
+) -> MyResult<Option<LoudnessMeasurement>> {
+    let output = Command::new("ffmpeg")
+        // the stats are logged at the info level
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(&clip.video_path)
+        .arg("-vn")
+        .arg("-af")
+        .arg(format!(
+            "atrim = start={} : end={}, asetpts=PTS-STARTPTS, {} : print_format=json",
+            clip.start,
+            clip.end,
+            loudnorm(target)?
+        ))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await?;
+    let log = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        Err(format!("ffmpeg loudness error:\n{log}"))?;
+    }
+    parse_loudnorm(&log)
+}
+
+fn video_encode_args(
+    command: &mut Command,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    profile: Option<&str>,
+) {
+    // fast and good quality by default
+    if let Some(speed) = &preset.preset {
+        command.arg("-preset").arg(speed);
+    }
+    if let Some(crf) = preset.crf {
+        command.arg("-crf").arg(crf.to_string());
+    }
+    command.arg("-c:v").arg(&preset.video_codec);
+    if let Some(profile) = profile {
+        command.arg("-profile:v").arg(profile);
     }
-    // fast and good quality
-    command.arg("-preset").arg("veryfast");
-    command.arg("-crf").arg("18");
-    command.arg("-c:v").arg("libx264"); // force h264
     command.arg("-pix_fmt").arg(&base_encode.pix_fmt);
     command
         .arg("-color_primaries")
@@ -129,13 +578,43 @@ pub fn make_clip(
     command
         .arg("-colorspace")
         .arg(base_encode.color_space.as_ref().map_or("bt709", |v| v));
-    command.arg("-fps_mode").arg("vfr"); // force variable frame rate
-    if let Some(audio) = &base_encode.audio {
-        command.arg("-c:a").arg("aac"); // force aac
+    match preset.fps {
+        Some(fps) => {
+            command.arg("-r").arg(fps.to_string());
+            command.arg("-fps_mode").arg("cfr");
+        }
+        None => {
+            command.arg("-fps_mode").arg("vfr"); // force variable frame rate
+        }
+    }
+}
+
+fn audio_encode_args(
+    command: &mut Command,
+    audio: Option<&AudioProbeResult>,
+    preset: &EncodingPreset,
+) {
+    command.arg("-c:a").arg(&preset.audio_codec);
+    if let Some(bitrate) = &preset.audio_bitrate {
+        command.arg("-b:a").arg(bitrate);
+    }
+    if let Some(audio) = audio {
         command.arg("-ar").arg(audio.sample_rate.to_string());
         command.arg("-ac").arg(audio.channels.to_string());
         command.arg("-channel_layout").arg(&audio.channel_layout);
     }
+}
+
+/// Audio settings and the stuff that makes clips concattable, then the output.
+fn clip_output_args(
+    command: &mut Command,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &Path,
+) {
+    if let Some(audio) = &base_encode.audio {
+        audio_encode_args(command, Some(audio), preset);
+    }
     // set rotation to 0 (termux ffmpeg seems to copy it)
     command.arg("-metadata:s:v").arg("rotate=0");
     // 1/90000 time scale, for consistent time base before concat. vfr
@@ -147,6 +626,1217 @@ pub fn make_clip(
     // doesn't get blocked by the yes thing
     command.arg("-y");
     command.arg(output_path);
+}
 
+/// Re-encodes the whole clip with the full filter graph.
+fn encode_clip(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &str,
+    edges: &Edges,
+    text_files: &[PathBuf],
+    loudness: Option<&LoudnessMeasurement>,
+) -> MyResult<Command> {
+    let mut command = ffmpeg();
+    command.arg("-display_rotation").arg("0");
+    command.arg("-i").arg(&clip.video_path);
+
+    let mut filters = audio_filter(clip, base_encode, 0, loudness)?;
+    if !filters.is_empty() {
+        filters.push_str("; ");
+    }
+
+    // trim video
+    filters.push_str(&format!(
+        "[0:v] trim = start={} : end={}, setpts=PTS-STARTPTS{}",
+        clip.start,
+        clip.end,
+        playback_filter(&clip.playback, false)?
+    ));
+    match clip
+        .override_rotation
+        .as_ref()
+        .unwrap_or(&clip.probe.rotation)
+    {
+        Rotation::Unrotated => {}
+        Rotation::Neg90 => filters.push_str(", transpose = dir=clock"),
+        Rotation::Pos90 => filters.push_str(", transpose = dir=cclock"),
+        Rotation::Neg180 => filters.push_str(", hflip, vflip"),
+    }
+    if text_files.is_empty() {
+        filters.push_str(&fit_filter(
+            &clip.fit,
+            source_size(clip),
+            (base_encode.width, base_encode.height),
+            "outv",
+        )?);
+    } else {
+        filters.push_str(&fit_filter(
+            &clip.fit,
+            source_size(clip),
+            (base_encode.width, base_encode.height),
+            "fitted",
+        )?);
+        filters = filters.trim_end_matches("; ").to_string();
+        filters.push_str(&text_filter(
+            &clip.texts,
+            text_files,
+            clip.duration(),
+            &font(),
+            "fitted",
+        )?);
+    }
+    add_outputs(
+        &mut command,
+        filters,
+        base_encode,
+        preset,
+        output_path,
+        clip.duration(),
+        edges,
+    );
     Ok(command)
 }
+
+/// Maps `[outv]` and `[outa]` to the output. If some of it goes into
+/// transitions, they're split off into their own files (see [`edge_path`]).
+fn add_outputs(
+    command: &mut Command,
+    mut filters: String,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    output_path: &str,
+    duration: f64,
+    edges: &Edges,
+) {
+    // (start, end, path) of each output
+    let mut pieces = Vec::new();
+    if edges.lead_in > 0.0 {
+        pieces.push((0.0, edges.lead_in, edge_path(output_path, "head")));
+    }
+    pieces.push((
+        edges.lead_in,
+        duration - edges.lead_out,
+        PathBuf::from(output_path),
+    ));
+    if edges.lead_out > 0.0 {
+        pieces.push((
+            duration - edges.lead_out,
+            duration,
+            edge_path(output_path, "tail"),
+        ));
+    }
+    let has_audio = base_encode.audio.is_some();
+    let outputs = if edges.is_empty() {
+        vec![(String::from("[outv]"), String::from("[outa]"))]
+    } else {
+        // encode once, then cut it up
+        let mut filters_tail = format!("; [outv] split = {}", pieces.len());
+        for j in 0..pieces.len() {
+            filters_tail.push_str(&format!(" [v{j}]"));
+        }
+        if has_audio {
+            filters_tail.push_str(&format!("; [outa] asplit = {}", pieces.len()));
+            for j in 0..pieces.len() {
+                filters_tail.push_str(&format!(" [a{j}]"));
+            }
+        }
+        for (j, (start, end, _)) in pieces.iter().enumerate() {
+            filters_tail.push_str(&format!(
+                "; [v{j}] trim = start={start} : end={end}, setpts=PTS-STARTPTS [outv{j}]"
+            ));
+            if has_audio {
+                filters_tail.push_str(&format!(
+                    "; [a{j}] atrim = start={start} : end={end}, asetpts=PTS-STARTPTS [outa{j}]"
+                ));
+            }
+        }
+        filters = filters.trim_end_matches("; ").to_string();
+        filters.push_str(&filters_tail);
+        (0..pieces.len())
+            .map(|j| (format!("[outv{j}]"), format!("[outa{j}]")))
+            .collect()
+    };
+
+    command.arg("-filter_complex").arg(filters);
+    for ((video, audio), (_, _, path)) in outputs.iter().zip(&pieces) {
+        // specify what the outputs are
+        command.arg("-map").arg(video);
+        if has_audio {
+            command.arg("-map").arg(audio);
+        }
+        video_encode_args(command, base_encode, preset, None);
+        clip_output_args(command, base_encode, preset, path);
+    }
+}
+
+/// Colours end up in the filter graph, so nothing that could escape it.
+pub fn check_color(color: &str) -> MyResult<()> {
+    if color.is_empty()
+        || !color
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '#' | '@' | '.'))
+    {
+        Err(format!("Invalid color {color:?}."))?;
+    }
+    Ok(())
+}
+
+fn font() -> String {
+    if Path::new(ANDROID_FONT).exists() {
+        format!("fontfile={ANDROID_FONT}")
+    } else {
+        String::from("font=Sans")
+    }
+}
+
+/// Each overlay's text goes in a file next to the clip, so it doesn't have to
+/// be escaped for the filter graph.
+fn text_files(texts: &[TextOverlay], output_path: &str) -> Vec<(PathBuf, String)> {
+    texts
+        .iter()
+        .enumerate()
+        .map(|(j, overlay)| {
+            (
+                Path::new(output_path).with_extension(format!("text{j}.txt")),
+                overlay.text.clone(),
+            )
+        })
+        .collect()
+}
+
+fn paths(files: &[(PathBuf, String)]) -> Vec<PathBuf> {
+    files.iter().map(|(path, _)| path.clone()).collect()
+}
+
+/// A text overlay on a clip that's `duration` seconds long.
+pub fn check_text(overlay: &TextOverlay, duration: f64) -> MyResult<()> {
+    let TextOverlay {
+        text,
+        start,
+        end,
+        size,
+        color,
+        ..
+    } = overlay;
+    let end = end.unwrap_or(duration);
+    if text.trim().is_empty() {
+        Err("Text overlays can't be empty.")?;
+    }
+    if !(*start >= 0.0 && *start < end) {
+        Err(format!("Text {text:?} has to start before it ends."))?;
+    }
+    if !(*size > 0.0 && *size <= 1.0) {
+        Err(format!("Text {text:?} needs a size between 0 and 1."))?;
+    }
+    check_color(color)
+}
+
+/// Draws the texts over `[{input}]`, ending with `[outv]`. `text_files` has
+/// each overlay's text (see [`text_files`]).
+fn text_filter(
+    texts: &[TextOverlay],
+    text_files: &[PathBuf],
+    duration: f64,
+    font: &str,
+    input: &str,
+) -> MyResult<String> {
+    let mut filters = format!("; [{input}] ");
+    for (j, (overlay, path)) in texts.iter().zip(text_files).enumerate() {
+        check_text(overlay, duration)?;
+        let TextOverlay {
+            start,
+            end,
+            position,
+            size,
+            color,
+            ..
+        } = overlay;
+        let end = end.unwrap_or(duration);
+        let y = match position {
+            TextPosition::Top => "h*0.05",
+            TextPosition::Center => "(h-text_h)/2",
+            TextPosition::Bottom => "h*0.95-text_h",
+        };
+        if j > 0 {
+            filters.push_str(", ");
+        }
+        filters.push_str(&format!(
+            "drawtext = textfile='{}' : expansion=none : {font} : \
+            fontsize=h*{size} : fontcolor={color} : borderw=2 : bordercolor=black@0.6 : \
+            x=(w-text_w)/2 : y={y} : enable='between(t,{start},{end})'",
+            path.display()
+        ));
+    }
+    filters.push_str(" [outv]");
+    Ok(filters)
+}
+
+pub fn check_fit(fit: &Fit) -> MyResult<()> {
+    match fit {
+        Fit::Blur(BlurFit { downscale, blur }) => {
+            if *downscale == 0 || !blur.is_finite() || *blur < 0.0 {
+                Err("Blur needs a downscale of at least 1 and a blur of at least 0.")?;
+            }
+        }
+        Fit::Letterbox(LetterboxFit { color }) => check_color(color)?,
+        Fit::Crop(CropFit { x, y }) => {
+            if !(0.0..=1.0).contains(x) || !(0.0..=1.0).contains(y) {
+                Err("Crop position has to be between 0 and 1.")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Continues the filter chain for the trimmed and rotated clip, sizing it to
+/// `(width, height)` and ending with `[{label}]`.
+fn fit_filter(
+    fit: &Fit,
+    (original_width, original_height): (u32, u32),
+    (width, height): (u32, u32),
+    label: &str,
+) -> MyResult<String> {
+    check_fit(fit)?;
+    let aspect_ratio = width as f64 / height as f64;
+    let my_aspect_ratio = original_width as f64 / original_height as f64;
+    if my_aspect_ratio == aspect_ratio {
+        return Ok(if original_width != width || original_height != height {
+            // aspect ratio is the same, just need to scale up/down
+            format!(", scale = {width}:{height}, setsar = 1 [{label}]")
+        } else {
+            format!(" [{label}]")
+        });
+    }
+
+    let mut filters = String::new();
+    match fit {
+        Fit::Blur(BlurFit { downscale, blur }) => {
+            filters.push_str(", split [v_trimmed] [v_trimmed_copy]; ");
+            filters.push_str(&format!(
+                "[v_trimmed] scale = {}, setsar = 1 [v_scaled]; ",
+                if my_aspect_ratio >= aspect_ratio {
+                    // this clip is wider, use their width
+                    format!("{width}:-1")
+                } else {
+                    format!("-1:{height}")
+                }
+            ));
+
+            // create the blurred background:
+            // 1. crop the video to what is needed (crop =
+            //    width:height:x:y)
+            // 2. scale the video down
+            // 3. blur it
+            // 4. scale the video up
+            // 5. overlay the actual video
+            let cropped_width = original_width.min(original_height * width / height);
+            let cropped_height = original_height.min(original_width * height / width);
+            // split up long strings because they break rustfmt
+            filters.push_str(&format!(
+                "[v_trimmed_copy] crop = {cropped_width}:{cropped_height}:{}:{}, ",
+                (original_width - cropped_width) / 2,
+                (original_height - cropped_height) / 2,
+            ));
+            filters.push_str(&format!(
+                "scale = {}:{}, gblur = sigma={}, scale = {width}:{height}, ",
+                width / downscale,
+                height / downscale,
+                (width / downscale) as f64 * blur,
+            ));
+            filters.push_str("setsar = 1 [v_blurred]; ");
+            filters.push_str("[v_blurred] [v_scaled] ");
+            filters.push_str(&format!(
+                "overlay = (main_w-overlay_w)/2:(main_h-overlay_h)/2 [{label}]; "
+            ));
+        }
+        Fit::Letterbox(LetterboxFit { color }) => {
+            // libx264 needs even sizes
+            filters.push_str(&format!(
+                ", scale = {width}:{height} : force_original_aspect_ratio=decrease \
+                : force_divisible_by=2"
+            ));
+            filters.push_str(&format!(
+                ", pad = {width}:{height} : (ow-iw)/2 : (oh-ih)/2 : color={color}"
+            ));
+            filters.push_str(&format!(", setsar = 1 [{label}]"));
+        }
+        Fit::Crop(CropFit { x, y }) => {
+            filters.push_str(&format!(
+                ", scale = {width}:{height} : force_original_aspect_ratio=increase"
+            ));
+            filters.push_str(&format!(
+                ", crop = {width}:{height} : (iw-ow)*{x} : (ih-oh)*{y}"
+            ));
+            filters.push_str(&format!(", setsar = 1 [{label}]"));
+        }
+    }
+    Ok(filters)
+}
+
+fn xfade_name(kind: TransitionKind) -> &'static str {
+    match kind {
+        TransitionKind::Crossfade => "fade",
+        TransitionKind::FadeBlack => "fadeblack",
+        TransitionKind::Wipe => "wipeleft",
+    }
+}
+
+fn transition_filter(transition: &Transition, has_audio: bool) -> String {
+    let Transition { kind, duration } = transition;
+    let mut filters = format!(
+        "[0:v] fps = {TRANSITION_FPS}, settb = AVTB [from]; \
+        [1:v] fps = {TRANSITION_FPS}, settb = AVTB [to]; \
+        [from] [to] xfade = transition={} : duration={duration} : offset=0 [outv]",
+        xfade_name(*kind)
+    );
+    if has_audio {
+        filters.push_str(&format!("; [0:a] [1:a] acrossfade = d={duration} [outa]"));
+    }
+    filters
+}
+
+/// Blends the end of one clip into the start of the next, from the edges that
+/// [`make_clip`] split off.
+pub fn make_transition(
+    transition: &Transition,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    from: &Path,
+    to: &Path,
+    output_path: &Path,
+) -> Command {
+    let mut command = ffmpeg();
+    command.arg("-i").arg(from);
+    command.arg("-i").arg(to);
+    let has_audio = base_encode.audio.is_some();
+    command
+        .arg("-filter_complex")
+        .arg(transition_filter(transition, has_audio));
+    command.arg("-map").arg("[outv]");
+    if has_audio {
+        command.arg("-map").arg("[outa]");
+    }
+    video_encode_args(&mut command, base_encode, preset, None);
+    clip_output_args(&mut command, base_encode, preset, output_path);
+    command
+}
+
+pub fn check_music(start: f64, volume: f64, fade_out: f64) -> MyResult<()> {
+    if !volume.is_finite() || volume < 0.0 {
+        Err(format!("Invalid music volume {volume}."))?;
+    }
+    if start.is_nan() || fade_out.is_nan() || start < 0.0 || fade_out < 0.0 {
+        Err("Music start and fade out can't be negative.")?;
+    }
+    Ok(())
+}
+
+/// The filter that lays `music` under `[0:a]`, or makes it the only audio if
+/// there's none, as `[outa]`.
+fn music_filter(music: &CookMusic, has_audio: bool, duration: f64) -> MyResult<String> {
+    let CookMusic {
+        start,
+        volume,
+        fade_out,
+        ..
+    } = *music;
+    check_music(start, volume, fade_out)?;
+    // padded with silence in case it's too short
+    let mut filter = format!(
+        "[1:a] atrim = start={start}, asetpts=PTS-STARTPTS, volume = {volume}, \
+        apad, atrim = end={duration}"
+    );
+    if fade_out > 0.0 {
+        filter.push_str(&format!(
+            ", afade = t=out : st={} : d={}",
+            (duration - fade_out).max(0.0),
+            fade_out.min(duration)
+        ));
+    }
+    if has_audio {
+        filter.push_str(
+            " [music]; [0:a][music] amix = inputs=2 : duration=first : normalize=0 [outa]",
+        );
+    } else {
+        filter.push_str(" [outa]");
+    }
+    Ok(filter)
+}
+
+/// Mixes the background track into the concatted video, which is `duration`
+/// seconds long. Only the audio gets re-encoded.
+pub fn mix_music(
+    music: &CookMusic,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    input: &Path,
+    duration: f64,
+    output: &Path,
+) -> MyResult<Command> {
+    let mut command = ffmpeg();
+    command.arg("-i").arg(input);
+    if music.repeat {
+        command.arg("-stream_loop").arg("-1");
+    }
+    command.arg("-i").arg(&music.path);
+    command
+        .arg("-filter_complex")
+        .arg(music_filter(music, base_encode.audio.is_some(), duration)?);
+    command.arg("-map").arg("0:v");
+    command.arg("-map").arg("[outa]");
+    command.arg("-c:v").arg("copy");
+    audio_encode_args(&mut command, base_encode.audio.as_ref(), preset);
+    command.arg("-y");
+    command.arg(output);
+    Ok(command)
+}
+
+/// The libx264 profile to re-encode the edges with, if the clip can be stream
+/// copied: it has to be h264 that already has the size, pixel format, colors
+/// and rotation the encoding wants.
+fn copy_profile(clip: &CookClip, base_encode: &ProbeResult) -> Option<&'static str> {
+    let color = |value: &Option<String>| value.clone().unwrap_or_else(|| "bt709".into());
+    let rotation = clip
+        .override_rotation
+        .as_ref()
+        .unwrap_or(&clip.probe.rotation);
+    let compatible = clip.probe.codec.as_deref() == Some("h264")
+        && *rotation == Rotation::Unrotated
+        && clip.playback == Playback::default()
+        && source_size(clip) == (base_encode.width, base_encode.height)
+        && clip.probe.pix_fmt == base_encode.pix_fmt
+        && color(&clip.probe.color_primaries) == color(&base_encode.color_primaries)
+        && color(&clip.probe.color_transfer) == color(&base_encode.color_transfer)
+        && color(&clip.probe.color_space) == color(&base_encode.color_space);
+    if !compatible {
+        return None;
+    }
+    match clip.probe.profile.as_deref()? {
+        "Baseline" | "Constrained Baseline" => Some("baseline"),
+        "Main" => Some("main"),
+        "High" => Some("high"),
+        _ => None,
+    }
+}
+
+/// Keyframe times from ffprobe's `packet=pts_time,flags` CSV output.
+fn parse_keyframes(output: &str) -> Vec<f64> {
+    let mut keyframes = output
+        .lines()
+        .filter_map(|line| {
+            let (time, flags) = line.trim().split_once(',')?;
+            if !flags.starts_with('K') {
+                return None;
+            }
+            time.parse::<f64>().ok()
+        })
+        .collect::<Vec<_>>();
+    keyframes.sort_by(f64::total_cmp);
+    keyframes.dedup();
+    keyframes
+}
+
+async fn probe_keyframes(clip: &CookClip) -> MyResult<Vec<f64>> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        // only read around the clip
+        .arg("-read_intervals")
+        .arg(format!("{}%{}", clip.start, clip.end))
+        // packet flags are enough, so nothing gets decoded
+        .arg("-show_entries")
+        .arg("packet=pts_time,flags")
+        .arg("-output_format")
+        .arg("csv=p=0")
+        .arg(&clip.video_path)
+        .output()
+        .await?;
+    if !output.status.success() {
+        Err(format!(
+            "ffprobe keyframes error:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))?;
+    }
+    Ok(parse_keyframes(&String::from_utf8(output.stdout)?))
+}
+
+/// The part of a clip that gets stream copied. Before and after it are partial
+/// GOPs that have to be re-encoded.
+#[derive(Debug, PartialEq)]
+struct Cut {
+    /// first keyframe in the clip
+    copy_start: f64,
+    /// last keyframe in the clip, where the last partial GOP starts
+    copy_end: f64,
+}
+
+fn plan_cut(keyframes: &[f64], start: f64, end: f64) -> Option<Cut> {
+    let copy_start = *keyframes.iter().find(|&&time| time >= start - CUT_MARGIN)?;
+    let copy_end = *keyframes
+        .iter()
+        .rev()
+        .find(|&&time| time <= end + CUT_MARGIN)?;
+    (copy_end - copy_start >= MIN_COPY_SECONDS).then_some(Cut {
+        copy_start,
+        copy_end,
+    })
+}
+
+/// Video only, to MPEG-TS so each segment keeps its own SPS/PPS when they're
+/// joined.
+fn cut_segment(clip: &CookClip, start: f64, end: f64) -> Command {
+    let mut command = ffmpeg();
+    command.arg("-display_rotation").arg("0");
+    // same timeline as ffprobe's keyframe times
+    command.arg("-seek_timestamp").arg("1");
+    command.arg("-ss").arg(start.to_string());
+    command.arg("-to").arg(end.to_string());
+    command.arg("-i").arg(&clip.video_path);
+    command.arg("-map").arg("0:v:0");
+    command
+}
+
+/// Copies the whole GOPs in the clip, re-encodes the partial ones at the
+/// edges, then joins them and adds re-encoded audio.
+fn stream_copy(
+    clip: &CookClip,
+    base_encode: &ProbeResult,
+    preset: &EncodingPreset,
+    profile: &str,
+    cut: &Cut,
+    output_path: &str,
+    loudness: Option<&LoudnessMeasurement>,
+) -> MyResult<ClipPlan> {
+    let output_path = Path::new(output_path);
+    let dir = output_path.parent().ok_or("clip has no folder??")?;
+    let stem = output_path
+        .file_stem()
+        .ok_or("clip has no name??")?
+        .to_string_lossy();
+    let mut steps = Vec::new();
+    let mut segments = Vec::new();
+
+    if cut.copy_start - clip.start > CUT_MARGIN {
+        let name = format!("{stem}.head.ts");
+        let mut command = cut_segment(clip, clip.start, cut.copy_start - CUT_MARGIN);
+        video_encode_args(&mut command, base_encode, preset, Some(profile));
+        command
+            .arg("-f")
+            .arg("mpegts")
+            .arg("-y")
+            .arg(dir.join(&name));
+        steps.push(ClipStep {
+            command,
+            offset: 0.0,
+        });
+        segments.push(name);
+    }
+
+    let name = format!("{stem}.copy.ts");
+    // seeking with stream copy starts at the keyframe before the seek point
+    let mut command = cut_segment(clip, cut.copy_start + CUT_MARGIN, cut.copy_end - CUT_MARGIN);
+    command.arg("-c:v").arg("copy");
+    command
+        .arg("-f")
+        .arg("mpegts")
+        .arg("-y")
+        .arg(dir.join(&name));
+    steps.push(ClipStep {
+        command,
+        offset: cut.copy_start - clip.start,
+    });
+    segments.push(name);
+
+    if clip.end - cut.copy_end > CUT_MARGIN {
+        let name = format!("{stem}.tail.ts");
+        let mut command = cut_segment(clip, cut.copy_end - CUT_MARGIN, clip.end);
+        video_encode_args(&mut command, base_encode, preset, Some(profile));
+        command
+            .arg("-f")
+            .arg("mpegts")
+            .arg("-y")
+            .arg(dir.join(&name));
+        steps.push(ClipStep {
+            command,
+            offset: cut.copy_end - clip.start,
+        });
+        segments.push(name);
+    }
+
+    let list_path = dir.join(format!("{stem}.segments.txt"));
+    let list = segments
+        .iter()
+        .map(|name| format!("file '{name}'\n"))
+        .collect::<String>();
+
+    let mut command = ffmpeg();
+    command.arg("-f").arg("concat");
+    command.arg("-i").arg(&list_path);
+    command.arg("-i").arg(&clip.video_path);
+    command.arg("-map").arg("0:v");
+    let filters = audio_filter(clip, base_encode, 1, loudness)?;
+    if !filters.is_empty() {
+        command.arg("-filter_complex").arg(filters);
+        command.arg("-map").arg("[outa]");
+    }
+    command.arg("-c:v").arg("copy");
+    clip_output_args(&mut command, base_encode, preset, output_path);
+    steps.push(ClipStep {
+        command,
+        offset: 0.0,
+    });
+    Ok(ClipPlan {
+        files: vec![(list_path, list)],
+        steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::AudioProbeResult;
+
+    fn probe() -> ProbeResult {
+        ProbeResult {
+            width: 1920,
+            height: 1080,
+            duration: 60.0,
+            rotation: Rotation::Unrotated,
+            pix_fmt: "yuv420p".into(),
+            color_space: Some("bt709".into()),
+            color_transfer: None,
+            color_primaries: Some("bt709".into()),
+            bit_rate: 20_000_000,
+            audio: Some(AudioProbeResult {
+                sample_rate: 48000,
+                bit_rate: 128_000,
+                channels: 2,
+                channel_layout: "stereo".into(),
+            }),
+            codec: Some("h264".into()),
+            profile: Some("High".into()),
+        }
+    }
+
+    fn clip(probe: ProbeResult) -> CookClip {
+        CookClip {
+            id: String::from("video"),
+            video_path: "video.mp4".into(),
+            probe,
+            start: 1.5,
+            end: 9.0,
+            override_rotation: None,
+            transition: None,
+            fit: Fit::default(),
+            texts: Vec::new(),
+            audio: ClipAudio::default(),
+            loudness: None,
+            playback: Playback::default(),
+        }
+    }
+
+    #[test]
+    fn test_copy_profile() {
+        let base = probe();
+        assert_eq!(copy_profile(&clip(probe()), &base), Some("high"));
+
+        let mut baseline = probe();
+        baseline.profile = Some("Constrained Baseline".into());
+        assert_eq!(copy_profile(&clip(baseline), &base), Some("baseline"));
+
+        let mut hevc = probe();
+        hevc.codec = Some("hevc".into());
+        assert_eq!(copy_profile(&clip(hevc), &base), None);
+
+        // older probes don't know the codec
+        let mut old = probe();
+        old.codec = None;
+        assert_eq!(copy_profile(&clip(old), &base), None);
+
+        let mut small = probe();
+        small.width = 1280;
+        small.height = 720;
+        assert_eq!(copy_profile(&clip(small), &base), None);
+
+        let mut rotated = clip(probe());
+        rotated.override_rotation = Some(Rotation::Neg180);
+        assert_eq!(copy_profile(&rotated, &base), None);
+
+        let mut hdr = probe();
+        hdr.color_transfer = Some("arib-std-b67".into());
+        assert_eq!(copy_profile(&clip(hdr), &base), None);
+    }
+
+    #[test]
+    fn test_parse_keyframes() {
+        let output = "0.000000,K__\n0.033367,___\nN/A,K__\n2.002000,K_\n1.001000,K__\n";
+        assert_eq!(parse_keyframes(output), vec![0.0, 1.001, 2.002]);
+    }
+
+    #[test]
+    fn test_plan_cut() {
+        let keyframes = [0.0, 2.0, 4.0, 6.0, 8.0, 10.0];
+        assert_eq!(
+            plan_cut(&keyframes, 1.5, 9.0),
+            Some(Cut {
+                copy_start: 2.0,
+                copy_end: 8.0
+            })
+        );
+        // starting and ending on keyframes means nothing to re-encode
+        assert_eq!(
+            plan_cut(&keyframes, 2.0, 9.9999),
+            Some(Cut {
+                copy_start: 2.0,
+                copy_end: 10.0
+            })
+        );
+        // not enough whole GOPs to bother
+        assert_eq!(plan_cut(&keyframes, 2.5, 5.0), None);
+        assert_eq!(plan_cut(&[], 0.0, 10.0), None);
+    }
+
+    #[test]
+    fn test_measurements_needed() {
+        let base = probe();
+        let preset = EncodingPreset::default();
+        let edges = Edges::default();
+        assert_eq!(
+            measurements_needed(&clip(probe()), &base, &preset, &edges),
+            vec![Measurement::Keyframes]
+        );
+        let normalized = CookClip {
+            loudness: Some(Loudness::default()),
+            texts: vec![TextOverlay::default()],
+            ..clip(probe())
+        };
+        assert_eq!(
+            measurements_needed(&normalized, &base, &preset, &edges),
+            vec![Measurement::Loudness]
+        );
+        // without them, it gets planned as one plain encode
+        let plan = make_clip(
+            &clip(probe()),
+            &base,
+            &preset,
+            "clip0.mp4",
+            &edges,
+            &Measurements::default(),
+        )
+        .unwrap();
+        assert_eq!(plan.steps.len(), 1);
+    }
+
+    #[test]
+    fn test_plan_edges() {
+        let crossfade = |duration| {
+            Some(Transition {
+                kind: TransitionKind::Crossfade,
+                duration,
+            })
+        };
+        let with_transition = |transition| {
+            CookItem::Clip(Box::new(CookClip {
+                transition,
+                ..clip(probe())
+            }))
+        };
+        let mut items = vec![
+            with_transition(crossfade(1.0)),
+            with_transition(None),
+            // the last clip's transition doesn't go anywhere
+            with_transition(crossfade(2.0)),
+        ];
+        assert_eq!(
+            plan_edges(&items).unwrap(),
+            vec![
+                Edges {
+                    lead_in: 0.0,
+                    lead_out: 1.0
+                },
+                Edges {
+                    lead_in: 1.0,
+                    lead_out: 0.0
+                },
+                Edges::default(),
+            ]
+        );
+
+        // clips are 7.5 seconds long
+        items[1] = with_transition(crossfade(6.5));
+        assert!(plan_edges(&items).is_err());
+        items[1] = with_transition(crossfade(0.0));
+        assert!(plan_edges(&items).is_err());
+
+        // title cards can have transitions too
+        let card = CookItem::Card(CookCard {
+            duration: 3.0,
+            background: CardSource::Color(String::from("black")),
+            texts: Vec::new(),
+            transition: crossfade(0.5),
+        });
+        let edges = plan_edges(&[card, with_transition(None)]).unwrap();
+        assert_eq!(edges[0].lead_out, 0.5);
+        assert_eq!(edges[1].lead_in, 0.5);
+    }
+
+    #[test]
+    fn test_transition_filter() {
+        let transition = Transition {
+            kind: TransitionKind::FadeBlack,
+            duration: 0.5,
+        };
+        let filters = transition_filter(&transition, true);
+        assert!(filters.contains("xfade = transition=fadeblack : duration=0.5 : offset=0 [outv]"));
+        assert!(filters.ends_with("acrossfade = d=0.5 [outa]"));
+        assert!(!transition_filter(&transition, false).contains("[outa]"));
+    }
+
+    #[test]
+    fn test_edge_path() {
+        assert_eq!(
+            edge_path("work/1/clip3.mp4", "tail"),
+            PathBuf::from("work/1/clip3.tail.mp4")
+        );
+    }
+
+    #[test]
+    fn test_fit_same_aspect_ratio() {
+        let fit = Fit::Letterbox(LetterboxFit::default());
+        assert_eq!(
+            fit_filter(&fit, (1920, 1080), (1920, 1080), "outv").unwrap(),
+            " [outv]"
+        );
+        assert_eq!(
+            fit_filter(&fit, (1280, 720), (1920, 1080), "outv").unwrap(),
+            ", scale = 1920:1080, setsar = 1 [outv]"
+        );
+    }
+
+    #[test]
+    fn test_fit_blur() {
+        // portrait clip in a landscape video
+        assert_eq!(
+            fit_filter(&Fit::default(), (1080, 1920), (1920, 1080), "outv").unwrap(),
+            ", split [v_trimmed] [v_trimmed_copy]; \
+            [v_trimmed] scale = -1:1080, setsar = 1 [v_scaled]; \
+            [v_trimmed_copy] crop = 1080:607:0:656, \
+            scale = 640:360, gblur = sigma=64, scale = 1920:1080, setsar = 1 [v_blurred]; \
+            [v_blurred] [v_scaled] overlay = (main_w-overlay_w)/2:(main_h-overlay_h)/2 [outv]; "
+        );
+        let fit = Fit::Blur(BlurFit {
+            downscale: 4,
+            blur: 0.05,
+        });
+        let filters = fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").unwrap();
+        assert!(filters.contains("scale = 480:270, gblur = sigma=24,"));
+        let fit = Fit::Blur(BlurFit {
+            downscale: 0,
+            blur: 0.1,
+        });
+        assert!(fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").is_err());
+    }
+
+    #[test]
+    fn test_fit_letterbox() {
+        let fit = Fit::Letterbox(LetterboxFit {
+            color: String::from("#202020"),
+        });
+        assert_eq!(
+            fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").unwrap(),
+            ", scale = 1920:1080 : force_original_aspect_ratio=decrease : force_divisible_by=2\
+            , pad = 1920:1080 : (ow-iw)/2 : (oh-ih)/2 : color=#202020, setsar = 1 [outv]"
+        );
+        let fit = Fit::Letterbox(LetterboxFit {
+            color: String::from("black [outv]; [0:v] null"),
+        });
+        assert!(fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").is_err());
+    }
+
+    #[test]
+    fn test_fit_crop() {
+        assert_eq!(
+            fit_filter(
+                &Fit::Crop(CropFit::default()),
+                (1080, 1920),
+                (1920, 1080),
+                "outv"
+            )
+            .unwrap(),
+            ", scale = 1920:1080 : force_original_aspect_ratio=increase\
+            , crop = 1920:1080 : (iw-ow)*0.5 : (ih-oh)*0.5, setsar = 1 [outv]"
+        );
+        // pan to the top
+        let fit = Fit::Crop(CropFit { x: 0.5, y: 0.0 });
+        let filters = fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").unwrap();
+        assert!(filters.contains("crop = 1920:1080 : (iw-ow)*0.5 : (ih-oh)*0,"));
+        let fit = Fit::Crop(CropFit { x: 1.5, y: 0.5 });
+        assert!(fit_filter(&fit, (1080, 1920), (1920, 1080), "outv").is_err());
+    }
+
+    #[test]
+    fn test_text_filter() {
+        let texts = [
+            TextOverlay {
+                text: String::from("day 1"),
+                start: 1.0,
+                end: Some(3.5),
+                position: TextPosition::Top,
+                ..TextOverlay::default()
+            },
+            TextOverlay {
+                text: String::from("100% real"),
+                ..TextOverlay::default()
+            },
+        ];
+        let files = [
+            PathBuf::from("w/clip0.text0.txt"),
+            PathBuf::from("w/clip0.text1.txt"),
+        ];
+        assert_eq!(
+            text_filter(&texts, &files, 5.0, "font=Sans", "fitted").unwrap(),
+            "; [fitted] drawtext = textfile='w/clip0.text0.txt' : expansion=none : font=Sans : \
+            fontsize=h*0.06 : fontcolor=white : borderw=2 : bordercolor=black@0.6 : \
+            x=(w-text_w)/2 : y=h*0.05 : enable='between(t,1,3.5)', \
+            drawtext = textfile='w/clip0.text1.txt' : expansion=none : font=Sans : \
+            fontsize=h*0.06 : fontcolor=white : borderw=2 : bordercolor=black@0.6 : \
+            x=(w-text_w)/2 : y=h*0.95-text_h : enable='between(t,0,5)' [outv]"
+        );
+
+        let bad = [TextOverlay {
+            text: String::from("hi"),
+            start: 4.0,
+            end: Some(2.0),
+            ..TextOverlay::default()
+        }];
+        assert!(text_filter(&bad, &files, 5.0, "font=Sans", "fitted").is_err());
+        let bad = [TextOverlay {
+            text: String::from("hi"),
+            color: String::from("red'"),
+            ..TextOverlay::default()
+        }];
+        assert!(text_filter(&bad, &files, 5.0, "font=Sans", "fitted").is_err());
+    }
+
+    #[test]
+    fn test_fit_label() {
+        let filters = fit_filter(
+            &Fit::Crop(CropFit::default()),
+            (1080, 1920),
+            (1920, 1080),
+            "fitted",
+        )
+        .unwrap();
+        assert!(filters.ends_with("setsar = 1 [fitted]"));
+    }
+
+    #[test]
+    fn test_audio_filter() {
+        let base = probe();
+        let plain = audio_filter(&clip(probe()), &base, 0, None).unwrap();
+        assert_eq!(
+            plain,
+            "[0:a] atrim = start=1.5 : end=9, asetpts=PTS-STARTPTS [outa]"
+        );
+
+        let with_audio = |audio: ClipAudio| CookClip {
+            audio,
+            ..clip(probe())
+        };
+        let filter = audio_filter(
+            &with_audio(ClipAudio {
+                volume: 0.5,
+                fade_in: 1.0,
+                fade_out: 2.0,
+                ..ClipAudio::default()
+            }),
+            &base,
+            1,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            filter,
+            "[1:a] atrim = start=1.5 : end=9, asetpts=PTS-STARTPTS, volume = 0.5, \
+            afade = t=in : d=1, afade = t=out : st=5.5 : d=2 [outa]"
+        );
+
+        let muted = audio_filter(
+            &with_audio(ClipAudio {
+                mute: true,
+                ..ClipAudio::default()
+            }),
+            &base,
+            0,
+            None,
+        )
+        .unwrap();
+        assert!(muted.starts_with("anullsrc"));
+
+        for audio in [
+            ClipAudio {
+                volume: -1.0,
+                ..ClipAudio::default()
+            },
+            ClipAudio {
+                fade_in: 5.0,
+                fade_out: 5.0,
+                ..ClipAudio::default()
+            },
+            ClipAudio {
+                fade_out: f64::NAN,
+                ..ClipAudio::default()
+            },
+        ] {
+            assert!(audio_filter(&with_audio(audio), &base, 0, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_music_filter() {
+        let music = CookMusic {
+            path: "music.mp3".into(),
+            start: 10.0,
+            volume: 0.3,
+            repeat: true,
+            fade_out: 2.0,
+        };
+        assert_eq!(
+            music_filter(&music, true, 30.0).unwrap(),
+            "[1:a] atrim = start=10, asetpts=PTS-STARTPTS, volume = 0.3, apad, \
+            atrim = end=30, afade = t=out : st=28 : d=2 [music]; \
+            [0:a][music] amix = inputs=2 : duration=first : normalize=0 [outa]"
+        );
+        // fade is cut short if the video is
+        assert_eq!(
+            music_filter(&music, false, 1.0).unwrap(),
+            "[1:a] atrim = start=10, asetpts=PTS-STARTPTS, volume = 0.3, apad, \
+            atrim = end=1, afade = t=out : st=0 : d=1 [outa]"
+        );
+        let loud = CookMusic {
+            volume: f64::INFINITY,
+            ..music
+        };
+        assert!(music_filter(&loud, true, 30.0).is_err());
+    }
+
+    #[test]
+    fn test_parse_loudnorm() {
+        let log = "[Parsed_loudnorm_2 @ 0x7f8b4c004a00] \n\
+            {\n\
+            \t\"input_i\" : \"-27.61\",\n\
+            \t\"input_tp\" : \"-4.47\",\n\
+            \t\"input_lra\" : \"18.06\",\n\
+            \t\"input_thresh\" : \"-39.20\",\n\
+            \t\"output_i\" : \"-16.58\",\n\
+            \t\"output_tp\" : \"-1.50\",\n\
+            \t\"output_lra\" : \"14.78\",\n\
+            \t\"output_thresh\" : \"-27.71\",\n\
+            \t\"normalization_type\" : \"dynamic\",\n\
+            \t\"target_offset\" : \"0.58\"\n\
+            }\n";
+        assert_eq!(
+            parse_loudnorm(log).unwrap(),
+            Some(LoudnessMeasurement {
+                integrated: -27.61,
+                true_peak: -4.47,
+                range: 18.06,
+                threshold: -39.2,
+                offset: 0.58,
+            })
+        );
+        // silence
+        let silent = log
+            .replace("\"-27.61\"", "\"-inf\"")
+            .replace("\"-4.47\"", "\"-inf\"");
+        assert_eq!(parse_loudnorm(&silent).unwrap(), None);
+        assert!(parse_loudnorm("Conversion failed!").is_err());
+    }
+
+    #[test]
+    fn test_loudnorm_filter() {
+        let base = probe();
+        let measured = LoudnessMeasurement {
+            integrated: -27.61,
+            true_peak: -4.47,
+            range: 18.06,
+            threshold: -39.2,
+            offset: 0.58,
+        };
+        let normalized = CookClip {
+            loudness: Some(Loudness::default()),
+            ..clip(probe())
+        };
+        assert_eq!(
+            audio_filter(&normalized, &base, 0, Some(&measured)).unwrap(),
+            "[0:a] atrim = start=1.5 : end=9, asetpts=PTS-STARTPTS, \
+            loudnorm = I=-16 : TP=-1.5 : LRA=11 : measured_I=-27.61 : measured_TP=-4.47 : \
+            measured_LRA=18.06 : measured_thresh=-39.2 : offset=0.58 : linear=true, \
+            aresample = 48000 [outa]"
+        );
+        // silent clips don't get measured
+        assert_eq!(
+            audio_filter(&normalized, &base, 0, None).unwrap(),
+            "[0:a] atrim = start=1.5 : end=9, asetpts=PTS-STARTPTS [outa]"
+        );
+        let quiet = Loudness {
+            target: -80.0,
+            ..Loudness::default()
+        };
+        assert!(loudnorm(&quiet).is_err());
+    }
+
+    #[test]
+    fn test_playback() {
+        assert_eq!(atempo_factors(1.5), vec![1.5]);
+        assert_eq!(atempo_factors(4.0), vec![2.0, 2.0]);
+        assert_eq!(atempo_factors(3.0), vec![2.0, 1.5]);
+        assert_eq!(atempo_factors(0.25), vec![0.5, 0.5]);
+        assert_eq!(atempo_factors(0.3), vec![0.5, 0.6]);
+
+        let playback = Playback {
+            speed: 4.0,
+            reverse: true,
+        };
+        assert_eq!(
+            playback_filter(&playback, false).unwrap(),
+            ", reverse, setpts=PTS/4"
+        );
+        assert_eq!(
+            playback_filter(&playback, true).unwrap(),
+            ", areverse, atempo = 2, atempo = 2"
+        );
+        assert_eq!(playback_filter(&Playback::default(), true).unwrap(), "");
+        for speed in [0.1, 5.0, f64::NAN] {
+            let playback = Playback {
+                speed,
+                reverse: false,
+            };
+            assert!(playback_filter(&playback, false).is_err());
+        }
+
+        // half speed, so the 7.5 second clip takes 15 seconds
+        let slow = CookClip {
+            playback: Playback {
+                speed: 0.5,
+                reverse: false,
+            },
+            audio: ClipAudio {
+                fade_out: 1.0,
+                ..ClipAudio::default()
+            },
+            ..clip(probe())
+        };
+        assert_eq!(slow.duration(), 15.0);
+        assert_eq!(
+            audio_filter(&slow, &probe(), 0, None).unwrap(),
+            "[0:a] atrim = start=1.5 : end=9, asetpts=PTS-STARTPTS, atempo = 0.5, \
+            afade = t=out : st=14 : d=1 [outa]"
+        );
+        assert_eq!(copy_profile(&slow, &probe()), None);
+    }
+}
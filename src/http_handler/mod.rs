@@ -7,8 +7,8 @@ use crate::{
         handlers::{
             cook, delete_videos,
             edit_video_metadata::{self, ReqType},
-            move_to_downloads, probe_videos, rename_tag, restore_from_downloads, serve_thumbnail,
-            serve_video,
+            jobs, probe_videos, projects, rename_tag, restore_from_downloads, serve_thumbnail,
+            serve_video, stow, sync_videos, trash, undo,
         },
         util::{
             CORS, MyResponse, Req, build_html_response, build_json_response, build_text_response,
@@ -17,10 +17,10 @@ use crate::{
     },
 };
 
-mod defs;
-mod handlers;
-mod make_filter;
-mod probe;
+pub mod defs;
+pub mod handlers;
+pub mod make_filter;
+pub mod probe;
 mod util;
 
 async fn handle_request(req: Req, state: SharedState) -> MyResponse {
@@ -57,16 +57,56 @@ async fn handle_request(req: Req, state: SharedState) -> MyResponse {
                     .boxed(),
             )?),
         (&Method::GET, "/list") => build_json_response(&*state.read().await),
-        (&Method::POST, "/for-youtube") => move_to_downloads::handle(req, state).await,
+        (&Method::POST, "/for-youtube") => stow::handle_for_youtube(req, state).await,
+        (&Method::POST, "/stow") => stow::handle(req, state).await,
+        (&Method::GET, "/stow") => stow::list(state).await,
         (&Method::POST, "/restore") => restore_from_downloads::handle(req, state).await,
         (&Method::DELETE, "/videos") => delete_videos::handle(req, state).await,
+        (&Method::POST, "/trash/restore") => trash::restore(req, state).await,
+        (&Method::DELETE, "/trash") => trash::empty(state).await,
         (&Method::POST, "/tag/rename") => rename_tag::handle(req, state).await,
         (&Method::POST, "/preview") => probe_videos::handle(req, state).await,
         (&Method::POST, "/cook") => cook::handle(req, state).await,
+        (&Method::POST, "/cook/plan") => cook::plan(req, state).await,
+        (&Method::GET, "/presets") => cook::presets().await,
+        (&Method::POST, "/sync") => sync_videos::handle(req, state).await,
+        (&Method::GET, "/jobs") => jobs::list(state).await,
+        (method @ (&Method::GET | &Method::DELETE), path) if path.starts_with("/jobs/") => {
+            let rest = &path["/jobs/".len()..];
+            match (method, rest.strip_suffix("/log")) {
+                (&Method::GET, Some(id)) => jobs::log(state, id).await,
+                (&Method::GET, None) => jobs::get(state, rest).await,
+                (&Method::DELETE, None) => jobs::cancel(state, rest).await,
+                _ => build_text_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    format!("Method {method} not supported at {path}."),
+                ),
+            }
+        }
+        (&Method::GET, "/projects") => projects::list(state).await,
+        (&Method::POST, "/projects") => projects::create(req, state).await,
+        (method, path) if path.starts_with("/projects/") => {
+            // owned so `req` can be moved into the handler
+            let id = path["/projects/".len()..].to_string();
+            match *method {
+                Method::GET => projects::get(state, &id).await,
+                Method::PUT => projects::update(req, state, &id).await,
+                Method::DELETE => projects::delete(state, &id).await,
+                _ => build_text_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    format!("Method {method} not supported at {path}."),
+                ),
+            }
+        }
+        (&Method::POST, "/undo") => undo::handle(state, false).await,
+        (&Method::POST, "/redo") => undo::handle(state, true).await,
         (&Method::OPTIONS, _) => Ok(Response::builder()
             .status(StatusCode::NO_CONTENT)
             .header("Access-Control-Allow-Origin", CORS)
-            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS, DELETE")
+            .header(
+                "Access-Control-Allow-Methods",
+                "GET, POST, PUT, OPTIONS, DELETE",
+            )
             .header("Access-Control-Allow-Headers", "Content-Type")
             .body(Full::new(Bytes::new()).map_err(|e| match e {}).boxed())?),
         (&Method::POST, path)
@@ -81,7 +121,9 @@ async fn handle_request(req: Req, state: SharedState) -> MyResponse {
             edit_video_metadata::handle(req, state, req_type).await
         }
         (&Method::GET, path) if path.starts_with("/v/") => serve_video::handle(req, state).await,
-        (&Method::GET, path) if path.starts_with("/t/") => serve_thumbnail::handle(req).await,
+        (&Method::GET, path) if path.starts_with("/t/") => {
+            serve_thumbnail::handle(req, state).await
+        }
         (&Method::GET, path) => build_html_response(
             StatusCode::NOT_FOUND,
             include_str!("../static/404.html").replace("{PATH}", &escape_html(path)),
@@ -2,18 +2,125 @@ use std::path::PathBuf;
 
 use serde::Deserialize;
 
-use crate::common::{ProbeResult, Rotation};
+use crate::{
+    common::{ProbeResult, Rotation},
+    http_handler::defs::{ClipAudio, Fit, Loudness, Playback, TextOverlay, Transition},
+};
 
 pub struct CookClip {
+    /// the source video's ID
+    pub id: String,
     pub video_path: PathBuf,
     pub probe: ProbeResult,
     pub start: f64,
     pub end: f64,
     pub override_rotation: Option<Rotation>,
+    /// into the next clip
+    pub transition: Option<Transition>,
+    pub fit: Fit,
+    pub texts: Vec<TextOverlay>,
+    pub audio: ClipAudio,
+    /// normalizes the audio to this if set
+    pub loudness: Option<Loudness>,
+    pub playback: Playback,
+}
+
+impl CookClip {
+    /// How long the clip is in the output, in seconds, after it's sped up or
+    /// slowed down.
+    pub fn duration(&self) -> f64 {
+        (self.end - self.start) / self.playback.speed
+    }
+}
+
+/// [`Music`](crate::http_handler::defs::Music) with the video found.
+pub struct CookMusic {
+    pub path: PathBuf,
+    pub start: f64,
+    pub volume: f64,
+    pub repeat: bool,
+    pub fade_out: f64,
+}
+
+#[derive(Debug)]
+pub enum CardSource {
+    Color(String),
+    /// blurred frame from a video, `time` seconds in
+    Frame {
+        video_path: PathBuf,
+        time: f64,
+    },
+}
+
+pub struct CookCard {
+    pub duration: f64,
+    pub background: CardSource,
+    pub texts: Vec<TextOverlay>,
+    /// into the next clip
+    pub transition: Option<Transition>,
+}
+
+/// Something that goes in the cooked video.
+pub enum CookItem {
+    Clip(Box<CookClip>),
+    Card(CookCard),
+}
+
+impl CookItem {
+    /// How long it is in the output, in seconds.
+    pub fn duration(&self) -> f64 {
+        match self {
+            CookItem::Clip(clip) => clip.duration(),
+            CookItem::Card(card) => card.duration,
+        }
+    }
+
+    pub fn transition(&self) -> Option<Transition> {
+        match self {
+            CookItem::Clip(clip) => clip.transition,
+            CookItem::Card(card) => card.transition,
+        }
+    }
+
+    /// Everything that changes how it renders on its own, for the segment
+    /// cache. Transitions are left out since they only affect the edges, which
+    /// get keyed separately.
+    pub fn describe(&self) -> String {
+        match self {
+            CookItem::Clip(clip) => {
+                // destructured so new fields can't be forgotten
+                let CookClip {
+                    id,
+                    video_path: _,
+                    probe,
+                    start,
+                    end,
+                    override_rotation,
+                    transition: _,
+                    fit,
+                    texts,
+                    audio,
+                    loudness,
+                    playback,
+                } = clip.as_ref();
+                format!(
+                    "clip {id:?} {probe:?} {start} {end} {override_rotation:?} {fit:?} {texts:?} {audio:?} {loudness:?} {playback:?}"
+                )
+            }
+            CookItem::Card(CookCard {
+                duration,
+                background,
+                texts,
+                transition: _,
+            }) => format!("card {duration} {background:?} {texts:?}"),
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct FfprobeVideoStream {
+    pub codec_name: Option<String>,
+    pub profile: Option<String>,
     pub width: u32,
     pub height: u32,
     pub pix_fmt: String,
@@ -52,3 +159,23 @@ pub struct FfprobeAudioStream {
 pub struct FfprobeAudio {
     pub streams: Vec<FfprobeAudioStream>,
 }
+
+/// What the first `loudnorm` pass prints. The numbers are all strings.
+#[derive(Deserialize, Debug)]
+pub struct LoudnormStats {
+    pub input_i: String,
+    pub input_tp: String,
+    pub input_lra: String,
+    pub input_thresh: String,
+    pub target_offset: String,
+}
+
+/// How loud a clip is, for the second `loudnorm` pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoudnessMeasurement {
+    pub integrated: f64,
+    pub true_peak: f64,
+    pub range: f64,
+    pub threshold: f64,
+    pub offset: f64,
+}
@@ -5,13 +5,14 @@ use tokio::process::Command;
 
 use crate::{
     common::{AudioProbeResult, ProbeResult},
+    config::Encoders,
     http_handler::probe::defs::{FfprobeAudio, FfprobeVideo, FfprobeVideoStreamSideData},
     util::MyResult,
 };
 
 pub mod defs;
 
-static ENTRIES: &str = "stream=pix_fmt,width,height,bit_rate,color_space,color_transfer,color_primaries:format=duration:stream_side_data=rotation";
+static ENTRIES: &str = "stream=codec_name,profile,pix_fmt,width,height,bit_rate,color_space,color_transfer,color_primaries:format=duration:stream_side_data=rotation";
 
 pub async fn probe_video(path: &PathBuf) -> MyResult<ProbeResult> {
     let ffprobe_result = Command::new("ffprobe")
@@ -88,6 +89,8 @@ pub async fn probe_video(path: &PathBuf) -> MyResult<ProbeResult> {
         color_space: ffprobe_output.streams.0.color_space,
         color_transfer: ffprobe_output.streams.0.color_transfer,
         pix_fmt: ffprobe_output.streams.0.pix_fmt,
+        codec: ffprobe_output.streams.0.codec_name,
+        profile: ffprobe_output.streams.0.profile,
         audio: ffprobe_output_audio
             .streams
             .pop()
@@ -102,3 +105,68 @@ pub async fn probe_video(path: &PathBuf) -> MyResult<ProbeResult> {
             .transpose()?,
     })
 }
+
+/// Lists the encoders the local ffmpeg was built with.
+pub async fn probe_encoders() -> MyResult<Encoders> {
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-encoders")
+        .output()
+        .await?;
+    if !output.status.success() {
+        Err(format!(
+            "ffmpeg encoders error:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ))?;
+    }
+    Ok(parse_encoders(&String::from_utf8(output.stdout)?))
+}
+
+/// Reads the list after the `------` line, where each line is the flags
+/// (starting with `V` for video or `A` for audio), the name, and a description.
+fn parse_encoders(output: &str) -> Encoders {
+    let mut encoders = Encoders::default();
+    let list = output
+        .lines()
+        .skip_while(|line| !line.trim().starts_with("---"))
+        .skip(1);
+    for line in list {
+        let mut words = line.split_whitespace();
+        let (Some(flags), Some(name)) = (words.next(), words.next()) else {
+            continue;
+        };
+        match flags.chars().next() {
+            Some('V') => encoders.video.push(name.to_string()),
+            Some('A') => encoders.audio.push(name.to_string()),
+            _ => {}
+        }
+    }
+    encoders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_encoders() {
+        let output = "Encoders:
+ V..... = Video
+ A..... = Audio
+ S..... = Subtitle
+ .F.... = Frame-level multithreading
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+ V....D h264_mediacodec      H.264 Android MediaCodec encoder (codec h264)
+ A....D aac                  AAC (Advanced Audio Coding)
+ S..... srt                  SubRip subtitle
+";
+        assert_eq!(
+            parse_encoders(output),
+            Encoders {
+                video: vec![String::from("libx264"), String::from("h264_mediacodec")],
+                audio: vec![String::from("aac")],
+            }
+        );
+    }
+}
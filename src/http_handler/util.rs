@@ -1,8 +1,16 @@
 use http_body_util::{BodyExt, Full, combinators::BoxBody};
-use hyper::{Request, Response, StatusCode, body::Bytes};
-use serde::Serialize;
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Buf, Bytes},
+};
+use serde::{Serialize, de::DeserializeOwned};
 
-use crate::util::MyResult;
+use crate::{
+    common::SharedState,
+    http_handler::defs::{InvalidRequest, PartialFailure, Problem},
+    journal::VideoFailure,
+    util::MyResult,
+};
 
 pub const CORS: &str = "http://127.0.0.1:8000";
 
@@ -33,8 +41,13 @@ pub fn build_html_response(status: StatusCode, message: String) -> MyResponse {
 }
 
 pub fn build_json_response<T: Serialize>(object: &T) -> MyResponse {
+    build_json_error(StatusCode::OK, object)
+}
+
+/// JSON with a status other than 200, usually an error.
+pub fn build_json_error<T: Serialize>(status: StatusCode, object: &T) -> MyResponse {
     Ok(Response::builder()
-        .status(StatusCode::OK)
+        .status(status)
         .header("Content-Type", "application/json")
         .header("Access-Control-Allow-Origin", CORS)
         .body(
@@ -43,3 +56,29 @@ pub fn build_json_response<T: Serialize>(object: &T) -> MyResponse {
                 .boxed(),
         )?)
 }
+
+/// Parses a JSON body. If it doesn't parse, returns a 400 response saying why
+/// instead.
+pub async fn read_json<T: DeserializeOwned>(req: Req) -> MyResult<Result<T, MyResponse>> {
+    let body = req.collect().await?.aggregate();
+    Ok(serde_json::from_reader(body.reader()).map_err(|err| {
+        build_json_error(
+            StatusCode::BAD_REQUEST,
+            &InvalidRequest::new(vec![Problem::new("", err.to_string())]),
+        )
+    }))
+}
+
+/// Responds with the state, or if some videos couldn't be changed, a 500 that
+/// lists them along with the state, since the rest did change.
+pub async fn build_state_response(state: &SharedState, failed: Vec<VideoFailure>) -> MyResponse {
+    let state = state.read().await;
+    if failed.is_empty() {
+        build_json_response(&*state)
+    } else {
+        build_json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &PartialFailure::new(failed, &state),
+        )
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hyper::body::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    progress::Progress,
+    util::{MyResult, write_atomic},
+};
+
+/// Records of finished jobs past this many get deleted.
+const MAX_JOB_RECORDS: usize = 50;
+
+/// How much of a running job's log is kept in memory for people attaching
+/// late. The whole log is in the file.
+const MAX_LOG_TAIL: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// What gets saved to `jobs/{id}.json`. The log goes in `jobs/{id}.log`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRecord {
+    pub id: String,
+    /// what the job is making, e.g. the output file name
+    pub name: String,
+    pub status: JobStatus,
+    pub started: SystemTime,
+    pub finished: Option<SystemTime>,
+    /// the file the job made, if it succeeded
+    pub output: Option<PathBuf>,
+    /// ID of the project that was cooked, if it was a saved one
+    #[serde(default)]
+    pub project: Option<String>,
+    pub error: Option<String>,
+}
+
+/// One line of a job's log, which is newline-delimited JSON.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent<'a> {
+    /// output meant for people, like ffmpeg errors
+    Log {
+        line: &'a str,
+    },
+    Progress(&'a Progress),
+    /// the last line, with how the job went
+    Done(&'a JobRecord),
+}
+
+/// The end of the log so far, plus a way to follow new output. Lines are sent
+/// to [`write_log`] as they come, so the log survives the server stopping.
+/// `writer` and `sender` are gone once the job finishes.
+struct LiveLog {
+    tail: Vec<u8>,
+    writer: Option<mpsc::UnboundedSender<Bytes>>,
+    /// the [`write_log`] task, once the file's open
+    writing: Option<JoinHandle<()>>,
+    sender: Option<broadcast::Sender<Bytes>>,
+}
+
+impl LiveLog {
+    fn append(&mut self, text: &Bytes) {
+        if let Some(writer) = &self.writer {
+            // the writer only stops if the file can't be written to, which it
+            // already complained about
+            let _ = writer.send(text.clone());
+        }
+        self.tail.extend_from_slice(text);
+        if self.tail.len() > MAX_LOG_TAIL {
+            // only keep whole lines
+            let excess = self.tail.len() - MAX_LOG_TAIL;
+            let cut = self.tail[excess..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(self.tail.len(), |i| excess + i + 1);
+            self.tail.drain(..cut);
+        }
+    }
+}
+
+/// Appends lines to the job's log file until the job finishes.
+async fn write_log(mut file: fs::File, mut receiver: mpsc::UnboundedReceiver<Bytes>) {
+    while let Some(text) = receiver.recv().await {
+        // tokio writes in the background until flushed
+        if let Err(err) = async {
+            file.write_all(&text).await?;
+            file.flush().await
+        }
+        .await
+        {
+            eprintln!("[job] Failed to write to the log, so the rest won't be saved: {err}");
+            return;
+        }
+    }
+}
+
+/// A running job. Work being done for the job should log through it and stop
+/// when it's cancelled.
+pub struct Job {
+    pub id: String,
+    record: Mutex<JobRecord>,
+    log: Mutex<LiveLog>,
+    cancel: CancellationToken,
+}
+
+impl Job {
+    /// Adds an event to the log and sends it to anyone following along.
+    fn emit(&self, event: &JobEvent) {
+        let mut text = match serde_json::to_vec(event) {
+            Ok(text) => text,
+            Err(err) => {
+                eprintln!("[job] Failed to serialize {event:?}: {err}");
+                return;
+            }
+        };
+        text.push(b'\n');
+        let text = Bytes::from(text);
+        let mut log = self.log.lock().unwrap_or_else(|err| err.into_inner());
+        log.append(&text);
+        if let Some(sender) = &log.sender {
+            // no one listening is fine
+            let _ = sender.send(text);
+        }
+    }
+
+    pub fn log(&self, line: &str) {
+        self.emit(&JobEvent::Log { line });
+    }
+
+    pub fn progress(&self, progress: &Progress) {
+        self.emit(&JobEvent::Progress(progress));
+    }
+
+    /// Returns the end of the log so far and, if the job is still running, a
+    /// receiver for the rest. Taken together so no output gets missed or
+    /// repeated.
+    pub fn attach(&self) -> (Bytes, Option<broadcast::Receiver<Bytes>>) {
+        let log = self.log.lock().unwrap_or_else(|err| err.into_inner());
+        (
+            Bytes::copy_from_slice(&log.tail),
+            log.sender.as_ref().map(|sender| sender.subscribe()),
+        )
+    }
+
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn record(&self) -> JobRecord {
+        self.record
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
+
+/// Keeps track of running jobs and the records of past ones.
+pub struct Jobs {
+    dir: PathBuf,
+    running: Mutex<HashMap<String, Arc<Job>>>,
+}
+
+/// Job IDs end up in file paths, so only allow what [`Jobs::start`] makes.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+impl Jobs {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn running(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<Job>>> {
+        self.running.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+
+    fn log_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.log"))
+    }
+
+    async fn save_record(&self, record: &JobRecord) -> MyResult<()> {
+        write_atomic(&self.record_path(&record.id), &serde_json::to_vec(record)?).await
+    }
+
+    /// Starts tracking a job making `name`. Returns `None` if another running
+    /// job is already making it. Checked and reserved together so two jobs can't
+    /// both take the name.
+    pub async fn start(&self, name: String, project: Option<String>) -> MyResult<Option<Arc<Job>>> {
+        fs::create_dir_all(&self.dir).await?;
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+        let (sender, _) = broadcast::channel(1024);
+        let (writer, receiver) = mpsc::unbounded_channel();
+        let job = {
+            let mut running = self.running();
+            if running.values().any(|job| job.record().name == name) {
+                return Ok(None);
+            }
+            let mut id = millis.to_string();
+            let mut n = 1;
+            while running.contains_key(&id) {
+                n += 1;
+                id = format!("{millis}-{n}");
+            }
+            let job = Arc::new(Job {
+                id: id.clone(),
+                record: Mutex::new(JobRecord {
+                    id: id.clone(),
+                    name,
+                    status: JobStatus::Running,
+                    started: SystemTime::now(),
+                    finished: None,
+                    output: None,
+                    project,
+                    error: None,
+                }),
+                log: Mutex::new(LiveLog {
+                    tail: Vec::new(),
+                    writer: Some(writer),
+                    writing: None,
+                    sender: Some(sender),
+                }),
+                cancel: CancellationToken::new(),
+            });
+            running.insert(id, job.clone());
+            job
+        };
+        let opened = async {
+            self.save_record(&job.record()).await?;
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.log_path(&job.id))
+                .await?;
+            MyResult::Ok(file)
+        }
+        .await;
+        match opened {
+            Ok(file) => {
+                job.log
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .writing = Some(tokio::spawn(write_log(file, receiver)));
+            }
+            Err(err) => {
+                self.running().remove(&job.id);
+                return Err(err);
+            }
+        }
+        self.prune().await?;
+        Ok(Some(job))
+    }
+
+    /// Saves how the job went, and stops tracking it as running.
+    pub async fn finish(&self, job: &Job, result: MyResult<PathBuf>) -> MyResult<()> {
+        let record = {
+            let mut record = job.record.lock().unwrap_or_else(|err| err.into_inner());
+            record.finished = Some(SystemTime::now());
+            match result {
+                Ok(output) => {
+                    record.status = JobStatus::Succeeded;
+                    record.output = Some(output);
+                }
+                Err(_) if job.cancel.is_cancelled() => record.status = JobStatus::Cancelled,
+                Err(err) => {
+                    record.status = JobStatus::Failed;
+                    record.error = Some(err.to_string());
+                }
+            }
+            record.clone()
+        };
+        job.emit(&JobEvent::Done(&record));
+        let writing = {
+            let mut log = job.log.lock().unwrap_or_else(|err| err.into_inner());
+            // closes the stream for anyone attached, and lets the writer finish
+            log.sender = None;
+            log.writer = None;
+            log.writing.take()
+        };
+        if let Some(writing) = writing
+            && let Err(err) = writing.await
+        {
+            eprintln!("[job] Log writer for {} died: {err}", job.id);
+        }
+        let saved = self.save_record(&record).await;
+        self.running().remove(&job.id);
+        saved
+    }
+
+    pub fn get_running(&self, id: &str) -> Option<Arc<Job>> {
+        self.running().get(id).cloned()
+    }
+
+    /// Returns whether the job was running.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.get_running(id) {
+            Some(job) => {
+                job.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> MyResult<Option<JobRecord>> {
+        if let Some(job) = self.get_running(id) {
+            return Ok(Some(job.record()));
+        }
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.record_path(id)).await {
+            Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    /// The saved log of a job. Running jobs have the log so far.
+    pub async fn saved_log(&self, id: &str) -> MyResult<Option<Vec<u8>>> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        match fs::read(self.log_path(id)).await {
+            Ok(log) => Ok(Some(log)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    /// All job records, newest first.
+    pub async fn list(&self) -> MyResult<Vec<JobRecord>> {
+        let mut records = Vec::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(records),
+            Err(err) => Err(err)?,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+            else {
+                continue;
+            };
+            // running jobs have the latest info in memory
+            if let Some(record) = self.get(&id).await? {
+                records.push(record);
+            }
+        }
+        records.sort_by_key(|record| std::cmp::Reverse(record.started));
+        Ok(records)
+    }
+
+    /// Deletes the oldest finished job records past [`MAX_JOB_RECORDS`].
+    async fn prune(&self) -> MyResult<()> {
+        let records = self.list().await?;
+        for record in records.iter().skip(MAX_JOB_RECORDS) {
+            if record.status == JobStatus::Running {
+                continue;
+            }
+            let _ = fs::remove_file(self.record_path(&record.id)).await;
+            let _ = fs::remove_file(self.log_path(&record.id)).await;
+        }
+        Ok(())
+    }
+
+    /// Jobs that were running when the server last stopped didn't finish, so
+    /// this marks them as failed. Only call on startup.
+    pub async fn mark_interrupted(&self) -> MyResult<()> {
+        for mut record in self.list().await? {
+            if record.status != JobStatus::Running || self.get_running(&record.id).is_some() {
+                continue;
+            }
+            record.status = JobStatus::Failed;
+            record.error = Some(String::from("The server stopped before the job finished."));
+            self.save_record(&record).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let dir = tempdir().unwrap();
+        let jobs = Jobs::new(dir.path().join("jobs"));
+        let job = jobs
+            .start("video-sort-beach".into(), Some("p1".into()))
+            .await
+            .unwrap()
+            .unwrap();
+        job.log("before attaching");
+        // lines are saved as they come, by another task
+        let mut log = Vec::new();
+        for _ in 0..100 {
+            log = jobs.saved_log(&job.id).await.unwrap().unwrap();
+            if !log.is_empty() {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            &log[..],
+            b"{\"type\":\"log\",\"line\":\"before attaching\"}\n"
+        );
+        let (so_far, receiver) = job.attach();
+        assert_eq!(
+            &so_far[..],
+            b"{\"type\":\"log\",\"line\":\"before attaching\"}\n"
+        );
+        let mut receiver = receiver.unwrap();
+        job.log("after attaching");
+        assert_eq!(
+            &receiver.recv().await.unwrap()[..],
+            b"{\"type\":\"log\",\"line\":\"after attaching\"}\n"
+        );
+        assert_eq!(
+            jobs.get(&job.id).await.unwrap().unwrap().status,
+            JobStatus::Running
+        );
+
+        jobs.finish(&job, Err("ffmpeg exploded".into()))
+            .await
+            .unwrap();
+        // finishing logs the record then closes the stream
+        let done = receiver.recv().await.unwrap();
+        let done: serde_json::Value = serde_json::from_slice(&done).unwrap();
+        assert_eq!(done["type"], "done");
+        assert_eq!(done["status"], "Failed");
+        assert!(receiver.recv().await.is_err());
+        assert!(jobs.get_running(&job.id).is_none());
+        let record = jobs.get(&job.id).await.unwrap().unwrap();
+        assert_eq!(record.status, JobStatus::Failed);
+        assert_eq!(record.error.as_deref(), Some("ffmpeg exploded"));
+        assert_eq!(record.project.as_deref(), Some("p1"));
+        let log = String::from_utf8(jobs.saved_log(&job.id).await.unwrap().unwrap()).unwrap();
+        assert_eq!(log.lines().count(), 3);
+        assert!(log.starts_with("{\"type\":\"log\",\"line\":\"before attaching\"}\n"));
+        assert!(dir.path().join(format!("jobs/{}.log", job.id)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_interrupted() {
+        let dir = tempdir().unwrap();
+        let jobs = Jobs::new(dir.path().join("jobs"));
+        let job = jobs.start("a".into(), None).await.unwrap().unwrap();
+        assert!(jobs.cancel(&job.id));
+        assert!(job.cancellation().is_cancelled());
+        // still running until it's finished
+        assert!(jobs.start("a".into(), None).await.unwrap().is_none());
+        jobs.finish(&job, Err("killed".into())).await.unwrap();
+        assert_eq!(
+            jobs.get(&job.id).await.unwrap().unwrap().status,
+            JobStatus::Cancelled
+        );
+        assert!(!jobs.cancel(&job.id));
+
+        // a job left running by a previous server
+        let stale = jobs.start("b".into(), None).await.unwrap().unwrap();
+        let restarted = Jobs::new(dir.path().join("jobs"));
+        restarted.mark_interrupted().await.unwrap();
+        let record = restarted.get(&stale.id).await.unwrap().unwrap();
+        assert_eq!(record.status, JobStatus::Failed);
+        assert_eq!(restarted.list().await.unwrap().len(), 2);
+
+        assert!(jobs.get("../state").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_log_tail_is_bounded() {
+        let dir = tempdir().unwrap();
+        let jobs = Jobs::new(dir.path().join("jobs"));
+        let job = jobs.start("a".into(), None).await.unwrap().unwrap();
+        let line = "x".repeat(1000);
+        for _ in 0..200 {
+            job.log(&line);
+        }
+        let (tail, _) = job.attach();
+        assert!(tail.len() <= MAX_LOG_TAIL);
+        assert!(tail.starts_with(b"{\"type\":\"log\""));
+        jobs.finish(&job, Err("killed".into())).await.unwrap();
+        // plus the done line
+        let log = jobs.saved_log(&job.id).await.unwrap().unwrap();
+        assert_eq!(log.split(|&byte| byte == b'\n').count(), 202);
+    }
+}
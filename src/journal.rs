@@ -0,0 +1,541 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    common::{AppState, Video},
+    util::{MyResult, write_atomic},
+};
+
+/// Older operations get forgotten past this.
+const MAX_OPERATIONS: usize = 100;
+
+/// How a single video changed in an operation. `None` means the video wasn't
+/// tracked on that side.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VideoChange {
+    pub id: String,
+    pub before: Option<Video>,
+    pub after: Option<Video>,
+}
+
+/// Something done through the app that can be undone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Operation {
+    pub description: String,
+    pub at: SystemTime,
+    pub changes: Vec<VideoChange>,
+}
+
+impl Operation {
+    pub fn new(description: String) -> Self {
+        Self {
+            description,
+            at: SystemTime::now(),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, before: Option<Video>, after: Option<Video>) {
+        let Some(id) = before.as_ref().or(after.as_ref()).map(|v| v.id.clone()) else {
+            return;
+        };
+        self.changes.push(VideoChange { id, before, after });
+    }
+
+    /// IDs of the videos that changed, for saving them.
+    pub fn ids(&self) -> Vec<String> {
+        self.changes
+            .iter()
+            .map(|change| change.id.clone())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct JournalFile {
+    done: Vec<Operation>,
+    undone: Vec<Operation>,
+}
+
+/// Operations that can be undone, and ones that were undone and can be redone.
+/// Kept in its own file next to the state.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: JournalFile,
+}
+
+impl Journal {
+    pub async fn load(path: &Path) -> MyResult<Self> {
+        let file = match fs::read_to_string(path).await {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => JournalFile::default(),
+            Err(err) => Err(err)?,
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    async fn save(&self) -> MyResult<()> {
+        write_atomic(&self.path, &serde_json::to_vec(&self.file)?).await
+    }
+
+    /// Drops changes to videos that were deleted for good, since they can't be
+    /// undone anymore. Operations left with no changes are dropped too.
+    pub async fn forget(&mut self, ids: &[String]) -> MyResult<()> {
+        for operations in [&mut self.file.done, &mut self.file.undone] {
+            for operation in operations.iter_mut() {
+                operation.changes.retain(|change| !ids.contains(&change.id));
+            }
+            operations.retain(|operation| !operation.changes.is_empty());
+        }
+        self.save().await
+    }
+
+    /// Most recent first. The flag is whether the operation was undone.
+    pub fn history(&self) -> impl Iterator<Item = (&Operation, bool)> {
+        self.file
+            .undone
+            .iter()
+            .map(|operation| (operation, true))
+            .chain(
+                self.file
+                    .done
+                    .iter()
+                    .rev()
+                    .map(|operation| (operation, false)),
+            )
+    }
+}
+
+fn side(change: &VideoChange, undo: bool) -> Option<&Video> {
+    if undo {
+        change.before.as_ref()
+    } else {
+        change.after.as_ref()
+    }
+}
+
+/// A video that couldn't be changed, and why.
+#[derive(Serialize, Debug)]
+pub struct VideoFailure {
+    pub id: String,
+    pub name: String,
+    pub error: String,
+}
+
+impl AppState {
+    /// Runs `change` on a copy of each video that `select` picks, then saves
+    /// where the files ended up and records the ones that worked as one
+    /// operation. Moving files can mean copying them across filesystems, so the
+    /// state is only locked to take the copies and to apply the results. Videos
+    /// that fail are left as they were and returned with why, so one bad file
+    /// doesn't stop the rest from being saved and undoable.
+    pub async fn change_videos(
+        &self,
+        description: String,
+        select: impl Fn(&Video) -> bool,
+        mut change: impl FnMut(&mut Video) -> BoxFuture<'_, MyResult<()>>,
+    ) -> MyResult<Vec<VideoFailure>> {
+        let selected = self
+            .read()
+            .await
+            .videos
+            .iter()
+            .filter(|video| select(video))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut moved = Vec::new();
+        let mut failed = Vec::new();
+        for mut video in selected {
+            match change(&mut video).await {
+                Ok(()) => moved.push(video),
+                Err(err) => failed.push(VideoFailure {
+                    id: video.id.clone(),
+                    name: video.display_name(),
+                    error: err.to_string(),
+                }),
+            }
+        }
+        let mut operation = Operation::new(description);
+        {
+            let mut state = self.write().await;
+            for moved in moved {
+                // e.g. purged from the trash in the meantime
+                let Some(video) = state.videos.iter_mut().find(|video| video.id == moved.id) else {
+                    continue;
+                };
+                let before = video.clone();
+                video.take_location(&moved);
+                operation.record(Some(before), Some(video.clone()));
+            }
+        }
+        self.save_videos(&operation.ids()).await?;
+        self.record(operation).await?;
+        Ok(failed)
+    }
+
+    /// Adds the operation to the journal, clearing anything that could be
+    /// redone. Call after the state has been saved and the lock released.
+    pub async fn record(&self, operation: Operation) -> MyResult<()> {
+        if operation.changes.is_empty() {
+            return Ok(());
+        }
+        let mut journal = self.journal().await;
+        journal.file.done.push(operation);
+        let excess = journal.file.done.len().saturating_sub(MAX_OPERATIONS);
+        journal.file.done.drain(..excess);
+        journal.file.undone.clear();
+        journal.save().await
+    }
+
+    /// Undoes the most recent operation, returning its description, or `None`
+    /// if there's nothing to undo.
+    pub async fn undo(&self) -> MyResult<Option<String>> {
+        self.step(true).await
+    }
+
+    /// Redoes the most recently undone operation.
+    pub async fn redo(&self) -> MyResult<Option<String>> {
+        self.step(false).await
+    }
+
+    /// Applies one side of the changes to the state. Everything gets checked
+    /// first, then the files are moved without the state locked, since that can
+    /// mean copying them across filesystems. If a move fails partway, the files
+    /// already moved are put back so the operation isn't left half undone.
+    async fn apply(&self, changes: &[VideoChange], undo: bool) -> MyResult<()> {
+        let current = {
+            let state = self.read().await;
+            changes
+                .iter()
+                .map(|change| {
+                    state
+                        .videos
+                        .iter()
+                        .find(|video| video.id == change.id)
+                        .cloned()
+                })
+                .collect::<Vec<_>>()
+        };
+        for (change, current) in changes.iter().zip(&current) {
+            if let Some(target) = side(change, undo)
+                && current.is_none()
+                && !fs::try_exists(target.current_loc()).await?
+            {
+                Err(format!(
+                    "{} was permanently deleted, so it can't be brought back.",
+                    target.display_name()
+                ))?;
+            }
+        }
+
+        let mut moved = current.clone();
+        let mut result = Ok(());
+        for (i, change) in changes.iter().enumerate().rev() {
+            let (Some(video), Some(target)) = (&mut moved[i], side(change, undo)) else {
+                continue;
+            };
+            if let Err(err) = video.move_like(target).await {
+                // in the opposite order they were moved
+                for (video, current) in moved[i + 1..].iter_mut().zip(&current[i + 1..]) {
+                    if let (Some(video), Some(current)) = (video, current)
+                        && let Err(err) = video.move_like(current).await
+                    {
+                        eprintln!("[journal] Couldn't put back {}: {err}", video.id);
+                    }
+                }
+                result = Err(err);
+                break;
+            }
+        }
+
+        let mut state = self.write().await;
+        for (change, moved) in changes.iter().zip(moved) {
+            let index = state.videos.iter().position(|video| video.id == change.id);
+            if result.is_err() {
+                // in case a file couldn't be put back, note where it really is
+                if let (Some(index), Some(moved)) = (index, moved) {
+                    state.videos[index].take_location(&moved);
+                }
+                continue;
+            }
+            match (index, side(change, undo)) {
+                (Some(index), Some(target)) => {
+                    let video = &mut state.videos[index];
+                    if let Some(moved) = moved {
+                        video.take_location(&moved);
+                    }
+                    video.tags = target.tags.clone();
+                    video.note = target.note.clone();
+                }
+                (Some(index), None) => {
+                    state.videos.remove(index);
+                }
+                (None, Some(target)) => state.videos.push(target.clone()),
+                (None, None) => {}
+            }
+        }
+        result
+    }
+
+    async fn step(&self, undo: bool) -> MyResult<Option<String>> {
+        let mut journal = self.journal().await;
+        let JournalFile { done, undone } = &mut journal.file;
+        let (from, to) = if undo { (done, undone) } else { (undone, done) };
+        let Some(operation) = from.pop() else {
+            return Ok(None);
+        };
+        let result = self.apply(&operation.changes, undo).await;
+        let ids = operation.ids();
+        if let Err(err) = result {
+            from.push(operation);
+            // in case a file couldn't be put back, save where things really are
+            self.save_videos(&ids).await?;
+            return Err(err);
+        }
+        let description = operation.description.clone();
+        to.push(operation);
+        self.save_videos(&ids).await?;
+        journal.save().await?;
+        Ok(Some(description))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::State;
+    use futures_util::FutureExt;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use tokio::time;
+
+    #[tokio::test]
+    async fn test_undo_redo() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let path = dir.join("a.mp4");
+        std::fs::write(&path, b"video").unwrap();
+        let video = Video::new(
+            "a".into(),
+            path.clone(),
+            "a.jpg".into(),
+            SystemTime::UNIX_EPOCH,
+            5,
+        );
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video],
+                ..State::default()
+            },
+            dir,
+        )
+        .await;
+
+        // add a tag, then move the file
+        let mut operation = Operation::new("Add tag beach".into());
+        {
+            let video = &mut state.write().await.videos[0];
+            let before = video.clone();
+            video.tags.insert("beach".into());
+            operation.record(Some(before), Some(video.clone()));
+        }
+        state.record(operation).await.unwrap();
+        let moved = dir.join("elsewhere/a.mp4");
+        let mut operation = Operation::new("Move".into());
+        {
+            let video = &mut state.write().await.videos[0];
+            let before = video.clone();
+            std::fs::create_dir(dir.join("elsewhere")).unwrap();
+            video.stow("elsewhere".into(), moved.clone()).await.unwrap();
+            operation.record(Some(before), Some(video.clone()));
+        }
+        state.record(operation).await.unwrap();
+
+        assert_eq!(state.undo().await.unwrap().as_deref(), Some("Move"));
+        assert!(path.exists());
+        assert_eq!(*state.read().await.videos[0].current_loc(), path);
+        assert_eq!(
+            state.undo().await.unwrap().as_deref(),
+            Some("Add tag beach")
+        );
+        assert!(state.read().await.videos[0].tags.is_empty());
+        assert_eq!(state.undo().await.unwrap(), None);
+
+        assert_eq!(
+            state.redo().await.unwrap().as_deref(),
+            Some("Add tag beach")
+        );
+        assert!(state.read().await.videos[0].tags.contains("beach"));
+
+        // the journal survives a restart, and a new operation clears redo
+        let journal = Journal::load(&dir.join("journal.json")).await.unwrap();
+        let history = journal
+            .history()
+            .map(|(operation, undone)| (operation.description.as_str(), undone))
+            .collect::<Vec<_>>();
+        assert_eq!(history, vec![("Move", true), ("Add tag beach", false)]);
+        state
+            .record(Operation {
+                description: "Remove tag beach".into(),
+                at: SystemTime::now(),
+                changes: vec![VideoChange {
+                    id: "a".into(),
+                    before: None,
+                    after: None,
+                }],
+            })
+            .await
+            .unwrap();
+        assert_eq!(state.redo().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_undo_deleted_file() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let video = Video::new(
+            "a".into(),
+            dir.join("a.mp4"),
+            "a.jpg".into(),
+            SystemTime::UNIX_EPOCH,
+            5,
+        );
+        let state = AppState::for_tests(State::default(), dir).await;
+        let mut operation = Operation::new("Delete".into());
+        operation.record(Some(video), None);
+        state.record(operation).await.unwrap();
+        assert!(state.undo().await.is_err());
+        // still there to try again
+        assert_eq!(state.journal().await.history().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_undo_fails_partway() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let video = |id: &str| {
+            let path = dir.join(format!("{id}.mp4"));
+            std::fs::write(&path, b"video").unwrap();
+            Video::new(
+                id.into(),
+                path,
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                5,
+            )
+        };
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video("a"), video("b")],
+                ..State::default()
+            },
+            dir,
+        )
+        .await;
+
+        // a gets moved back to a folder that can't be made, after b's tag has
+        // already been undone
+        let mut operation = Operation::new("Tag and move".into());
+        {
+            let videos = &mut state.write().await.videos;
+            let before = Video::new(
+                "a".into(),
+                dir.join("blocked/a.mp4"),
+                "a.jpg".into(),
+                SystemTime::UNIX_EPOCH,
+                5,
+            );
+            std::fs::write(dir.join("blocked"), b"not a folder").unwrap();
+            operation.record(Some(before), Some(videos[0].clone()));
+            let before = videos[1].clone();
+            videos[1].tags.insert("beach".into());
+            operation.record(Some(before), Some(videos[1].clone()));
+        }
+        state.record(operation).await.unwrap();
+
+        assert!(state.undo().await.is_err());
+        let state_ref = state.read().await;
+        assert!(state_ref.videos[1].tags.contains("beach"));
+        assert_eq!(*state_ref.videos[0].current_loc(), dir.join("a.mp4"));
+        drop(state_ref);
+        let history = state
+            .journal()
+            .await
+            .history()
+            .map(|(operation, undone)| (operation.description.clone(), undone))
+            .collect::<Vec<_>>();
+        assert_eq!(history, vec![(String::from("Tag and move"), false)]);
+    }
+
+    #[tokio::test]
+    async fn test_change_videos_partly_fails() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let video = |id: &str| {
+            let path = dir.join(format!("{id}.mp4"));
+            std::fs::write(&path, b"video").unwrap();
+            Video::new(
+                id.into(),
+                path,
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                5,
+            )
+        };
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video("a"), video("b")],
+                ..State::default()
+            },
+            dir,
+        )
+        .await;
+        std::fs::remove_file(dir.join("a.mp4")).unwrap();
+
+        let trash_dir = dir.join("trash");
+        std::fs::create_dir(&trash_dir).unwrap();
+        let failed = state
+            .change_videos(
+                "Delete".into(),
+                |_| true,
+                |video| {
+                    let trash_dir = trash_dir.clone();
+                    let state = state.clone();
+                    async move {
+                        // the state isn't locked while files move
+                        let locked = time::timeout(Duration::from_secs(1), state.write()).await;
+                        assert!(locked.is_ok());
+                        video.trash(&trash_dir).await
+                    }
+                    .boxed()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            failed.iter().map(|failure| &failure.id).collect::<Vec<_>>(),
+            vec!["a"]
+        );
+        {
+            let state = state.read().await;
+            assert!(state.videos[0].trashed_at().is_none());
+            assert!(state.videos[1].trashed_at().is_some());
+        }
+
+        // b's move got recorded, so it can be undone
+        assert_eq!(state.undo().await.unwrap(), Some(String::from("Delete")));
+        assert!(state.read().await.videos[1].trashed_at().is_none());
+        assert!(dir.join("b.mp4").exists());
+    }
+}
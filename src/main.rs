@@ -3,6 +3,7 @@ use std::{
     error::Error,
     io::ErrorKind,
     net::SocketAddr,
+    path::Path,
     process::exit,
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
@@ -10,21 +11,43 @@ use std::{
 
 use hyper::{server::conn::http1, service::service_fn};
 use hyper_util::rt::TokioIo;
-use tokio::{fs, net::TcpListener, sync::RwLock};
+use tokio::{fs, net::TcpListener};
 
 use crate::{
-    common::{DIR_PATH, SharedState, State},
-    fmt::{bold, code, link},
-    http_handler::handle_request_wrapper,
+    cache::{SegmentCache, segment_cache_dir},
+    common::{AppState, DIR_PATH, SharedState, assign_missing_ids},
+    config::Config,
+    fmt::{bold, code, faded, link},
+    http_handler::{
+        defs::InvalidRequest,
+        handle_request_wrapper,
+        handlers::cook::{check, run as run_cook},
+    },
+    journal::Journal,
     register::add_videos,
-    util::{MyResult, format_size},
+    storage::{StorageKind, migrate_storage},
+    sync::{SyncOptions, sync_videos},
+    trash::purge_periodically,
+    util::{MyResult, format_age, format_size},
+    watch::watch_videos,
 };
 
+mod cache;
 mod common;
+mod config;
+mod cook;
 mod fmt;
 mod http_handler;
+mod jobs;
+mod journal;
+mod migrations;
+mod progress;
 mod register;
+mod storage;
+mod sync;
+mod trash;
 mod util;
+mod watch;
 
 async fn start_server(state: SharedState) -> MyResult<()> {
     const PORT: u16 = 8008;
@@ -45,21 +68,19 @@ async fn start_server(state: SharedState) -> MyResult<()> {
                     service_fn(move |req| handle_request_wrapper(req, state_clone.clone())),
                 )
                 .await
+                && !err.is_incomplete_message()
+                && !err.is_body_write_aborted()
+                && !err
+                    .source()
+                    .and_then(|e| e.downcast_ref::<std::io::Error>())
+                    .is_some_and(|err| {
+                        matches!(
+                            err.kind(),
+                            ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
+                        )
+                    })
             {
-                if !err.is_incomplete_message()
-                    && !err.is_body_write_aborted()
-                    && !err
-                        .source()
-                        .and_then(|e| e.downcast_ref::<std::io::Error>())
-                        .map_or(false, |err| {
-                            matches!(
-                                err.kind(),
-                                ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
-                            )
-                        })
-                {
-                    eprintln!("Error serving connection: {:?}", err);
-                }
+                eprintln!("Error serving connection: {:?}", err);
             }
         });
     }
@@ -68,30 +89,47 @@ async fn start_server(state: SharedState) -> MyResult<()> {
 #[tokio::main]
 async fn main() -> MyResult<()> {
     fs::create_dir_all(format!("{DIR_PATH}/thumbs/")).await?;
-    let sharable_state = Arc::new(RwLock::new({
-        let mut state = match fs::read_to_string(format!("{DIR_PATH}/state.json")).await {
-            Ok(json) => serde_json::from_str(&json)?,
-            Err(err) if err.kind() == ErrorKind::NotFound => State {
-                videos: Vec::new(),
-                version: None,
-            },
-            Err(err) => Err(err)?,
-        };
-        state.version = Some(String::from(env!("CARGO_PKG_VERSION")));
-        state
-    }));
-
-    let (program_name, command, add_path) = {
+    let (program_name, command, args) = {
         let mut args = std::env::args();
         (
             args.next().unwrap_or_else(|| String::from("./video-sort")),
             args.next(),
-            args.next(),
+            args.collect::<Vec<_>>(),
         )
     };
 
+    let storage_kind = StorageKind::detect(Path::new(DIR_PATH)).await?;
+    // has to happen before the state gets loaded so the old storage isn't open
+    if command.as_deref() == Some("migrate-storage") {
+        let usage = code(&format!("{program_name} migrate-storage <json|sqlite>"));
+        let Some(to) = args.first().and_then(|kind| StorageKind::parse(kind)) else {
+            eprintln!("Missing or unknown storage kind: {usage}");
+            exit(2);
+        };
+        migrate_storage(Path::new(DIR_PATH), storage_kind, to).await?;
+        eprintln!(
+            "Moved the state from {} to {} storage.",
+            storage_kind.name(),
+            to.name()
+        );
+        return Ok(());
+    }
+
+    let storage = storage_kind.open(Path::new(DIR_PATH))?;
+    let state = storage.load().await?.unwrap_or_default();
+    let journal = Journal::load(&Path::new(DIR_PATH).join("journal.json")).await?;
+    let sharable_state = Arc::new(AppState::new(state, storage, journal));
+    let assigned_ids = {
+        let mut state = sharable_state.write().await;
+        state.version = Some(String::from(env!("CARGO_PKG_VERSION")));
+        assign_missing_ids(&mut state).await
+    };
+    if assigned_ids {
+        sharable_state.save().await?;
+    }
+
     match command.as_deref() {
-        None => {
+        None | Some("watch") => {
             eprintln!(
                 "{}",
                 bold(&format!("Video Sort {}", env!("CARGO_PKG_VERSION")))
@@ -113,17 +151,231 @@ async fn main() -> MyResult<()> {
                     );
                 }
             }
+            sharable_state.jobs().mark_interrupted().await?;
+            let config = Config::load().await?;
+            tokio::spawn(purge_periodically(config.clone(), sharable_state.clone()));
+            if command.is_some() {
+                let state = sharable_state.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = watch_videos(config, state).await {
+                        eprintln!("[watch] Stopped watching for new videos: {err:?}");
+                    }
+                });
+            }
             start_server(sharable_state).await?;
         }
         Some("add") => {
-            let Some(path) = add_path else {
+            let usage = code(&format!(
+                "{program_name} add <path> [--ext mp4,mov] [--exclude <pattern>]"
+            ));
+            let mut config = Config::load().await?;
+            let mut path = None;
+            let mut args = args.into_iter();
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--ext" | "--exclude" => {
+                        let Some(value) = args.next() else {
+                            eprintln!("Missing value for {}: {usage}", code(&arg));
+                            exit(2);
+                        };
+                        if arg == "--ext" {
+                            config.extensions = value
+                                .split(',')
+                                .map(|ext| ext.trim_start_matches('.').to_string())
+                                .collect();
+                        } else {
+                            config.exclude.push(value);
+                        }
+                    }
+                    _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+                    _ => {
+                        eprintln!("Unexpected argument {}: {usage}", code(&arg));
+                        exit(2);
+                    }
+                }
+            }
+            let Some(path) = path else {
+                eprintln!("Missing path: {usage}");
+                exit(2);
+            };
+            add_videos(&path, &config, sharable_state).await?;
+        }
+        Some("sync") => {
+            let mut options = SyncOptions::default();
+            for arg in args {
+                match arg.as_str() {
+                    "--prune" => options.prune = true,
+                    "--reprobe" => options.reprobe = true,
+                    "--rethumbnail" => options.rethumbnail = true,
+                    "--add" => options.add = true,
+                    _ => {
+                        eprintln!(
+                            "Unexpected argument {}: {}",
+                            code(&arg),
+                            code(&format!(
+                                "{program_name} sync [--prune] [--reprobe] [--rethumbnail] [--add]"
+                            ))
+                        );
+                        exit(2);
+                    }
+                }
+            }
+            let config = Config::load().await?;
+            let report = sync_videos(&options, &config, &sharable_state).await?;
+            for missing in &report.missing {
+                println!("- {}", missing.path.display());
+            }
+            for moved in &report.moved {
+                println!("> {} -> {}", moved.from.display(), moved.to.display());
+            }
+            for changed in &report.changed {
+                println!(
+                    "~ {} ({} -> {})",
+                    changed.path.display(),
+                    format_size(changed.old_size),
+                    format_size(changed.size)
+                );
+            }
+            for path in &report.new {
+                println!("+ {}", path.display());
+            }
+            for root in &report.unreadable_roots {
+                println!("? {}", root.display());
+            }
+            eprintln!(
+                "{} missing, {} moved, {} changed, {} new.",
+                report.missing.len(),
+                report.moved.len(),
+                report.changed.len(),
+                report.new.len()
+            );
+            if report.pruned
+                + report.relocated
+                + report.reprobed
+                + report.rethumbnailed
+                + report.added
+                > 0
+            {
                 eprintln!(
-                    "Missing path: {}",
-                    code(&format!("{program_name} add <path>"))
+                    "Pruned {}, relocated {}, reprobed {}, rethumbnailed {}, added {}.",
+                    report.pruned,
+                    report.relocated,
+                    report.reprobed,
+                    report.rethumbnailed,
+                    report.added
+                );
+            }
+            // fix terminal from entering raw mode
+            let _ = tokio::process::Command::new("stty")
+                .arg("sane")
+                .status()
+                .await?;
+        }
+        Some("history") => {
+            let journal = sharable_state.journal().await;
+            let now = SystemTime::now();
+            let mut empty = true;
+            for (operation, undone) in journal.history().take(20) {
+                empty = false;
+                let line = format!(
+                    "{:>10}  {} ({} video{})",
+                    format_age(now.duration_since(operation.at).unwrap_or_default()),
+                    operation.description,
+                    operation.changes.len(),
+                    if operation.changes.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    }
                 );
+                if undone {
+                    println!("{}", faded(&format!("{line} [undone]")));
+                } else {
+                    println!("{line}");
+                }
+            }
+            if empty {
+                eprintln!("Nothing has happened yet.");
+            }
+        }
+        Some("cache") => {
+            let usage = code(&format!("{program_name} cache clear"));
+            if args.first().map(String::as_str) != Some("clear") {
+                eprintln!("Missing or unknown cache command: {usage}");
                 exit(2);
+            }
+            let cache = SegmentCache::new(segment_cache_dir(), 0);
+            let (entries, freed) = cache.clear().await?;
+            eprintln!(
+                "Deleted {entries} cached clip{}, freeing {}.",
+                if entries == 1 { "" } else { "s" },
+                format_size(freed)
+            );
+        }
+        Some("cook") => {
+            let usage = code(&format!(
+                "{program_name} cook <request.json> [--dry-run [--measure]]"
+            ));
+            let mut path = None;
+            let mut dry_run = false;
+            let mut measure = false;
+            for arg in args {
+                match arg.as_str() {
+                    "--dry-run" => dry_run = true,
+                    "--measure" => measure = true,
+                    _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+                    _ => {
+                        eprintln!("Unexpected argument {}: {usage}", code(&arg));
+                        exit(2);
+                    }
+                }
+            }
+            let Some(path) = path else {
+                eprintln!("Missing request file: {usage}");
+                exit(2);
+            };
+            let body = serde_json::from_slice(&fs::read(&path).await?)?;
+            let prepared = match check(body, &sharable_state).await? {
+                Ok(prepared) => prepared,
+                Err((_, problems)) => {
+                    eprintln!("{}", InvalidRequest::new(problems).error);
+                    exit(1);
+                }
             };
-            add_videos(&path, sharable_state).await?;
+            if dry_run {
+                let plan = cook::plan(
+                    prepared.clips,
+                    prepared.music,
+                    &prepared.encoding,
+                    &prepared.preset,
+                    &prepared.name,
+                    measure,
+                )
+                .await?;
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else {
+                let Some(job) = sharable_state
+                    .jobs()
+                    .start(prepared.name.clone(), prepared.project.clone())
+                    .await?
+                else {
+                    eprintln!("Another cook is already making {}.mp4.", prepared.name);
+                    exit(1);
+                };
+                run_cook(job.clone(), prepared, sharable_state.clone()).await;
+                let record = job.record();
+                match (record.output, record.error) {
+                    (Some(output), _) => eprintln!("Cooked {}.", output.display()),
+                    (None, error) => {
+                        eprintln!(
+                            "Cooking failed: {}. The log is at {DIR_PATH}/jobs/{}.log.",
+                            error.as_deref().unwrap_or("cancelled"),
+                            job.id
+                        );
+                        exit(1);
+                    }
+                }
+            }
         }
         Some("version" | "-v" | "--version") => {
             println!("{}", env!("CARGO_PKG_VERSION"));
@@ -132,9 +384,50 @@ async fn main() -> MyResult<()> {
             eprintln!("{}", bold("Available commands"));
             eprintln!("$ {}", code(&program_name));
             eprintln!("| Start the web server.");
-            eprintln!("$ {}", code(&format!("{program_name} add <path>")));
-            eprintln!("| Registers all .mp4 files in the given directory");
-            eprintln!("| (shallow).");
+            eprintln!("$ {}", code(&format!("{program_name} watch")));
+            eprintln!("| Start the web server, and automatically add new videos");
+            eprintln!("| that appear in folders previously passed to add.");
+            eprintln!(
+                "$ {}",
+                code(&format!(
+                    "{program_name} add <path> [--ext mp4,mov] [--exclude <pattern>]"
+                ))
+            );
+            eprintln!("| Registers all videos in the given directory and its");
+            eprintln!("| subdirectories. Extensions and excluded names default");
+            eprintln!("| to the ones in {DIR_PATH}/config.json.");
+            eprintln!(
+                "$ {}",
+                code(&format!(
+                    "{program_name} sync [--prune] [--reprobe] [--rethumbnail] [--add]"
+                ))
+            );
+            eprintln!("| Reports videos that were deleted, moved, changed, or");
+            eprintln!("| added outside the app. The flags stop tracking missing");
+            eprintln!("| videos, refresh changed ones, and add new ones. Moved");
+            eprintln!("| videos follow their file with --prune or --add.");
+            eprintln!(
+                "$ {}",
+                code(&format!("{program_name} migrate-storage <json|sqlite>"))
+            );
+            eprintln!("| Moves the saved state to state.json or an SQLite");
+            eprintln!("| database. SQLite saves faster with lots of videos.");
+            eprintln!("$ {}", code(&format!("{program_name} history")));
+            eprintln!("| Lists recent changes made in the app, newest first.");
+            eprintln!("| They can be undone from the web app.");
+            eprintln!("$ {}", code(&format!("{program_name} cache clear")));
+            eprintln!("| Deletes the clips kept from earlier cooks. Cooks keep");
+            eprintln!("| up to segment_cache_mb of them to reuse.");
+            eprintln!(
+                "$ {}",
+                code(&format!(
+                    "{program_name} cook <request.json> [--dry-run [--measure]]"
+                ))
+            );
+            eprintln!("| Cooks a request like the one POST /cook takes. With");
+            eprintln!("| --dry-run, prints the ffmpeg commands it would run as");
+            eprintln!("| JSON instead. --measure lets it probe the clips first,");
+            eprintln!("| for the exact commands.");
             eprintln!("$ {}", code(&format!("{program_name} update")));
             eprintln!("| Outputs a shell command to overwrite the program file");
             eprintln!("| with the latest version.");
@@ -0,0 +1,149 @@
+use serde_json::{Value, json};
+
+use crate::util::MyResult;
+
+type Migration = fn(&mut Value) -> MyResult<()>;
+
+/// `MIGRATIONS[n]` upgrades a schema `n` state.json document to schema `n + 1`.
+///
+/// Adding an optional field with `#[serde(default)]` doesn't need a migration,
+/// but renaming, restructuring, or removing anything does. Add a fixture of the
+/// old format to the tests too.
+const MIGRATIONS: &[Migration] = &[v0_to_v1, v1_to_v2];
+
+/// The schema version this build reads and writes.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Documents from before the `schema` field existed (0.12.3 and earlier) are
+/// schema 0.
+pub fn schema_of(doc: &Value) -> u32 {
+    doc.get("schema")
+        .and_then(Value::as_u64)
+        .map_or(0, |schema| schema as u32)
+}
+
+/// Whether the document was written by a newer version of video-sort, in which
+/// case it shouldn't be treated as corrupt.
+pub fn is_from_newer_version(json: &str) -> bool {
+    serde_json::from_str::<Value>(json).is_ok_and(|doc| schema_of(&doc) > SCHEMA_VERSION)
+}
+
+/// Upgrades the document in place to [`SCHEMA_VERSION`].
+pub fn migrate(doc: &mut Value) -> MyResult<()> {
+    let schema = schema_of(doc);
+    if schema > SCHEMA_VERSION {
+        Err(format!(
+            "state.json uses schema {schema}, but this version of video-sort only understands up to schema {SCHEMA_VERSION}. Run `video-sort update` to get the latest version."
+        ))?;
+    }
+    for migration in &MIGRATIONS[schema as usize..] {
+        migration(doc)?;
+    }
+    doc["schema"] = json!(SCHEMA_VERSION);
+    Ok(())
+}
+
+/// Fills in fields that were added to videos over time without defaults, and
+/// `roots` from the recursive `add`. IDs are left blank because generating
+/// them means reading the video files; `assign_missing_ids` does that after
+/// loading.
+fn v0_to_v1(doc: &mut Value) -> MyResult<()> {
+    let state = doc.as_object_mut().ok_or("state is not an object")?;
+    state.entry("version").or_insert(Value::Null);
+    state.entry("roots").or_insert(json!([]));
+    let videos = state
+        .get_mut("videos")
+        .and_then(Value::as_array_mut)
+        .ok_or("state has no videos list")?;
+    for video in videos {
+        let video = video.as_object_mut().ok_or("video is not an object")?;
+        video.entry("id").or_insert(json!(""));
+        video.entry("tags").or_insert(json!([]));
+        video.entry("note").or_insert(json!(""));
+        video.entry("probe").or_insert(Value::Null);
+        video.entry("stow_state").or_insert(json!("Original"));
+    }
+    Ok(())
+}
+
+/// `Elsewhere(path)` became `Stowed { target, path }` when stow targets became
+/// configurable. The only way to stow used to be moving to Downloads for
+/// YouTube, so that's the target.
+fn v1_to_v2(doc: &mut Value) -> MyResult<()> {
+    let videos = doc
+        .get_mut("videos")
+        .and_then(Value::as_array_mut)
+        .ok_or("state has no videos list")?;
+    for video in videos {
+        let Some(stow_state) = video.get_mut("stow_state") else {
+            continue;
+        };
+        if let Some(path) = stow_state.get("Elsewhere") {
+            *stow_state = json!({ "Stowed": { "target": "for-youtube", "path": path } });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::State;
+
+    fn load_fixture(json: &str) -> State {
+        let mut doc = serde_json::from_str(json).unwrap();
+        migrate(&mut doc).unwrap();
+        assert_eq!(schema_of(&doc), SCHEMA_VERSION);
+        serde_json::from_value(doc).unwrap()
+    }
+
+    #[test]
+    fn test_migrate_0_12_3() {
+        let state = load_fixture(include_str!("fixtures/0.12.3.json"));
+        assert_eq!(state.schema, SCHEMA_VERSION);
+        assert_eq!(state.videos.len(), 3);
+        assert!(state.roots.is_empty());
+        assert!(state.videos.iter().all(|video| video.id.is_empty()));
+
+        let beach = &state.videos[0];
+        assert!(beach.tags.contains("beach"));
+        assert_eq!(beach.note, "sunset, keep the first half");
+        assert_eq!(beach.probe.as_ref().unwrap().width, 1080);
+        let stowed = &state.videos[1];
+        assert_eq!(stowed.stow_target(), Some("for-youtube"));
+        assert_eq!(
+            stowed.current_loc().to_string_lossy(),
+            "./storage/downloads/for-youtube/yt_storagedcimCameraPXL_20250802_101010000.mp4.jpg.mp4"
+        );
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        let mut doc: Value = serde_json::from_str(include_str!("fixtures/0.12.3.json")).unwrap();
+        migrate(&mut doc).unwrap();
+        let migrated = doc.clone();
+        migrate(&mut doc).unwrap();
+        assert_eq!(doc, migrated);
+    }
+
+    #[test]
+    fn test_migrate_fills_missing_fields() {
+        let state = load_fixture(
+            r#"{ "videos": [{
+                "path": "a.mp4",
+                "thumbnail_name": "a.mp4.jpg",
+                "mtime": { "secs_since_epoch": 0, "nanos_since_epoch": 0 },
+                "size": 1
+            }] }"#,
+        );
+        assert!(state.videos[0].tags.is_empty());
+        assert!(state.videos[0].probe.is_none());
+        assert_eq!(state.version, None);
+    }
+
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let mut doc = json!({ "videos": [], "schema": SCHEMA_VERSION + 1 });
+        assert!(migrate(&mut doc).is_err());
+    }
+}
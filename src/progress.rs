@@ -0,0 +1,336 @@
+use std::time::Instant;
+
+use serde::Serialize;
+
+/// Concat only copies streams, so it's counted as this fraction of the clip
+/// encoding work.
+const CONCAT_WEIGHT: f64 = 0.1;
+/// Mixing in music only re-encodes the audio.
+const MUSIC_WEIGHT: f64 = 0.1;
+
+/// One block of ffmpeg's `-progress` output.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FfmpegProgress {
+    /// seconds of output written so far
+    pub out_time: f64,
+    /// e.g. 1.5 for `speed=1.5x`
+    pub speed: Option<f64>,
+    /// whether ffmpeg is done
+    pub end: bool,
+}
+
+/// Reads the `key=value` lines ffmpeg writes with `-progress pipe:1`. Each
+/// block ends with a `progress=continue` or `progress=end` line.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: FfmpegProgress,
+}
+
+impl ProgressParser {
+    /// Returns the progress once a block is complete.
+    pub fn feed(&mut self, line: &str) -> Option<FfmpegProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        match key {
+            // this is N/A until the first frame is written
+            "out_time_us" => {
+                if let Ok(micros) = value.parse::<i64>() {
+                    self.current.out_time = micros.max(0) as f64 / 1_000_000.0;
+                }
+            }
+            "speed" => self.current.speed = value.trim().trim_end_matches('x').parse().ok(),
+            "progress" => {
+                self.current.end = value == "end";
+                return Some(std::mem::take(&mut self.current));
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Clips,
+    Transitions,
+    Concat,
+    Music,
+}
+
+/// What gets sent to the client.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Progress {
+    /// 0 to 100, for the whole cook
+    pub percent: f64,
+    pub stage: Stage,
+    /// index of the clip this update came from, or the clip before the
+    /// transition
+    pub clip: Option<usize>,
+    pub clips: usize,
+    /// ffmpeg's speed for that clip or the concat, e.g. 1.5 means 1.5 seconds
+    /// of video per second
+    pub speed: Option<f64>,
+    /// estimated seconds left, from how long it's taken so far
+    pub eta: Option<f64>,
+}
+
+/// Adds up progress across the clips, which are encoded in parallel, the
+/// transitions between them, the concat, and mixing in music at the end.
+#[derive(Debug)]
+pub struct CookProgress {
+    durations: Vec<f64>,
+    done: Vec<f64>,
+    /// (clip before, duration) of each transition
+    transitions: Vec<(usize, f64)>,
+    transitions_done: Vec<f64>,
+    concat_done: f64,
+    /// `None` if there's no music
+    music_done: Option<f64>,
+    started: Instant,
+}
+
+impl CookProgress {
+    /// `durations` are how long each clip is, and `transitions` are the clip
+    /// before and duration of each transition.
+    pub fn new(durations: Vec<f64>, transitions: Vec<(usize, f64)>, started: Instant) -> Self {
+        Self {
+            done: vec![0.0; durations.len()],
+            durations,
+            transitions_done: vec![0.0; transitions.len()],
+            transitions,
+            concat_done: 0.0,
+            music_done: None,
+            started,
+        }
+    }
+
+    /// Counts mixing in music after the concat.
+    pub fn with_music(mut self) -> Self {
+        self.music_done = Some(0.0);
+        self
+    }
+
+    /// Length of the cooked video. Clips overlap in transitions.
+    fn total(&self) -> f64 {
+        self.durations.iter().sum::<f64>()
+            - self
+                .transitions
+                .iter()
+                .map(|(_, duration)| duration)
+                .sum::<f64>()
+    }
+
+    pub fn update_clip(
+        &mut self,
+        clip: usize,
+        progress: &FfmpegProgress,
+        now: Instant,
+    ) -> Progress {
+        if let (Some(done), Some(duration)) = (self.done.get_mut(clip), self.durations.get(clip)) {
+            // clips can take a few ffmpeg runs, so don't go backwards
+            *done = if progress.end {
+                *duration
+            } else {
+                done.max(progress.out_time.min(*duration))
+            };
+        }
+        self.snapshot(Stage::Clips, Some(clip), progress.speed, now)
+    }
+
+    /// `transition` indexes the transitions passed to [`CookProgress::new`].
+    pub fn update_transition(
+        &mut self,
+        transition: usize,
+        progress: &FfmpegProgress,
+        now: Instant,
+    ) -> Progress {
+        self.done.clone_from(&self.durations);
+        let Some(&(clip, duration)) = self.transitions.get(transition) else {
+            return self.snapshot(Stage::Transitions, None, progress.speed, now);
+        };
+        self.transitions_done[transition] = if progress.end {
+            duration
+        } else {
+            progress.out_time.min(duration)
+        };
+        self.snapshot(Stage::Transitions, Some(clip), progress.speed, now)
+    }
+
+    pub fn update_concat(&mut self, progress: &FfmpegProgress, now: Instant) -> Progress {
+        // everything else is done by now, even if it never said so
+        self.done.clone_from(&self.durations);
+        self.transitions_done = self
+            .transitions
+            .iter()
+            .map(|(_, duration)| *duration)
+            .collect();
+        self.concat_done = self.output_done(progress);
+        self.snapshot(Stage::Concat, None, progress.speed, now)
+    }
+
+    pub fn update_music(&mut self, progress: &FfmpegProgress, now: Instant) -> Progress {
+        self.update_concat(&FfmpegProgress::default(), now);
+        self.concat_done = self.total();
+        self.music_done = Some(self.output_done(progress));
+        self.snapshot(Stage::Music, None, progress.speed, now)
+    }
+
+    /// How much of the cooked video a run over the whole thing has done.
+    fn output_done(&self, progress: &FfmpegProgress) -> f64 {
+        if progress.end {
+            self.total()
+        } else {
+            progress.out_time.min(self.total())
+        }
+    }
+
+    fn snapshot(
+        &self,
+        stage: Stage,
+        clip: Option<usize>,
+        speed: Option<f64>,
+        now: Instant,
+    ) -> Progress {
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|(_, duration)| duration)
+            .sum::<f64>();
+        let music = if self.music_done.is_some() {
+            self.total() * MUSIC_WEIGHT
+        } else {
+            0.0
+        };
+        let total =
+            self.durations.iter().sum::<f64>() + transitions + self.total() * CONCAT_WEIGHT + music;
+        let done = self.done.iter().sum::<f64>()
+            + self.transitions_done.iter().sum::<f64>()
+            + self.concat_done * CONCAT_WEIGHT
+            + self.music_done.unwrap_or(0.0) * MUSIC_WEIGHT;
+        let fraction = if total > 0.0 { done / total } else { 0.0 };
+        let elapsed = now.duration_since(self.started).as_secs_f64();
+        Progress {
+            percent: fraction * 100.0,
+            stage,
+            clip,
+            clips: self.durations.len(),
+            speed,
+            eta: (fraction > 0.0).then(|| elapsed * (1.0 - fraction) / fraction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_progress_parser() {
+        let mut parser = ProgressParser::default();
+        let output = "frame=0\nout_time_us=N/A\nspeed=N/A\nprogress=continue\n\
+            frame=30\nout_time_us=1500000\nspeed=1.25x\nprogress=continue\n\
+            out_time_us=3000000\nspeed= 2x\nprogress=end\n";
+        let blocks = output
+            .lines()
+            .filter_map(|line| parser.feed(line))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blocks,
+            vec![
+                FfmpegProgress {
+                    out_time: 0.0,
+                    speed: None,
+                    end: false
+                },
+                FfmpegProgress {
+                    out_time: 1.5,
+                    speed: Some(1.25),
+                    end: false
+                },
+                FfmpegProgress {
+                    out_time: 3.0,
+                    speed: Some(2.0),
+                    end: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cook_progress() {
+        let start = Instant::now();
+        let mut progress = CookProgress::new(vec![10.0, 30.0], Vec::new(), start);
+        let block = |out_time: f64, end: bool| FfmpegProgress {
+            out_time,
+            speed: Some(2.0),
+            end,
+        };
+
+        // half of the first clip after 5 seconds
+        let update = progress.update_clip(0, &block(5.0, false), start + Duration::from_secs(5));
+        assert_eq!(update.stage, Stage::Clips);
+        assert_eq!(update.clip, Some(0));
+        assert_eq!(update.clips, 2);
+        assert!((update.percent - 5.0 / 44.0 * 100.0).abs() < 1e-9);
+        let eta = update.eta.unwrap();
+        assert!((eta - 5.0 * (44.0 - 5.0) / 5.0).abs() < 1e-9);
+
+        // ffmpeg can overshoot the duration a bit
+        let update = progress.update_clip(1, &block(31.0, false), start);
+        assert!((update.percent - 35.0 / 44.0 * 100.0).abs() < 1e-9);
+        progress.update_clip(0, &block(9.9, true), start);
+
+        let update = progress.update_concat(&block(20.0, false), start);
+        assert_eq!(update.stage, Stage::Concat);
+        assert!((update.percent - 42.0 / 44.0 * 100.0).abs() < 1e-9);
+        let update = progress.update_concat(&block(0.0, true), start);
+        assert!((update.percent - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_no_clips() {
+        let start = Instant::now();
+        let mut progress = CookProgress::new(Vec::new(), Vec::new(), start);
+        let update = progress.update_concat(&FfmpegProgress::default(), start);
+        assert_eq!(update.percent, 0.0);
+        assert_eq!(update.eta, None);
+    }
+
+    #[test]
+    fn test_transition_progress() {
+        let start = Instant::now();
+        // 2 second transition between two 10 second clips, so the video is 18
+        // seconds long
+        let mut progress = CookProgress::new(vec![10.0, 10.0], vec![(0, 2.0)], start);
+        let block = |out_time: f64| FfmpegProgress {
+            out_time,
+            speed: None,
+            end: false,
+        };
+        let total = 20.0 + 2.0 + 1.8;
+        let update = progress.update_transition(0, &block(1.0), start);
+        assert_eq!(update.stage, Stage::Transitions);
+        assert_eq!(update.clip, Some(0));
+        assert!((update.percent - 21.0 / total * 100.0).abs() < 1e-9);
+        let update = progress.update_concat(&block(9.0), start);
+        assert!((update.percent - 22.9 / total * 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_music_progress() {
+        let start = Instant::now();
+        let mut progress = CookProgress::new(vec![10.0, 10.0], Vec::new(), start).with_music();
+        let block = |out_time: f64| FfmpegProgress {
+            out_time,
+            speed: None,
+            end: false,
+        };
+        let total = 20.0 + 2.0 + 2.0;
+        let update = progress.update_concat(&block(20.0), start);
+        assert!((update.percent - 22.0 / total * 100.0).abs() < 1e-9);
+        let update = progress.update_music(&block(5.0), start);
+        assert_eq!(update.stage, Stage::Music);
+        assert!((update.percent - 22.5 / total * 100.0).abs() < 1e-9);
+    }
+}
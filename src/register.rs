@@ -1,4 +1,9 @@
-use std::{ffi::OsStr, sync::Arc};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
 
 use tokio::{
     fs::{self, metadata},
@@ -8,81 +13,219 @@ use tokio::{
 };
 
 use crate::{
-    common::{DIR_PATH, MAX_CONCURRENT_FFMPEG, SharedState, Video, save_state},
+    common::{
+        DIR_PATH, MAX_CONCURRENT_FFMPEG, Provenance, SharedState, Video, content_id, same_content,
+    },
+    config::Config,
     fmt::faded,
-    util::{BoxedError, MyResult, format_size},
+    http_handler::probe::probe_video,
+    util::{MyResult, format_size},
 };
 
-pub async fn add_videos(path: &str, state: SharedState) -> MyResult<()> {
-    let mut entries = fs::read_dir(path).await?;
+/// Tag for videos the app cooked.
+pub const COOKED_TAG: &str = "cooked";
+
+/// Recursively lists the files under `root` that look like videos, skipping
+/// anything (files or whole folders) whose name is excluded.
+async fn find_videos(root: &Path, config: &Config) -> MyResult<Vec<PathBuf>> {
+    let mut dirs = vec![root.to_path_buf()];
     let mut paths = Vec::new();
-    {
-        let videos = &state.read().await.videos;
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            // the root not existing is a user error, but a subfolder we can't
+            // read (e.g. Android/data) shouldn't stop the whole scan
+            Err(err) if dir != root => {
+                eprintln!("Skipping {}: {err}", dir.display());
+                continue;
+            }
+            Err(err) => Err(err)?,
+        };
         while let Some(entry) = entries.next_entry().await? {
+            if config.is_excluded(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
             let path = entry.path();
-            if path.extension() == Some(&OsStr::new("mp4"))
-                && !videos.iter().any(|video| *video.current_loc() == path)
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if config.is_video(&path)
+                // don't descend into symlinked folders (could loop), but do
+                // follow symlinked files
+                && (file_type.is_file() || fs::metadata(&path).await.is_ok_and(|m| m.is_file()))
             {
                 paths.push(path);
             }
         }
     }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Grabs the first frame of the video as a small JPEG in the thumbnails folder.
+/// Returns whether ffmpeg succeeded; failures are logged but not fatal since
+/// the video is still usable without a thumbnail.
+pub async fn make_thumbnail(path: &Path, thumbnail_name: &str) -> MyResult<bool> {
+    let file_name = path
+        .file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    eprintln!(
+        "{}",
+        faded(&format!("Creating thumbnail for {file_name}..."))
+    );
+    let ffmpeg_result = Command::new("ffmpeg")
+        .arg("-i")
+        .arg(path)
+        .arg("-frames")
+        .arg("1")
+        // fix width to 256, autocompute height
+        .arg("-vf")
+        .arg("scale=256:-1")
+        // lowest quality
+        .arg("-q")
+        .arg("20")
+        .arg("-y")
+        .arg(format!("{DIR_PATH}/thumbs/{thumbnail_name}"))
+        // otherwise ffmpeg reads keypresses from the terminal
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !ffmpeg_result.status.success() {
+        eprintln!("Failed to create thumbnail for {file_name}.");
+        io::stderr().write_all(&ffmpeg_result.stderr).await?;
+    }
+    Ok(ffmpeg_result.status.success())
+}
 
+/// A tracked video with the [`content_id`] `id` whose file is gone, i.e. that
+/// was probably moved outside the app. Returns its ID and where it was.
+async fn find_moved(id: &str, state: &SharedState) -> Option<(String, PathBuf)> {
+    let candidates = state
+        .read()
+        .await
+        .videos
+        .iter()
+        .filter(|video| same_content(&video.id, id))
+        .map(|video| (video.id.clone(), video.current_loc().clone()))
+        .collect::<Vec<_>>();
+    for (id, path) in candidates {
+        if fs::try_exists(&path).await.is_ok_and(|exists| !exists) {
+            return Some((id, path));
+        }
+    }
+    None
+}
+
+async fn register_video(path: PathBuf, state: &SharedState) -> MyResult<()> {
+    let metadata = metadata(&path).await?;
+    let mtime = metadata.modified()?;
+    let size = metadata.len();
+    let id = content_id(&path).await?;
+    let file_name = path
+        .file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let moved = find_moved(&id, state).await;
+    let (id, thumbnail_name, moved_from) = {
+        let mut state = state.write().await;
+        // `add` and `watch` can race to register the same file
+        if state
+            .videos
+            .iter()
+            .any(|video| *video.current_loc() == path)
+        {
+            return Ok(());
+        }
+        // keeps its tags and thumbnail, unless another copy took it first
+        if let Some((moved_id, from)) = moved
+            && let Some(video) = state
+                .videos
+                .iter_mut()
+                .find(|video| video.id == moved_id && *video.current_loc() == from)
+        {
+            video.relocate(path.clone(), mtime, size);
+            (moved_id, video.thumbnail_name.clone(), Some(from))
+        } else {
+            let id = state.unique_id(id);
+            let thumbnail_name = format!("{id}.jpg");
+            state.videos.push(Video::new(
+                id.clone(),
+                path.clone(),
+                thumbnail_name.clone(),
+                mtime,
+                size,
+            ));
+            (id, thumbnail_name, None)
+        }
+    };
+    state.save_videos(&[id]).await?;
+    if let Some(from) = moved_from {
+        println!("{file_name} (moved from {})", from.display());
+    } else if make_thumbnail(&path, &thumbnail_name).await? {
+        println!("{file_name} ({})", format_size(size));
+    }
+    Ok(())
+}
+
+/// Starts tracking a video that was just cooked, tagged [`COOKED_TAG`] and with
+/// what went into it. Cooking to the same name again overwrites the file, in
+/// which case the video already being tracked gets updated instead. Returns
+/// the video's ID.
+pub async fn register_cooked(
+    path: PathBuf,
+    provenance: Provenance,
+    state: &SharedState,
+) -> MyResult<String> {
+    let metadata = metadata(&path).await?;
+    let mtime = metadata.modified()?;
+    let size = metadata.len();
+    let probe = probe_video(&path).await?;
+    let id = content_id(&path).await?;
+    let (id, thumbnail_name) = {
+        let mut state = state.write().await;
+        let index = match state
+            .videos
+            .iter()
+            .position(|video| *video.current_loc() == path)
+        {
+            Some(index) => index,
+            None => {
+                let id = state.unique_id(id);
+                let thumbnail_name = format!("{id}.jpg");
+                state
+                    .videos
+                    .push(Video::new(id, path.clone(), thumbnail_name, mtime, size));
+                state.videos.len() - 1
+            }
+        };
+        let video = &mut state.videos[index];
+        video.mtime = mtime;
+        video.size = size;
+        video.probe = Some(probe);
+        video.cooked_from = Some(provenance);
+        video.tags.insert(String::from(COOKED_TAG));
+        (video.id.clone(), video.thumbnail_name.clone())
+    };
+    state.save_videos(std::slice::from_ref(&id)).await?;
+    make_thumbnail(&path, &thumbnail_name).await?;
+    Ok(id)
+}
+
+/// Creates thumbnails for and starts tracking the given video files, a few at
+/// a time.
+pub async fn register_videos(paths: Vec<PathBuf>, state: &SharedState) {
     let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FFMPEG));
     let handles = paths
-        .iter()
+        .into_iter()
         .map(|path| {
-            let path = path.clone();
             let path_clone = path.clone();
             let state = state.clone();
             let semaphore = semaphore.clone();
             let handle = tokio::spawn(async move {
                 // _ will immediately drop the permit
                 let _permit = semaphore.acquire_owned().await?;
-                let file_name = path
-                    .file_name()
-                    .map(|s| s.to_string_lossy())
-                    .unwrap_or_default();
-                let metadata = metadata(&path).await?;
-                let mtime = metadata.modified()?;
-                let size = metadata.len();
-                let thumbnail_name = format!(
-                    "{}.jpg",
-                    sanitize_filename::sanitize(path.as_os_str().to_string_lossy())
-                );
-                eprintln!(
-                    "{}",
-                    faded(&format!("Creating thumbnail for {file_name}..."))
-                );
-                let ffmpeg_result = Command::new("ffmpeg")
-                    .arg("-i")
-                    .arg(&path)
-                    .arg("-frames")
-                    .arg("1")
-                    // fix width to 256, autocompute height
-                    .arg("-vf")
-                    .arg("scale=256:-1")
-                    // lowest quality
-                    .arg("-q")
-                    .arg("20")
-                    .arg("-y")
-                    .arg(format!("{DIR_PATH}/thumbs/{thumbnail_name}"))
-                    .output()
-                    .await?;
-                if ffmpeg_result.status.success() {
-                    println!("{file_name} ({})", format_size(size));
-                } else {
-                    eprintln!("Failed to create thumbnail for {file_name}.");
-                    io::stderr().write_all(&ffmpeg_result.stderr).await?;
-                }
-                {
-                    let mut state = state.write().await;
-                    state
-                        .videos
-                        .push(Video::new(path, thumbnail_name, mtime, size));
-                }
-                save_state(&*state.read().await).await?;
-                Ok::<(), BoxedError>(())
+                register_video(path, &state).await
             });
             (path_clone, handle)
         })
@@ -98,15 +241,182 @@ pub async fn add_videos(path: &str, state: SharedState) -> MyResult<()> {
             Ok(Ok(_)) => {}
         }
     }
+}
+
+/// `path` made absolute with symlinks resolved, so the same file compares
+/// equal however it was typed. Left alone if it doesn't exist.
+async fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .await
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Lists the videos under `root` that aren't being tracked yet.
+pub async fn find_new_videos(
+    root: &Path,
+    config: &Config,
+    state: &SharedState,
+) -> MyResult<Vec<PathBuf>> {
+    let found = find_videos(root, config).await?;
+    let tracked = state
+        .read()
+        .await
+        .videos
+        .iter()
+        .map(|video| video.current_loc().clone())
+        .collect::<Vec<_>>();
+    // older videos were added with paths relative to wherever `add` ran
+    let mut known = HashSet::new();
+    for path in tracked {
+        known.insert(canonical(&path).await);
+    }
+    let mut new = Vec::new();
+    for path in found {
+        if !known.contains(&canonical(&path).await) {
+            new.push(path);
+        }
+    }
+    Ok(new)
+}
+
+pub async fn add_videos(path: &str, config: &Config, state: SharedState) -> MyResult<()> {
+    // so the same folder typed differently (or from elsewhere) is one root
+    let root = fs::canonicalize(path).await?;
+    let paths = find_new_videos(&root, config, &state).await?;
+    let mut existing = None;
+    for other in state.read().await.roots.clone() {
+        if canonical(&other).await == root {
+            existing = Some(other);
+            break;
+        }
+    }
+    {
+        // remember the folder so `sync` knows where to look for new videos
+        let mut state = state.write().await;
+        // replaces roots from before they were canonicalized
+        state.roots.retain(|other| Some(other) != existing.as_ref());
+        state.roots.push(root);
+    }
+    state.save().await?;
+
+    let found_any = !paths.is_empty();
+    register_videos(paths, &state).await;
 
     // fix terminal from entering raw mode
     let _ = Command::new("stty").arg("sane").status().await?;
 
-    if paths.is_empty() {
-        eprintln!("No new .mp4 files found in {path}.");
-    } else {
+    if found_any {
         eprintln!("Done.");
+    } else {
+        eprintln!("No new videos found in {path}.");
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{AppState, State};
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_find_videos_recursive() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        for path in [
+            "a.mp4",
+            "notes.txt",
+            "2024-01-01/b.MOV",
+            "2024-01-01/nested/c.webm",
+            ".thumbnails/d.mp4",
+            ".trashed-1712345678-e.mp4",
+            "2024-01-02/.trashed-1712345678/f.mp4",
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::File::create(path).unwrap();
+        }
+
+        let found = find_videos(root, &Config::default()).await.unwrap();
+        let mut found = found
+            .iter()
+            .map(|path| {
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(
+            found,
+            vec!["2024-01-01/b.MOV", "2024-01-01/nested/c.webm", "a.mp4"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_new_videos_canonical() {
+        let dir = tempdir().unwrap();
+        let real = dir.path().join("real");
+        std::fs::create_dir(&real).unwrap();
+        std::fs::write(real.join("a.mp4"), b"a").unwrap();
+        std::fs::write(real.join("b.mp4"), b"b").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let state = AppState::for_tests(
+            State {
+                videos: vec![Video::new(
+                    String::from("a"),
+                    real.join("a.mp4"),
+                    String::from("a.jpg"),
+                    SystemTime::UNIX_EPOCH,
+                    1,
+                )],
+                ..State::default()
+            },
+            dir.path(),
+        )
+        .await;
+        // the same folder by another name shouldn't make a.mp4 look new
+        let found = find_new_videos(&link, &Config::default(), &state)
+            .await
+            .unwrap();
+        assert_eq!(found, vec![link.join("b.mp4")]);
+    }
+
+    #[tokio::test]
+    async fn test_register_moved_video() {
+        let dir = tempdir().unwrap();
+        let from = dir.path().join("a.mp4");
+        let to = dir.path().join("b.mp4");
+        std::fs::write(&to, b"a").unwrap();
+        let id = content_id(&to).await.unwrap();
+        let copy = dir.path().join("copy.mp4");
+        std::fs::write(&copy, b"a").unwrap();
+        let video = |id: &str, path: &Path| {
+            Video::new(
+                id.to_string(),
+                path.to_path_buf(),
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                1,
+            )
+        };
+        let state = AppState::for_tests(
+            State {
+                // the copy is still there, so it's the missing -2 that moved
+                videos: vec![video(&id, &copy), video(&format!("{id}-2"), &from)],
+                ..State::default()
+            },
+            dir.path(),
+        )
+        .await;
+        register_video(to.clone(), &state).await.unwrap();
+        let state = state.read().await;
+        assert_eq!(state.videos.len(), 2);
+        assert_eq!(*state.videos[1].current_loc(), to);
+        assert_eq!(state.videos[1].thumbnail_name, format!("{id}-2.jpg"));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::{
+    common::State,
+    fmt::{bold, faded},
+    migrations::{SCHEMA_VERSION, is_from_newer_version, migrate, schema_of},
+    storage::Storage,
+    util::{BoxedError, MyResult, write_atomic},
+};
+
+/// Keeps everything in one pretty-printed state.json, which is easy to read
+/// and fix by hand but gets rewritten in full on every change.
+pub struct JsonStorage {
+    dir: PathBuf,
+}
+
+impl JsonStorage {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> BoxFuture<'_, MyResult<Option<State>>> {
+        load_state_from(&self.dir).boxed()
+    }
+
+    fn save(&self, state: &State) -> BoxFuture<'_, MyResult<()>> {
+        let json = serde_json::to_string_pretty(state);
+        async move { write_atomic(&self.dir.join("state.json"), json?.as_bytes()).await }.boxed()
+    }
+}
+
+/// How many previous versions of state.json to keep in case it gets corrupted.
+const STATE_BACKUPS: usize = 3;
+
+/// Parses a state.json document, upgrading it first if it's from an older
+/// version. Also returns the schema it was stored in.
+fn parse_state(json: &str) -> MyResult<(State, u32)> {
+    let mut doc: Value = serde_json::from_str(json)?;
+    let schema = schema_of(&doc);
+    migrate(&mut doc)?;
+    Ok((serde_json::from_value(doc)?, schema))
+}
+
+/// Reads state.json. If it's unreadable, falls back to the newest backup that
+/// isn't.
+async fn load_state_from(dir: &Path) -> MyResult<Option<State>> {
+    let path = dir.join("state.json");
+    let err: BoxedError = match fs::read_to_string(&path).await {
+        Ok(json) => match parse_state(&json) {
+            Ok((state, schema)) => {
+                if schema != SCHEMA_VERSION {
+                    // keep a copy from before the upgrade in case a migration
+                    // is wrong
+                    let old_path = dir.join(format!("state.json.schema{schema}"));
+                    if !fs::try_exists(&old_path).await? {
+                        write_atomic(&old_path, json.as_bytes()).await?;
+                    }
+                    write_atomic(&path, serde_json::to_string_pretty(&state)?.as_bytes()).await?;
+                    eprintln!(
+                        "{}",
+                        faded(&format!(
+                            "Upgraded state.json from schema {schema} to {SCHEMA_VERSION}. The old version was saved to {}.",
+                            old_path.display()
+                        ))
+                    );
+                }
+                rotate_backups(dir, &json).await?;
+                return Ok(Some(state));
+            }
+            // an older backup would lose whatever the newer version did
+            Err(err) if is_from_newer_version(&json) => return Err(err),
+            Err(err) => err,
+        },
+        // saves are atomic, so a missing state.json means this is the first
+        // run (or someone deleted it on purpose), not a botched save
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => err.into(),
+    };
+
+    eprintln!(
+        "{}: Failed to read {}: {err}",
+        bold("Error"),
+        path.display()
+    );
+    for n in 1..=STATE_BACKUPS {
+        let backup_path = dir.join(format!("state.json.bak{n}"));
+        let Ok(json) = fs::read_to_string(&backup_path).await else {
+            continue;
+        };
+        let Ok((state, _)) = parse_state(&json) else {
+            continue;
+        };
+        // keep the broken file around for inspection rather than letting the
+        // next save overwrite it
+        let corrupt_path = dir.join("state.json.corrupt");
+        fs::rename(&path, &corrupt_path).await?;
+        write_atomic(&path, json.as_bytes()).await?;
+        eprintln!(
+            "Recovered from {}. The unreadable file was moved to {}.",
+            backup_path.display(),
+            corrupt_path.display()
+        );
+        return Ok(Some(state));
+    }
+    Err(err)
+}
+
+/// Shifts state.json.bak1 to bak2 and so on, then saves the given state.json
+/// contents as bak1. Does nothing if bak1 is already up to date, so restarting
+/// a few times doesn't push out the older backups.
+async fn rotate_backups(dir: &Path, json: &str) -> MyResult<()> {
+    let backup_path = |n: usize| dir.join(format!("state.json.bak{n}"));
+    if fs::read_to_string(backup_path(1))
+        .await
+        .is_ok_and(|backup| backup == json)
+    {
+        return Ok(());
+    }
+    for n in (1..STATE_BACKUPS).rev() {
+        match fs::rename(backup_path(n), backup_path(n + 1)).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            result => result?,
+        }
+    }
+    write_atomic(&backup_path(1), json.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_load_state_backups() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let state_json = |version: &str| {
+            serde_json::to_string(&State {
+                version: Some(version.to_string()),
+                ..State::default()
+            })
+            .unwrap()
+        };
+        let load_version = async || load_state_from(dir).await.unwrap().unwrap().version;
+
+        // first run
+        assert!(load_state_from(dir).await.unwrap().is_none());
+
+        std::fs::write(dir.join("state.json"), state_json("1")).unwrap();
+        assert_eq!(load_version().await.as_deref(), Some("1"));
+        // restarting without changes doesn't rotate
+        assert_eq!(load_version().await.as_deref(), Some("1"));
+        assert!(!dir.join("state.json.bak2").exists());
+        for version in ["2", "3", "4"] {
+            std::fs::write(dir.join("state.json"), state_json(version)).unwrap();
+            load_version().await;
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir.join("state.json.bak3")).unwrap(),
+            state_json("2")
+        );
+        assert!(!dir.join("state.json.bak4").exists());
+
+        // killed mid-write
+        std::fs::write(dir.join("state.json"), &state_json("5")[..10]).unwrap();
+        assert_eq!(load_version().await.as_deref(), Some("4"));
+        assert!(dir.join("state.json.corrupt").exists());
+        assert_eq!(load_version().await.as_deref(), Some("4"));
+    }
+
+    #[tokio::test]
+    async fn test_load_state_migrates() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let old_json = include_str!("../migrations/fixtures/0.12.3.json");
+        std::fs::write(dir.join("state.json"), old_json).unwrap();
+
+        let state = load_state_from(dir).await.unwrap().unwrap();
+        assert_eq!(state.schema, SCHEMA_VERSION);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("state.json.schema0")).unwrap(),
+            old_json
+        );
+        let saved: Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("state.json")).unwrap())
+                .unwrap();
+        assert_eq!(schema_of(&saved), SCHEMA_VERSION);
+
+        // a file from the future isn't mistaken for a corrupt one
+        std::fs::write(
+            dir.join("state.json"),
+            format!(r#"{{ "schema": {}, "videos": [] }}"#, SCHEMA_VERSION + 1),
+        )
+        .unwrap();
+        assert!(load_state_from(dir).await.is_err());
+        assert!(!dir.join("state.json.corrupt").exists());
+    }
+}
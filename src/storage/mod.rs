@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use futures_util::future::BoxFuture;
+use tokio::fs;
+
+use crate::{
+    common::{State, assign_missing_ids},
+    util::MyResult,
+};
+
+mod json;
+mod sqlite;
+
+pub use json::JsonStorage;
+pub use sqlite::SqliteStorage;
+
+/// Where the state gets persisted. The state itself always lives in memory;
+/// this only has to write changes out and read them back on startup.
+///
+/// Methods take a snapshot of what they need from `state` before returning, so
+/// the caller doesn't have to hold the state lock while the write happens.
+pub trait Storage: Send + Sync {
+    /// Returns `None` if nothing has been saved yet.
+    fn load(&self) -> BoxFuture<'_, MyResult<Option<State>>>;
+
+    fn save(&self, state: &State) -> BoxFuture<'_, MyResult<()>>;
+
+    /// Saves just the videos with these IDs. IDs that are no longer in `state`
+    /// were deleted.
+    fn save_videos(&self, state: &State, _ids: &[String]) -> BoxFuture<'_, MyResult<()>> {
+        self.save(state)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    Json,
+    Sqlite,
+}
+
+impl StorageKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(StorageKind::Json),
+            "sqlite" => Some(StorageKind::Sqlite),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StorageKind::Json => "json",
+            StorageKind::Sqlite => "sqlite",
+        }
+    }
+
+    fn file_name(&self) -> &'static str {
+        match self {
+            StorageKind::Json => "state.json",
+            StorageKind::Sqlite => "state.db",
+        }
+    }
+
+    /// state.db takes priority since it only exists after someone opted in
+    /// with `migrate-storage`.
+    pub async fn detect(dir: &Path) -> MyResult<Self> {
+        if fs::try_exists(dir.join(StorageKind::Sqlite.file_name())).await? {
+            Ok(StorageKind::Sqlite)
+        } else {
+            Ok(StorageKind::Json)
+        }
+    }
+
+    pub fn open(&self, dir: &Path) -> MyResult<Box<dyn Storage>> {
+        Ok(match self {
+            StorageKind::Json => Box::new(JsonStorage::new(dir)),
+            StorageKind::Sqlite => Box::new(SqliteStorage::open(&dir.join(self.file_name()))?),
+        })
+    }
+}
+
+/// Copies the state into the other kind of storage, then renames the old file
+/// out of the way so the new one gets used from now on. Videos from before IDs
+/// get theirs first, since SQLite needs them to be unique.
+pub async fn migrate_storage(dir: &Path, from: StorageKind, to: StorageKind) -> MyResult<()> {
+    if from == to {
+        Err(format!("Already using {} storage.", to.name()))?;
+    }
+    let mut state = from.open(dir)?.load().await?.unwrap_or_default();
+    assign_missing_ids(&mut state).await;
+    to.open(dir)?.save(&state).await?;
+    let old_path = dir.join(from.file_name());
+    if fs::try_exists(&old_path).await? {
+        let mut retired_path = old_path.clone().into_os_string();
+        retired_path.push(".migrated");
+        fs::rename(&old_path, retired_path).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Video;
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_migrate_storage() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        assert_eq!(StorageKind::detect(dir).await.unwrap(), StorageKind::Json);
+        // videos from before IDs, which would clash in SQLite
+        let legacy = |name: &str| {
+            Video::new(
+                String::new(),
+                format!("{name}.mp4").into(),
+                format!("{name}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                1,
+            )
+        };
+        let state = State {
+            videos: vec![legacy("a"), legacy("b")],
+            roots: vec!["storage/dcim".into()],
+            ..State::default()
+        };
+        JsonStorage::new(dir).save(&state).await.unwrap();
+
+        migrate_storage(dir, StorageKind::Json, StorageKind::Sqlite)
+            .await
+            .unwrap();
+        assert_eq!(StorageKind::detect(dir).await.unwrap(), StorageKind::Sqlite);
+        assert!(dir.join("state.json.migrated").exists());
+        let loaded = StorageKind::Sqlite
+            .open(dir)
+            .unwrap()
+            .load()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.roots, state.roots);
+        assert_eq!(loaded.videos.len(), 2);
+        assert!(loaded.videos.iter().all(|video| !video.id.is_empty()));
+        assert_ne!(loaded.videos[0].id, loaded.videos[1].id);
+
+        migrate_storage(dir, StorageKind::Sqlite, StorageKind::Json)
+            .await
+            .unwrap();
+        assert_eq!(StorageKind::detect(dir).await.unwrap(), StorageKind::Json);
+        assert!(dir.join("state.db.migrated").exists());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use futures_util::{FutureExt, future::BoxFuture};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde_json::Value;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    common::State,
+    migrations::{SCHEMA_VERSION, migrate, schema_of},
+    storage::Storage,
+    util::MyResult,
+};
+
+/// Keeps each video in its own row, so editing a tag only rewrites that video.
+/// Rows hold the same JSON as state.json so the migrations work on both.
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    // a panic mid-transaction rolls it back, so the connection is fine
+    connection
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The whole state as rows, taken before the write so it can happen on another
+/// thread.
+struct Rows {
+    meta: String,
+    /// (ID, JSON) in order
+    videos: Vec<(String, String)>,
+}
+
+impl Rows {
+    fn of(state: &State) -> MyResult<Self> {
+        let mut meta = serde_json::to_value(state)?;
+        // those go in their own table
+        meta["videos"] = Value::Array(Vec::new());
+        Ok(Self {
+            meta: serde_json::to_string(&meta)?,
+            videos: state
+                .videos
+                .iter()
+                .map(|video| Ok((video.id.clone(), serde_json::to_string(video)?)))
+                .collect::<MyResult<_>>()?,
+        })
+    }
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> MyResult<Self> {
+        let connection = Connection::open(path)?;
+        // the write-ahead log survives the process being killed mid-write,
+        // which Android loves to do
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS meta (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS videos (
+                id TEXT PRIMARY KEY,
+                position INTEGER NOT NULL,
+                data TEXT NOT NULL
+            );",
+        )?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` on a blocking thread, since sqlite calls (and waiting for the
+    /// connection) would otherwise stall the runtime.
+    fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> MyResult<T> + Send + 'static,
+    ) -> BoxFuture<'static, MyResult<T>> {
+        let connection = self.connection.clone();
+        async move { spawn_blocking(move || f(&mut lock(&connection))).await? }.boxed()
+    }
+}
+
+fn load_sync(connection: &mut Connection) -> MyResult<Option<State>> {
+    let Some(meta) = connection
+        .query_row("SELECT value FROM meta WHERE key = 'state'", [], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let mut doc: Value = serde_json::from_str(&meta)?;
+    let videos = connection
+        .prepare("SELECT data FROM videos ORDER BY position")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .map(|data| Ok(serde_json::from_str(&data?)?))
+        .collect::<MyResult<Vec<Value>>>()?;
+    doc["videos"] = Value::Array(videos);
+
+    let schema = schema_of(&doc);
+    migrate(&mut doc)?;
+    let state: State = serde_json::from_value(doc)?;
+    if schema != SCHEMA_VERSION {
+        let transaction = connection.transaction()?;
+        save_all(&transaction, &Rows::of(&state)?)?;
+        transaction.commit()?;
+    }
+    Ok(Some(state))
+}
+
+/// Writes the given videos, where `None` means it was deleted.
+fn save_videos_sync(
+    connection: &mut Connection,
+    videos: &[(String, Option<String>)],
+) -> MyResult<()> {
+    let transaction = connection.transaction()?;
+    for (id, data) in videos {
+        match data {
+            Some(data) => {
+                let updated = transaction.execute(
+                    "UPDATE videos SET data = ?1 WHERE id = ?2",
+                    params![data, id],
+                )?;
+                if updated == 0 {
+                    // new videos always go at the end
+                    transaction.execute(
+                        "INSERT INTO videos (id, position, data)
+                        VALUES (?1, (SELECT COALESCE(MAX(position) + 1, 0) FROM videos), ?2)",
+                        params![id, data],
+                    )?;
+                }
+            }
+            None => {
+                transaction.execute("DELETE FROM videos WHERE id = ?1", params![id])?;
+            }
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Replaces everything in the database with the given state.
+fn save_all(transaction: &Transaction, rows: &Rows) -> MyResult<()> {
+    transaction.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES ('state', ?1)",
+        params![rows.meta],
+    )?;
+    transaction.execute("DELETE FROM videos", [])?;
+    let mut insert =
+        transaction.prepare("INSERT INTO videos (id, position, data) VALUES (?1, ?2, ?3)")?;
+    for (position, (id, data)) in rows.videos.iter().enumerate() {
+        insert.execute(params![id, position as i64, data])?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> BoxFuture<'_, MyResult<Option<State>>> {
+        self.with_connection(load_sync)
+    }
+
+    fn save(&self, state: &State) -> BoxFuture<'_, MyResult<()>> {
+        let rows = match Rows::of(state) {
+            Ok(rows) => rows,
+            Err(err) => return async move { Err(err) }.boxed(),
+        };
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            save_all(&transaction, &rows)?;
+            transaction.commit()?;
+            Ok(())
+        })
+    }
+
+    fn save_videos(&self, state: &State, ids: &[String]) -> BoxFuture<'_, MyResult<()>> {
+        let videos = ids
+            .iter()
+            .map(|id| {
+                let data = match state.videos.iter().find(|video| video.id == *id) {
+                    Some(video) => Some(serde_json::to_string(video)?),
+                    None => None,
+                };
+                Ok((id.clone(), data))
+            })
+            .collect::<MyResult<Vec<_>>>();
+        let videos = match videos {
+            Ok(videos) => videos,
+            Err(err) => return async move { Err(err) }.boxed(),
+        };
+        self.with_connection(move |connection| save_videos_sync(connection, &videos))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Video;
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    fn video(id: &str) -> Video {
+        Video::new(
+            id.to_string(),
+            format!("{id}.mp4").into(),
+            format!("{id}.jpg"),
+            SystemTime::UNIX_EPOCH,
+            1,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.db");
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert!(storage.load().await.unwrap().is_none());
+
+        let mut state = State {
+            videos: vec![video("a"), video("b"), video("c")],
+            roots: vec!["storage/dcim".into()],
+            ..State::default()
+        };
+        storage.save(&state).await.unwrap();
+
+        // row-level changes: edit one, delete one, add one
+        state.videos[0].tags.insert("beach".to_string());
+        state.videos.remove(1);
+        state.videos.push(video("d"));
+        storage
+            .save_videos(&state, &["a".into(), "b".into(), "d".into()])
+            .await
+            .unwrap();
+
+        drop(storage);
+        let loaded = SqliteStorage::open(&path)
+            .unwrap()
+            .load()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            loaded
+                .videos
+                .iter()
+                .map(|video| video.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "c", "d"]
+        );
+        assert!(loaded.videos[0].tags.contains("beach"));
+        assert_eq!(loaded.roots, state.roots);
+        assert_eq!(loaded.schema, SCHEMA_VERSION);
+    }
+}
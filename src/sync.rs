@@ -0,0 +1,392 @@
+use std::{io::ErrorKind, path::PathBuf, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    common::{DIR_PATH, SharedState, content_id, same_content},
+    config::Config,
+    http_handler::probe::probe_video,
+    register::{find_new_videos, make_thumbnail, register_videos},
+    util::MyResult,
+};
+
+/// What to do about the differences found. With everything off, `sync` only
+/// reports. Videos that were moved get their new path with `prune` or `add`,
+/// since either would otherwise lose their tags.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SyncOptions {
+    /// stop tracking videos whose file no longer exists
+    pub prune: bool,
+    /// re-run ffprobe on videos whose file changed
+    pub reprobe: bool,
+    /// regenerate thumbnails for videos whose file changed
+    pub rethumbnail: bool,
+    /// start tracking new videos found in previously added folders
+    pub add: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MissingVideo {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+/// A missing video whose file turned up somewhere new, going by its
+/// [`content_id`].
+#[derive(Serialize, Debug)]
+pub struct MovedVideo {
+    pub id: String,
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChangedVideo {
+    pub id: String,
+    /// not part of the report, just needed to regenerate the thumbnail
+    #[serde(skip)]
+    thumbnail_name: String,
+    pub path: PathBuf,
+    pub old_size: u64,
+    pub size: u64,
+    pub old_mtime: SystemTime,
+    pub mtime: SystemTime,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub missing: Vec<MissingVideo>,
+    /// not in `missing` or `new`
+    pub moved: Vec<MovedVideo>,
+    pub changed: Vec<ChangedVideo>,
+    pub new: Vec<PathBuf>,
+    /// added folders that couldn't be scanned, e.g. because they were deleted
+    pub unreadable_roots: Vec<PathBuf>,
+    pub pruned: usize,
+    pub relocated: usize,
+    pub reprobed: usize,
+    pub rethumbnailed: usize,
+    pub added: usize,
+}
+
+async fn find_differences(config: &Config, state: &SharedState) -> MyResult<SyncReport> {
+    let (videos, roots) = {
+        let state = state.read().await;
+        let videos = state
+            .videos
+            .iter()
+            .map(|video| {
+                (
+                    video.id.clone(),
+                    video.thumbnail_name.clone(),
+                    video.current_loc().clone(),
+                    video.size,
+                    video.mtime,
+                )
+            })
+            .collect::<Vec<_>>();
+        (videos, state.roots.clone())
+    };
+
+    let mut report = SyncReport::default();
+    for (id, thumbnail_name, path, old_size, old_mtime) in videos {
+        match fs::metadata(&path).await {
+            Ok(metadata) => {
+                let size = metadata.len();
+                let mtime = metadata.modified()?;
+                if size != old_size || mtime != old_mtime {
+                    report.changed.push(ChangedVideo {
+                        id,
+                        thumbnail_name,
+                        path,
+                        old_size,
+                        size,
+                        old_mtime,
+                        mtime,
+                    });
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                report.missing.push(MissingVideo { id, path });
+            }
+            Err(err) => {
+                eprintln!("Can't check {}: {err}", path.display());
+            }
+        }
+    }
+    for root in roots {
+        match find_new_videos(&root, config, state).await {
+            Ok(paths) => report.new.extend(paths),
+            Err(err) => {
+                eprintln!("Can't scan {}: {err}", root.display());
+                report.unreadable_roots.push(root);
+            }
+        }
+    }
+    if !report.missing.is_empty() {
+        let mut new = Vec::new();
+        for path in report.new.drain(..) {
+            let id = match content_id(&path).await {
+                Ok(id) => id,
+                Err(err) => {
+                    eprintln!("Can't read {}: {err}", path.display());
+                    new.push(path);
+                    continue;
+                }
+            };
+            match report
+                .missing
+                .iter()
+                .position(|missing| same_content(&missing.id, &id))
+            {
+                Some(index) => {
+                    let MissingVideo { id, path: from } = report.missing.remove(index);
+                    report.moved.push(MovedVideo { id, from, to: path });
+                }
+                None => new.push(path),
+            }
+        }
+        report.new = new;
+    }
+    Ok(report)
+}
+
+/// Compares the tracked videos against what's actually on disk, then fixes up
+/// the state according to `options`.
+pub async fn sync_videos(
+    options: &SyncOptions,
+    config: &Config,
+    state: &SharedState,
+) -> MyResult<SyncReport> {
+    let mut report = find_differences(config, state).await?;
+    let mut touched = Vec::new();
+
+    if options.reprobe || options.rethumbnail {
+        for changed in &report.changed {
+            let probe = if options.reprobe {
+                match probe_video(&changed.path).await {
+                    Ok(probe) => Some(probe),
+                    Err(err) => {
+                        eprintln!("Failed to probe {}: {err}", changed.path.display());
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            if options.rethumbnail && make_thumbnail(&changed.path, &changed.thumbnail_name).await?
+            {
+                report.rethumbnailed += 1;
+            }
+            let mut state = state.write().await;
+            let Some(video) = state.find_video_mut(&changed.id) else {
+                continue;
+            };
+            video.size = changed.size;
+            video.mtime = changed.mtime;
+            touched.push(changed.id.clone());
+            // a failed probe keeps the old one, which is likely still close
+            if let Some(probe) = probe {
+                report.reprobed += 1;
+                video.probe = Some(probe);
+            }
+        }
+    }
+
+    if options.prune || options.add {
+        for moved in &report.moved {
+            let metadata = fs::metadata(&moved.to).await?;
+            let mut state = state.write().await;
+            // unless it got moved again in the meantime
+            let Some(video) = state
+                .videos
+                .iter_mut()
+                .find(|video| video.id == moved.id && *video.current_loc() == moved.from)
+            else {
+                continue;
+            };
+            video.relocate(moved.to.clone(), metadata.modified()?, metadata.len());
+            touched.push(moved.id.clone());
+            report.relocated += 1;
+        }
+    }
+
+    if options.prune && !report.missing.is_empty() {
+        let pruned = {
+            let mut state = state.write().await;
+            let (pruned, remaining) = state.videos.drain(..).partition::<Vec<_>, _>(|video| {
+                report.missing.iter().any(|missing| missing.id == video.id)
+            });
+            state.videos = remaining;
+            pruned
+        };
+        report.pruned = pruned.len();
+        for video in pruned {
+            touched.push(video.id.clone());
+            fs::remove_file(format!("{DIR_PATH}/thumbs/{}", video.thumbnail_name))
+                .await
+                .or_else(|err| {
+                    if err.kind() == ErrorKind::NotFound {
+                        Ok(())
+                    } else {
+                        Err(err)
+                    }
+                })?;
+        }
+    }
+
+    if !touched.is_empty() {
+        state.save_videos(&touched).await?;
+    }
+
+    if options.add && !report.new.is_empty() {
+        let before = state.read().await.videos.len();
+        register_videos(report.new.clone(), state).await;
+        report.added = state.read().await.videos.len() - before;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{AppState, State, Video};
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_find_differences() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let video = |name: &str| {
+            let path = root.join(name);
+            std::fs::write(&path, b"video").unwrap();
+            let metadata = std::fs::metadata(&path).unwrap();
+            Video::new(
+                format!("{name}-id"),
+                path,
+                format!("{name}.jpg"),
+                metadata.modified().unwrap(),
+                metadata.len(),
+            )
+        };
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video("same.mp4"), video("gone.mp4"), video("edited.mp4")],
+                roots: vec![root.to_path_buf()],
+                ..State::default()
+            },
+            root,
+        )
+        .await;
+        std::fs::remove_file(root.join("gone.mp4")).unwrap();
+        std::fs::write(root.join("edited.mp4"), b"longer video").unwrap();
+        std::fs::write(root.join("new.mp4"), b"video").unwrap();
+
+        let report = find_differences(&Config::default(), &state).await.unwrap();
+        assert_eq!(
+            report
+                .missing
+                .iter()
+                .map(|v| v.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["gone.mp4-id"]
+        );
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].id, "edited.mp4-id");
+        assert_eq!(report.changed[0].old_size, 5);
+        assert_eq!(report.changed[0].size, 12);
+        assert_eq!(report.new, vec![root.join("new.mp4")]);
+    }
+
+    #[tokio::test]
+    async fn test_moved_video() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let from = root.join("a.mp4");
+        std::fs::write(&from, b"video").unwrap();
+        let mut video = Video::new(
+            content_id(&from).await.unwrap(),
+            from.clone(),
+            String::from("a.jpg"),
+            SystemTime::UNIX_EPOCH,
+            5,
+        );
+        video.tags.insert(String::from("keep"));
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video],
+                roots: vec![root.to_path_buf()],
+                ..State::default()
+            },
+            root,
+        )
+        .await;
+        let to = root.join("sorted/a.mp4");
+        std::fs::create_dir(root.join("sorted")).unwrap();
+        std::fs::rename(&from, &to).unwrap();
+
+        let options = SyncOptions {
+            prune: true,
+            add: true,
+            ..SyncOptions::default()
+        };
+        let report = sync_videos(&options, &Config::default(), &state)
+            .await
+            .unwrap();
+        assert!(report.missing.is_empty());
+        assert!(report.new.is_empty());
+        assert_eq!(report.moved.len(), 1);
+        assert_eq!(report.moved[0].to, to);
+        assert_eq!((report.relocated, report.pruned, report.added), (1, 0, 0));
+        let state = state.read().await;
+        assert_eq!(state.videos.len(), 1);
+        assert_eq!(*state.videos[0].current_loc(), to);
+        assert!(state.videos[0].tags.contains("keep"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_reprobe_keeps_probe() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let path = root.join("video.mp4");
+        std::fs::write(&path, b"not really a video").unwrap();
+        let mut video = Video::new(
+            String::from("video"),
+            path,
+            String::from("video.jpg"),
+            SystemTime::UNIX_EPOCH,
+            0,
+        );
+        video.probe = Some(
+            serde_json::from_value(serde_json::json!({
+                "width": 1920, "height": 1080, "duration": 10, "rotation": "Unrotated",
+                "pix_fmt": "yuv420p", "color_space": null, "color_transfer": null,
+                "color_primaries": null, "bit_rate": 0, "audio": null
+            }))
+            .unwrap(),
+        );
+        let state = AppState::for_tests(
+            State {
+                videos: vec![video],
+                ..State::default()
+            },
+            root,
+        )
+        .await;
+        let options = SyncOptions {
+            reprobe: true,
+            ..SyncOptions::default()
+        };
+        let report = sync_videos(&options, &Config::default(), &state)
+            .await
+            .unwrap();
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.reprobed, 0);
+        let state = state.read().await;
+        assert_eq!(state.videos[0].probe.as_ref().unwrap().width, 1920);
+        assert_eq!(state.videos[0].size, 18);
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tokio::{fs, time};
+
+use crate::{
+    common::{DIR_PATH, SharedState, Video},
+    config::Config,
+    fmt::faded,
+    util::MyResult,
+};
+
+pub fn trash_dir() -> PathBuf {
+    Path::new(DIR_PATH).join("trash")
+}
+
+async fn remove_if_exists(path: impl AsRef<Path>) -> MyResult<()> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err)?,
+        _ => Ok(()),
+    }
+}
+
+/// Permanently deletes the trashed videos that `should_purge` picks, along with
+/// their thumbnails. Returns how many were deleted. Videos whose files can't be
+/// deleted are kept, and the rest are still saved before the error is returned.
+async fn purge_videos(
+    state: &SharedState,
+    should_purge: impl Fn(&Video) -> bool,
+) -> MyResult<usize> {
+    let mut ids = Vec::new();
+    let mut errors = Vec::new();
+    {
+        let mut state = state.write().await;
+        for video in &state.videos {
+            if video.trashed_at().is_none() || !should_purge(video) {
+                continue;
+            }
+            if let Err(err) = remove_if_exists(video.current_loc()).await {
+                errors.push(format!("{}: {err}", video.display_name()));
+                continue;
+            }
+            println!("D {:?}", video.display_name());
+            ids.push(video.id.clone());
+            // preview video from older versions
+            let preview = format!("{DIR_PATH}/thumbs/{}.mp4", video.thumbnail_name);
+            let thumbnail = format!("{DIR_PATH}/thumbs/{}", video.thumbnail_name);
+            for path in [preview, thumbnail] {
+                if let Err(err) = remove_if_exists(&path).await {
+                    eprintln!("[trash] Failed to delete {path}: {err}");
+                }
+            }
+        }
+        state.videos.retain(|video| !ids.contains(&video.id));
+    }
+    if !ids.is_empty() {
+        state.save_videos(&ids).await?;
+        state.journal().await.forget(&ids).await?;
+    }
+    if !errors.is_empty() {
+        Err(format!(
+            "Deleted {} videos, but couldn't delete:\n{}",
+            ids.len(),
+            errors.join("\n")
+        ))?;
+    }
+    Ok(ids.len())
+}
+
+/// Permanently deletes everything in the trash.
+pub async fn empty_trash(state: &SharedState) -> MyResult<usize> {
+    purge_videos(state, |_| true).await
+}
+
+/// Permanently deletes videos that have been in the trash longer than the
+/// retention period.
+pub async fn purge_expired(config: &Config, state: &SharedState) -> MyResult<usize> {
+    let retention = Duration::from_secs(config.trash_retention_days * 24 * 60 * 60);
+    let now = SystemTime::now();
+    purge_videos(state, |video| {
+        video
+            .trashed_at()
+            .is_some_and(|at| now.duration_since(at).unwrap_or_default() >= retention)
+    })
+    .await
+}
+
+/// Purges expired videos now and then every hour while the server runs.
+pub async fn purge_periodically(config: Config, state: SharedState) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        match purge_expired(&config, &state).await {
+            Ok(0) => {}
+            Ok(count) => eprintln!(
+                "{}",
+                faded(&format!(
+                    "[trash] Deleted {count} videos from the trash for good"
+                ))
+            ),
+            Err(err) => eprintln!("[trash] Failed to empty old videos from the trash: {err:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{AppState, State},
+        journal::Operation,
+    };
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let trash = dir.join("trash");
+        std::fs::create_dir(&trash).unwrap();
+        let mut videos = Vec::new();
+        for id in ["kept", "trashed"] {
+            let path = dir.join(format!("{id}.mp4"));
+            std::fs::write(&path, b"video").unwrap();
+            videos.push(Video::new(
+                id.into(),
+                path,
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                5,
+            ));
+        }
+        videos[1].trash(&trash).await.unwrap();
+        let state = AppState::for_tests(
+            State {
+                videos,
+                ..State::default()
+            },
+            dir,
+        )
+        .await;
+        let mut operation = Operation::new("Delete".into());
+        operation.record(None, Some(state.read().await.videos[1].clone()));
+        state.record(operation).await.unwrap();
+
+        let config = Config {
+            trash_retention_days: 1,
+            ..Config::default()
+        };
+        // too recent
+        assert_eq!(purge_expired(&config, &state).await.unwrap(), 0);
+        let config = Config {
+            trash_retention_days: 0,
+            ..Config::default()
+        };
+        assert_eq!(purge_expired(&config, &state).await.unwrap(), 1);
+        let state_ref = state.read().await;
+        assert_eq!(state_ref.videos.len(), 1);
+        assert_eq!(state_ref.videos[0].id, "kept");
+        assert!(!trash.join("trashed.mp4").exists());
+        assert!(dir.join("kept.mp4").exists());
+        drop(state_ref);
+        // nothing to undo now that it's gone
+        assert_eq!(state.journal().await.history().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_purge_keeps_undeletable() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path();
+        let trash = dir.join("trash");
+        std::fs::create_dir(&trash).unwrap();
+        let mut videos = Vec::new();
+        for id in ["stuck", "gone"] {
+            let path = dir.join(format!("{id}.mp4"));
+            std::fs::write(&path, b"video").unwrap();
+            let mut video = Video::new(
+                id.into(),
+                path,
+                format!("{id}.jpg"),
+                SystemTime::UNIX_EPOCH,
+                5,
+            );
+            video.trash(&trash).await.unwrap();
+            videos.push(video);
+        }
+        // a folder where the file should be can't be removed like one
+        let stuck = videos[0].current_loc().clone();
+        std::fs::remove_file(&stuck).unwrap();
+        std::fs::create_dir(&stuck).unwrap();
+        let state = AppState::for_tests(
+            State {
+                videos,
+                ..State::default()
+            },
+            dir,
+        )
+        .await;
+        for video in state.read().await.videos.clone() {
+            let mut operation = Operation::new(format!("Delete {}", video.id));
+            operation.record(None, Some(video));
+            state.record(operation).await.unwrap();
+        }
+
+        assert!(empty_trash(&state).await.is_err());
+        let state_ref = state.read().await;
+        assert_eq!(state_ref.videos.len(), 1);
+        assert_eq!(state_ref.videos[0].id, "stuck");
+        drop(state_ref);
+        let history = state
+            .journal()
+            .await
+            .history()
+            .map(|(operation, _)| operation.description.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(history, vec![String::from("Delete stuck")]);
+    }
+}
@@ -1,3 +1,10 @@
+use std::{io::ErrorKind, path::Path, time::Duration};
+
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
 pub type BoxedError = Box<dyn std::error::Error + Send + Sync>;
 pub type MyResult<T> = Result<T, BoxedError>;
 
@@ -20,6 +27,112 @@ pub fn format_size(size: u64) -> String {
     }
 }
 
+/// Roughly how long ago something happened, like "5 min ago".
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs < 60 {
+        String::from("just now")
+    } else if secs < 60 * 60 {
+        format!("{} min ago", secs / 60)
+    } else if secs < 24 * 60 * 60 {
+        format!("{} h ago", secs / 60 / 60)
+    } else {
+        format!("{} d ago", secs / 24 / 60 / 60)
+    }
+}
+
+/// Replaces the file's contents such that, even if the process is killed
+/// partway, the file has either the old or the new contents, never something
+/// truncated. Writes to a temporary file, flushes it to disk, then renames it
+/// over the original.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> MyResult<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut file = File::create(&temp_path).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temp_path, path).await?;
+    // the rename itself only survives a crash once the folder is flushed too
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        File::open(parent).await?.sync_all().await?;
+    }
+    Ok(())
+}
+
+/// Moves a file, even to another filesystem, like from shared storage to the
+/// Termux home folder. `rename` can't do that, so then it gets copied instead.
+pub async fn move_file(from: &Path, to: &Path) -> MyResult<()> {
+    match fs::rename(from, to).await {
+        Err(err) if err.kind() == ErrorKind::CrossesDevices => copy_then_remove(from, to).await,
+        result => Ok(result?),
+    }
+}
+
+/// The slow way to move a file. The copy is flushed to disk before the original
+/// gets deleted, and it has a temporary name until then so a crash can't leave
+/// half a video where the whole one should be.
+async fn copy_then_remove(from: &Path, to: &Path) -> MyResult<()> {
+    let mut temp_path = to.as_os_str().to_owned();
+    temp_path.push(".partial");
+    let copied = async {
+        fs::copy(from, &temp_path).await?;
+        File::open(&temp_path).await?.sync_all().await?;
+        fs::rename(&temp_path, to).await?;
+        Ok::<_, BoxedError>(())
+    }
+    .await;
+    if let Err(err) = copied {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+    fs::remove_file(from).await?;
+    Ok(())
+}
+
+/// 64-bit FNV-1a. Unlike `DefaultHasher`, the output is guaranteed to stay the
+/// same across Rust versions, so it's safe to persist.
+pub fn fnv1a(bytes: &[u8], mut hash: u64) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+/// Matches `text` against a shell-style pattern where `*` matches any run of
+/// characters and `?` matches exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position of the last * and the text index it was tried at, for
+    // backtracking
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the * swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,4 +148,72 @@ mod tests {
         assert_eq!(format_size(1_000_000_000), "1.00 GB");
         assert_eq!(format_size(1_000_000_000_000), "1.00 TB");
     }
+
+    #[test]
+    fn test_format_age() {
+        assert_eq!(format_age(Duration::from_secs(5)), "just now");
+        assert_eq!(format_age(Duration::from_secs(150)), "2 min ago");
+        assert_eq!(format_age(Duration::from_secs(3 * 60 * 60)), "3 h ago");
+        assert_eq!(format_age(Duration::from_secs(50 * 60 * 60)), "2 d ago");
+    }
+
+    #[tokio::test]
+    async fn test_move_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("a.mp4");
+        let to = dir.path().join("b.mp4");
+        std::fs::write(&from, b"video").unwrap();
+        move_file(&from, &to).await.unwrap();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"video");
+
+        // what happens across filesystems, where rename fails
+        copy_then_remove(&to, &from).await.unwrap();
+        assert!(!to.exists());
+        assert_eq!(std::fs::read(&from).unwrap(), b"video");
+        assert!(!dir.path().join("a.mp4.partial").exists());
+
+        // a failed copy keeps the original
+        let missing = dir.path().join("missing/c.mp4");
+        assert!(copy_then_remove(&from, &missing).await.is_err());
+        assert!(from.exists());
+    }
+
+    #[tokio::test]
+    async fn test_write_atomic() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        write_atomic(&path, b"old").await.unwrap();
+        write_atomic(&path, b"new").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert!(!dir.path().join("state.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // test vectors from the FNV reference
+        assert_eq!(fnv1a(b"", FNV_OFFSET_BASIS), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a", FNV_OFFSET_BASIS), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(b"foobar", FNV_OFFSET_BASIS), 0x85944171f73967e8);
+        // hashing in pieces is the same as hashing all at once
+        assert_eq!(
+            fnv1a(b"bar", fnv1a(b"foo", FNV_OFFSET_BASIS)),
+            fnv1a(b"foobar", FNV_OFFSET_BASIS)
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(".thumbnails", ".thumbnails"));
+        assert!(!glob_match(".thumbnails", ".thumbnails2"));
+        assert!(glob_match(".trashed-*", ".trashed-1712345678-VID.mp4"));
+        assert!(glob_match(".trashed-*", ".trashed-"));
+        assert!(!glob_match(".trashed-*", "trashed-1"));
+        assert!(glob_match("*.tmp", "a.b.tmp"));
+        assert!(glob_match("VID_????.mp4", "VID_0001.mp4"));
+        assert!(!glob_match("VID_????.mp4", "VID_001.mp4"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("*a*b*", "xxbxxaxx"));
+        assert!(glob_match("*", ""));
+    }
 }
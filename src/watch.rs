@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::{fs, sync::mpsc, time};
+
+use crate::{
+    common::SharedState, config::Config, fmt::faded, register::register_videos, util::MyResult,
+};
+
+/// How long a file has to go without any filesystem events before we check
+/// whether it's done being written. Camera apps write in bursts, so this is a
+/// bit generous.
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// A file that changed recently and might be a new video.
+struct PendingFile {
+    last_event: Instant,
+    /// size when the file last went quiet, to make sure it's stopped growing
+    size: Option<u64>,
+}
+
+/// Returns the path in the same form `add` would have stored it if it's a video
+/// that should be registered. notify reports absolute paths, so `roots` pairs
+/// each absolute watched folder with the path originally passed to `add`.
+fn to_candidate(path: &Path, roots: &[(PathBuf, PathBuf)], config: &Config) -> Option<PathBuf> {
+    if !config.is_video(path) {
+        return None;
+    }
+    let (root, relative) = roots.iter().find_map(|(absolute, root)| {
+        path.strip_prefix(absolute)
+            .ok()
+            .map(|relative| (root, relative))
+    })?;
+    // only check the part of the path inside the watched folder, so the folder
+    // itself can't be excluded by accident
+    if relative
+        .components()
+        .any(|component| config.is_excluded(&component.as_os_str().to_string_lossy()))
+    {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+/// Checks the pending files, returning the ones whose size hasn't changed since
+/// they last went quiet. Files that disappeared are forgotten.
+async fn take_settled(pending: &mut HashMap<PathBuf, PendingFile>) -> Vec<PathBuf> {
+    let now = Instant::now();
+    let mut settled = Vec::new();
+    let mut gone = Vec::new();
+    for (path, file) in pending.iter_mut() {
+        if now.duration_since(file.last_event) < SETTLE_TIME {
+            continue;
+        }
+        match fs::metadata(path).await {
+            Ok(metadata) if file.size == Some(metadata.len()) => settled.push(path.clone()),
+            Ok(metadata) => {
+                // give it another round to make sure it's not still growing
+                file.size = Some(metadata.len());
+                file.last_event = now;
+            }
+            Err(_) => gone.push(path.clone()),
+        }
+    }
+    for path in settled.iter().chain(&gone) {
+        pending.remove(path);
+    }
+    settled
+}
+
+/// Watches the folders previously passed to `add` and registers new videos as
+/// they finish being written. Runs until the watcher fails.
+pub async fn watch_videos(config: Config, state: SharedState) -> MyResult<()> {
+    let roots = state.read().await.roots.clone();
+    if roots.is_empty() {
+        eprintln!("No folders to watch yet. Add some videos first.");
+        return Ok(());
+    }
+
+    let cwd = env::current_dir()?;
+    let roots = roots
+        .into_iter()
+        .map(|root| (cwd.join(&root), root))
+        .collect::<Vec<_>>();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // notify calls this from its own thread
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = tx.send(event);
+    })?;
+    for (absolute, root) in &roots {
+        watcher.watch(absolute, RecursiveMode::Recursive)?;
+        eprintln!("{}", faded(&format!("[watch] Watching {}", root.display())));
+    }
+
+    let mut pending = HashMap::<PathBuf, PendingFile>::new();
+    let mut interval = time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        eprintln!("[watch] Watcher error: {err}");
+                        continue;
+                    }
+                };
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    continue;
+                }
+                for path in event.paths {
+                    if let Some(path) = to_candidate(&path, &roots, &config) {
+                        pending.insert(path, PendingFile { last_event: Instant::now(), size: None });
+                    }
+                }
+            }
+            _ = interval.tick() => {
+                let settled = take_settled(&mut pending).await;
+                if settled.is_empty() {
+                    continue;
+                }
+                let new_paths = {
+                    let videos = &state.read().await.videos;
+                    settled
+                        .into_iter()
+                        .filter(|path| !videos.iter().any(|video| video.current_loc() == path))
+                        .collect::<Vec<_>>()
+                };
+                register_videos(new_paths, &state).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_candidate() {
+        let roots = vec![(
+            PathBuf::from("/data/home/storage/dcim/Camera"),
+            PathBuf::from("storage/dcim/Camera/"),
+        )];
+        let config = Config::default();
+        let candidate = |path: &str| to_candidate(Path::new(path), &roots, &config);
+        assert_eq!(
+            candidate("/data/home/storage/dcim/Camera/VID_1.mp4"),
+            Some(PathBuf::from("storage/dcim/Camera/VID_1.mp4"))
+        );
+        assert_eq!(
+            candidate("/data/home/storage/dcim/Camera/2024/VID_1.mov"),
+            Some(PathBuf::from("storage/dcim/Camera/2024/VID_1.mov"))
+        );
+        assert_eq!(
+            candidate("/data/home/storage/dcim/Camera/.pending-1712345678-VID_1.mp4"),
+            None
+        );
+        assert_eq!(
+            candidate("/data/home/storage/dcim/Camera/.thumbnails/VID_1.mp4"),
+            None
+        );
+        assert_eq!(candidate("/data/home/storage/dcim/Camera/IMG_1.jpg"), None);
+        assert_eq!(candidate("/data/home/storage/movies/VID_1.mp4"), None);
+    }
+
+    #[tokio::test]
+    async fn test_take_settled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("VID_1.mp4");
+        std::fs::write(&path, b"partial").unwrap();
+        let long_ago = Instant::now() - SETTLE_TIME;
+        let mut pending = HashMap::from([(
+            path.clone(),
+            PendingFile {
+                last_event: long_ago,
+                size: None,
+            },
+        )]);
+
+        // first quiet check only records the size
+        assert!(take_settled(&mut pending).await.is_empty());
+        assert_eq!(pending[&path].size, Some(7));
+
+        // still growing
+        std::fs::write(&path, b"partial video").unwrap();
+        pending.get_mut(&path).unwrap().last_event = long_ago;
+        assert!(take_settled(&mut pending).await.is_empty());
+
+        // done
+        pending.get_mut(&path).unwrap().last_event = long_ago;
+        assert_eq!(take_settled(&mut pending).await, vec![path.clone()]);
+        assert!(pending.is_empty());
+    }
+}